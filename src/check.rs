@@ -0,0 +1,142 @@
+use std::path::Path;
+
+use chrono::{Datelike, Local};
+use serde_json::Value;
+use tracing::{info, instrument, warn};
+
+use crate::error::{PharmaError, Result};
+use crate::foph_diff::{
+    extract_date_from_bundles, package_gtin, parse_date_str, process_bundles, read_foph_bundles, GtinRejections,
+    SWISS_GTIN_PREFIX,
+};
+use crate::swissmedic_diff::{is_xlsx, load_swissmedic_csv, LoadOptions};
+
+// ─── Input check ─────────────────────────────────────────────────────────────
+
+/// Structural problems found in a FOPH NDJSON file.
+#[derive(Clone, Debug, Default)]
+pub struct FophCheck {
+    pub bundles: usize,
+    pub bundles_without_timestamp: usize,
+    pub packages: usize,
+    pub packages_without_gtin: usize,
+    /// GS1 identifiers that are not a valid Swiss GTIN, by reason.
+    pub rejected_gtins: GtinRejections,
+    /// Packages on the SL without a price effective at the bundle date.
+    pub packages_without_price: usize,
+    pub zero_prices: usize,
+    pub malformed_price_dates: usize,
+}
+
+impl FophCheck {
+    pub fn problems(&self) -> usize {
+        self.bundles_without_timestamp
+            + self.packages_without_gtin
+            + self.packages_without_price
+            + self.zero_prices
+            + self.malformed_price_dates
+    }
+}
+
+/// Count the bundles, packages and malformed entries of `bundles`.
+pub fn check_bundles(bundles: &[Value]) -> FophCheck {
+    let mut check = FophCheck { bundles: bundles.len(), ..Default::default() };
+
+    for bundle in bundles {
+        let timestamp = bundle.get("timestamp").and_then(|v| v.as_str())
+            .or_else(|| bundle.get("meta").and_then(|m| m.get("lastUpdated")).and_then(|v| v.as_str()));
+        if timestamp.and_then(parse_date_str).is_none() {
+            check.bundles_without_timestamp += 1;
+        }
+
+        let resources = bundle.get("entry").and_then(|v| v.as_array()).into_iter().flatten()
+            .filter_map(|entry| entry.get("resource"));
+        for res in resources {
+            match res.get("resourceType").and_then(|v| v.as_str()) {
+                Some("PackagedProductDefinition") => {
+                    check.packages += 1;
+                    if package_gtin(res, &[SWISS_GTIN_PREFIX.to_string()], &mut check.rejected_gtins).is_none() {
+                        check.packages_without_gtin += 1;
+                    }
+                }
+                Some("RegulatedAuthorization") => {
+                    let prices = res.get("extension").and_then(|v| v.as_array()).into_iter().flatten()
+                        .filter(|ext| ext.get("url").and_then(|v| v.as_str()).is_some_and(|u| u.contains("productPrice")));
+                    for price in prices {
+                        let sub = |url: &str| {
+                            price.get("extension").and_then(|v| v.as_array())
+                                .and_then(|subs| subs.iter().find(|s| s.get("url").and_then(|v| v.as_str()) == Some(url)))
+                        };
+                        let value = sub("value").and_then(|s| s["valueMoney"]["value"].as_f64()).unwrap_or(0.0);
+                        if value <= 0.0 {
+                            check.zero_prices += 1;
+                        }
+                        let change_date = sub("changeDate").and_then(|s| s["valueDate"].as_str());
+                        if change_date.and_then(parse_date_str).is_none() {
+                            check.malformed_price_dates += 1;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    check
+}
+
+#[instrument(skip_all, fields(file = filename))]
+fn check_foph_ndjson(filename: &str) -> Result<usize> {
+    let bundles = read_foph_bundles(filename)?.bundles;
+    let mut check = check_bundles(&bundles);
+
+    let today = Local::now();
+    let current_dt = extract_date_from_bundles(&bundles, (today.year(), today.month() as i32, today.day() as i32));
+    let packages = process_bundles(&bundles, &current_dt);
+    check.packages_without_price = packages.values().filter(|p| p.prices.is_empty()).count();
+
+    info!("{}: {} bundles, {} packages ({} with a GTIN and price)",
+        filename, check.bundles, check.packages, packages.len() - check.packages_without_price);
+    let report = |count: usize, what: &str| {
+        if count > 0 {
+            warn!("{}: {} {}", filename, count, what);
+        }
+    };
+    report(check.bundles_without_timestamp, "bundles without a timestamp");
+    report(check.packages_without_gtin, "packages without a GTIN");
+    report(check.rejected_gtins.wrong_length, "GTINs not 13 characters long");
+    report(check.rejected_gtins.not_numeric, "GTINs with non-digits");
+    report(check.rejected_gtins.wrong_prefix, "GTINs without the 7680 prefix");
+    report(check.rejected_gtins.bad_checksum, "GTINs with a bad check digit");
+    report(check.packages_without_price, "SL packages without a current price");
+    report(check.zero_prices, "prices of zero or without a value");
+    report(check.malformed_price_dates, "prices with a missing or malformed changeDate");
+    Ok(check.problems())
+}
+
+#[instrument(skip_all, fields(file = filename))]
+fn check_swissmedic_csv(filename: &str) -> Result<usize> {
+    let csv = load_swissmedic_csv(filename, &LoadOptions::default())?;
+
+    // Title rows above the header are expected, skipped data rows are not
+    let first_data_line = csv.header_line.map_or(1, |h| h + 1);
+    let (title_rows, data_rows): (Vec<_>, Vec<_>) = csv.skipped.iter().partition(|row| row.line < first_data_line);
+    if let Some(header) = csv.header_line {
+        info!("{}: header on line {} after {} title rows", filename, header, title_rows.len());
+    }
+    for row in &data_rows {
+        warn!("{}:{}: skipped: {}", filename, row.line, row.reason);
+    }
+    Ok(data_rows.len() + csv.invalid_gtins.len())
+}
+
+/// Parse `filename` as a Swissmedic export (`.csv`, `.xlsx`) or FOPH NDJSON
+/// without writing anything, and fail if it has structural problems.
+pub fn run_check(filename: &str) -> Result<()> {
+    let is_csv = Path::new(filename).extension().is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+    let problems = if is_csv || is_xlsx(filename) { check_swissmedic_csv(filename)? } else { check_foph_ndjson(filename)? };
+    if problems > 0 {
+        return Err(PharmaError::CheckFailed { file: filename.to_string(), problems });
+    }
+    info!("{}: OK", filename);
+    Ok(())
+}
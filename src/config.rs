@@ -0,0 +1,75 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::{PharmaError, Result};
+
+// ─── Config file ─────────────────────────────────────────────────────────────
+
+/// Template printed by `--print-default-config`; every key is optional.
+pub const DEFAULT_CONFIG: &str = r#"# pharma2merge configuration
+#
+# Read from ~/.config/pharma2merge/config.toml unless --config FILE is given.
+# Command-line flags override the values set here.
+
+# Directory below which csv/, ndjson/ and diff/ are written (default: current directory)
+# output_dir = "/var/lib/pharma2merge"
+
+# Retries per download on network errors or HTTP 5xx responses
+# retries = 3
+
+# Worker threads for loading and diffing (default: one per CPU)
+# threads = 4
+
+# HTTP request timeout in seconds
+# http_timeout = 300
+
+# PEM file with an additional root certificate to trust for downloads
+# ca_cert = "/etc/ssl/certs/corporate-proxy.pem"
+"#;
+
+/// Settings read from a TOML config file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub output_dir: Option<PathBuf>,
+    pub retries: Option<u32>,
+    pub threads: Option<usize>,
+    /// HTTP timeout in seconds.
+    pub http_timeout: Option<u64>,
+    /// Additional root certificate for downloads (PEM).
+    pub ca_cert: Option<PathBuf>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/pharma2merge/config.toml`, falling back to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(base.join("pharma2merge").join("config.toml"))
+    }
+
+    /// Load `path`, or the default config file if it exists. An explicitly
+    /// given file must exist; a missing default file yields an empty config.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let path = match path {
+            Some(p) => p.to_path_buf(),
+            None => match Config::default_path() {
+                Some(p) if p.is_file() => p,
+                _ => return Ok(Config::default()),
+            },
+        };
+        let content = fs::read_to_string(&path).map_err(|e| PharmaError::Config {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        })?;
+        toml::from_str(&content).map_err(|e| PharmaError::Config {
+            path: path.display().to_string(),
+            message: e.message().to_string(),
+        })
+    }
+}
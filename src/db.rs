@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use crate::foph_diff::{normalize_name, PackageInfo, PackageMap};
+
+// ─── Package database ────────────────────────────────────────────────────────
+
+/// A `PackageMap` with indexes by name, manufacturer and ATC prefix, built
+/// once so that lookups need no scan over all packages. The GTIN lists of
+/// the indexes are in GTIN order.
+#[derive(Clone, Debug, Default)]
+pub struct PackageDatabase {
+    packages: PackageMap,
+    by_name: HashMap<String, Vec<String>>,
+    by_manufacturer: HashMap<String, Vec<String>>,
+    by_atc_prefix: HashMap<String, Vec<String>>,
+}
+
+/// Key of the name index: `normalize_name`, lowercased.
+fn name_key(name: &str) -> String {
+    normalize_name(name).to_lowercase()
+}
+
+impl PackageDatabase {
+    pub fn new(packages: PackageMap) -> Self {
+        let mut db = PackageDatabase::default();
+        for (gtin, info) in &packages {
+            db.by_name.entry(name_key(&info.name)).or_default().push(gtin.clone());
+            if let Some(manufacturer) = info.manufacturer.as_deref().filter(|m| !m.is_empty()) {
+                db.by_manufacturer.entry(manufacturer.to_string()).or_default().push(gtin.clone());
+            }
+            if let Some(atc) = &info.atc_code {
+                let atc = atc.trim().to_ascii_uppercase();
+                // Every prefix, so each ATC level (N, N02, N02B, …) is one lookup
+                for end in (1..=atc.len()).filter(|end| atc.is_char_boundary(*end)) {
+                    db.by_atc_prefix.entry(atc[..end].to_string()).or_default().push(gtin.clone());
+                }
+            }
+        }
+        db.packages = packages;
+        db
+    }
+
+    pub fn len(&self) -> usize {
+        self.packages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+
+    /// The wrapped packages, keyed by GTIN.
+    pub fn packages(&self) -> &PackageMap {
+        &self.packages
+    }
+
+    pub fn into_packages(self) -> PackageMap {
+        self.packages
+    }
+
+    pub fn get_by_gtin(&self, gtin: &str) -> Option<&PackageInfo> {
+        self.packages.get(gtin)
+    }
+
+    /// Packages named `name`, ignoring case, HTML entities and runs of
+    /// whitespace (see `normalize_name`).
+    pub fn search_by_name(&self, name: &str) -> Vec<(&str, &PackageInfo)> {
+        self.lookup(&self.by_name, &name_key(name))
+    }
+
+    /// Packages whose `manufacturer` is exactly `manufacturer`.
+    pub fn packages_by_manufacturer(&self, manufacturer: &str) -> Vec<(&str, &PackageInfo)> {
+        self.lookup(&self.by_manufacturer, manufacturer)
+    }
+
+    /// Packages whose ATC code starts with `prefix` (any case), e.g. `N02B`.
+    pub fn packages_by_atc_prefix(&self, prefix: &str) -> Vec<(&str, &PackageInfo)> {
+        self.lookup(&self.by_atc_prefix, &prefix.trim().to_ascii_uppercase())
+    }
+
+    fn lookup(&self, index: &HashMap<String, Vec<String>>, key: &str) -> Vec<(&str, &PackageInfo)> {
+        index.get(key).into_iter().flatten()
+            .filter_map(|gtin| self.packages.get_key_value(gtin))
+            .map(|(gtin, info)| (gtin.as_str(), info))
+            .collect()
+    }
+}
+
+impl From<PackageMap> for PackageDatabase {
+    fn from(packages: PackageMap) -> Self {
+        PackageDatabase::new(packages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn package(name: &str, manufacturer: &str, atc: &str) -> PackageInfo {
+        serde_json::from_value(json!({
+            "name": name, "prices": {}, "has_sl_entry": true, "manufacturer": manufacturer, "atc_code": atc,
+        })).unwrap()
+    }
+
+    fn database() -> PackageDatabase {
+        PackageDatabase::new(PackageMap::from([
+            ("7680543210017".to_string(), package("DAFALGAN  500 mg", "UPSA", "N02BE01")),
+            ("7680123450017".to_string(), package("Aspirin 500", "Bayer AG", "N02BA01")),
+            ("7680654320018".to_string(), package("PONSTAN", "Pfizer AG", "M01AG01")),
+            ("7680222220016".to_string(), package("ASPIRIN &amp; C", "Bayer AG", "")),
+        ]))
+    }
+
+    fn gtins(found: Vec<(&str, &PackageInfo)>) -> Vec<String> {
+        found.into_iter().map(|(gtin, _)| gtin.to_string()).collect()
+    }
+
+    #[test]
+    fn get_by_gtin_finds_only_known_gtins() {
+        let db = database();
+        assert_eq!(db.len(), 4);
+        assert_eq!(db.get_by_gtin("7680654320018").map(|p| p.name.as_str()), Some("PONSTAN"));
+        assert!(db.get_by_gtin("7680000000000").is_none());
+    }
+
+    #[test]
+    fn search_by_name_ignores_case_whitespace_and_entities() {
+        let db = database();
+        assert_eq!(gtins(db.search_by_name("aspirin 500")), ["7680123450017"]);
+        assert_eq!(gtins(db.search_by_name(" Dafalgan 500 MG ")), ["7680543210017"]);
+        assert_eq!(gtins(db.search_by_name("Aspirin & C")), ["7680222220016"]);
+        assert!(db.search_by_name("Aspirin").is_empty());
+    }
+
+    #[test]
+    fn packages_by_manufacturer_match_exactly_in_gtin_order() {
+        let db = database();
+        assert_eq!(gtins(db.packages_by_manufacturer("Bayer AG")), ["7680123450017", "7680222220016"]);
+        assert!(db.packages_by_manufacturer("bayer ag").is_empty());
+    }
+
+    #[test]
+    fn packages_by_atc_prefix_match_every_level() {
+        let db = database();
+        assert_eq!(gtins(db.packages_by_atc_prefix("n02")), ["7680123450017", "7680543210017"]);
+        assert_eq!(gtins(db.packages_by_atc_prefix("N02BE01")), ["7680543210017"]);
+        assert_eq!(gtins(db.packages_by_atc_prefix("M")), ["7680654320018"]);
+        assert!(db.packages_by_atc_prefix("A").is_empty());
+        assert!(db.packages_by_atc_prefix("").is_empty());
+    }
+}
@@ -0,0 +1,856 @@
+use std::env;
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use calamine::{open_workbook_from_rs, Reader, Xlsx};
+use chrono::{Datelike, Local};
+use reqwest::blocking::Client;
+use reqwest::header::{ACCEPT_RANGES, CONTENT_LENGTH, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::{Certificate, NoProxy, Proxy, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{error, info, instrument, warn};
+
+use crate::{create_output_dir, csv_escape, write_atomic, write_output, TimingStats, OUTPUT_LOG_TARGET};
+use crate::error::{PharmaError, Result};
+use crate::progress;
+use crate::swissmedic_diff::ColumnLayout;
+
+// ─── Constants ───────────────────────────────────────────────────────────────
+
+const SWISSMEDIC_URL: &str = "https://www.swissmedic.ch/dam/swissmedic/de/dokumente/internetlisten/zugelassene_packungen_human.xlsx.download.xlsx/zugelassene_packungen_ham.xlsx";
+const FOPH_RESOURCES_URL: &str = "https://epl.bag.admin.ch/api/sl/public/resources/current";
+const FOPH_STATIC_BASE: &str = "https://epl.bag.admin.ch/static/";
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+
+// ─── Options ─────────────────────────────────────────────────────────────────
+
+/// Settings for `run_download`.
+#[derive(Clone, Debug)]
+pub struct DownloadOptions {
+    /// Download the Swissmedic xlsx (→ CSV).
+    pub swissmedic: bool,
+    /// Download the FOPH SL ndjson.
+    pub fhir: bool,
+    /// Retries per file on network errors or 5xx responses.
+    pub retries: u32,
+    /// Send conditional requests based on the `.cache` sidecars and skip
+    /// unchanged files.
+    pub use_cache: bool,
+    /// HTTP timeout per request.
+    pub timeout: Duration,
+    /// PEM file with an additional root certificate to trust, e.g. of a
+    /// TLS-intercepting corporate proxy.
+    pub ca_cert: Option<PathBuf>,
+    /// Only report the resolved URLs and their size (via `HEAD`).
+    pub dry_run: bool,
+    /// Which sheets of the Swissmedic xlsx to convert.
+    pub sheets: SheetSelection,
+}
+
+/// Files written (or kept unchanged after a `304`) by `run_download`.
+#[derive(Clone, Debug, Default)]
+pub struct DownloadedFiles {
+    /// The Swissmedic CSV; the first sheet's with `SheetSelection::All`.
+    pub swissmedic: Option<PathBuf>,
+    pub foph: Option<PathBuf>,
+}
+
+/// Sheets of the Swissmedic xlsx converted to CSV.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SheetSelection {
+    /// The first sheet, as `swissmedic_<date>.csv`.
+    #[default]
+    First,
+    /// The sheet with this name, as `swissmedic_<date>.csv`.
+    Named(String),
+    /// Every sheet, as `swissmedic_<date>_<sheet>.csv`.
+    All,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            swissmedic: true,
+            fhir: true,
+            retries: 3,
+            use_cache: true,
+            timeout: Duration::from_secs(300),
+            ca_cert: None,
+            dry_run: false,
+            sheets: SheetSelection::First,
+        }
+    }
+}
+
+// ─── Download helpers ────────────────────────────────────────────────────────
+
+pub fn resolve_foph_ndjson_url(client: &Client) -> Result<String> {
+    info!("Fetching latest FOPH resource index from: {}", FOPH_RESOURCES_URL);
+    let response = client.get(FOPH_RESOURCES_URL).send()?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(PharmaError::HttpStatus { status, url: FOPH_RESOURCES_URL.to_string() });
+    }
+    let body = response.text()?;
+    let json: Value = serde_json::from_str(&body)?;
+    let file_url = json.get("fhir")
+        .and_then(|f: &Value| f.get("fileUrl"))
+        .and_then(|v: &Value| v.as_str())
+        .ok_or_else(|| PharmaError::InvalidResponse {
+            url: FOPH_RESOURCES_URL.to_string(),
+            message: "could not find fhir.fileUrl".to_string(),
+        })?;
+    let full_url = format!("{}{}", FOPH_STATIC_BASE, file_url);
+    info!("  Latest FOPH NDJSON: {}", full_url);
+    Ok(full_url)
+}
+
+pub fn download_url(client: &Client, url: &str) -> Result<Vec<u8>> {
+    match fetch_url(client, url, None)? {
+        Fetch::Body { bytes, .. } => Ok(bytes),
+        Fetch::NotModified => Err(PharmaError::InvalidResponse {
+            url: url.to_string(),
+            message: "304 Not Modified for an unconditional request".to_string(),
+        }),
+    }
+}
+
+// ─── Conditional GET cache ───────────────────────────────────────────────────
+
+/// Validators of a previous download, stored as a small JSON sidecar
+/// (`.swissmedic.cache` / `.sl_foph.cache`) next to the downloaded file.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CacheMeta {
+    pub url: String,
+    pub file: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// The sheets `file` was converted from, for the Swissmedic xlsx.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sheets: Option<SheetSelection>,
+}
+
+/// Result of a (possibly conditional) GET.
+pub enum Fetch {
+    NotModified,
+    Body { bytes: Vec<u8>, etag: Option<String>, last_modified: Option<String> },
+}
+
+/// GET `url`, sending `If-None-Match` / `If-Modified-Since` from `cached` if given.
+pub fn fetch_url(client: &Client, url: &str, cached: Option<&CacheMeta>) -> Result<Fetch> {
+    fetch_resumable(client, url, None, cached, &mut Partial::default())
+}
+
+/// Body received by an interrupted attempt, kept so the next attempt can
+/// continue with a `Range` request if the server sent `Accept-Ranges: bytes`.
+#[derive(Default)]
+struct Partial {
+    bytes: Vec<u8>,
+    resumable: bool,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// Like `fetch_url`, but continues from `partial` when possible and leaves
+/// whatever was received in `partial` if the body read fails. The progress
+/// bar is labelled with `source` ("swissmedic", "foph"), else the URL's host.
+#[instrument(skip_all, fields(url = url))]
+fn fetch_resumable(
+    client: &Client,
+    url: &str,
+    source: Option<&str>,
+    cached: Option<&CacheMeta>,
+    partial: &mut Partial,
+) -> Result<Fetch> {
+    let resuming = partial.resumable && !partial.bytes.is_empty();
+    let mut request = client.get(url);
+    if resuming {
+        info!("Resuming: {} at byte {}", url, partial.bytes.len());
+        request = request.header(RANGE, format!("bytes={}-", partial.bytes.len()));
+        // Only accept a partial response for the same version of the file
+        if let Some(validator) = partial.etag.as_ref().or(partial.last_modified.as_ref()) {
+            request = request.header(IF_RANGE, validator);
+        }
+    } else {
+        info!("Downloading: {}", url);
+        if let Some(meta) = cached {
+            if let Some(etag) = &meta.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(lm) = &meta.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, lm);
+            }
+        }
+    }
+    let mut response = request.send()?;
+    let status = response.status();
+    if resuming && status == StatusCode::RANGE_NOT_SATISFIABLE {
+        *partial = Partial::default();
+        return fetch_resumable(client, url, source, cached, partial);
+    }
+    if status == StatusCode::NOT_MODIFIED {
+        return Ok(Fetch::NotModified);
+    }
+    if !status.is_success() {
+        return Err(PharmaError::HttpStatus { status, url: url.to_string() });
+    }
+    if !(resuming && status == StatusCode::PARTIAL_CONTENT) {
+        // Fresh (or non-ranged) response: start over
+        let header = |name| response.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+        *partial = Partial {
+            bytes: Vec::new(),
+            resumable: header(ACCEPT_RANGES).is_some_and(|v| v.eq_ignore_ascii_case("bytes")),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+    }
+    let received = partial.bytes.len() as u64;
+    let label = source.map_or_else(|| response.url().host_str().unwrap_or_default().to_string(), str::to_string);
+    let bar = progress::bytes_bar(response.content_length().map(|len| received + len), &label);
+    bar.set_position(received);
+    let read = bar.wrap_read(&mut response).read_to_end(&mut partial.bytes);
+    bar.finish_and_clear();
+    if let Err(source) = read {
+        return Err(PharmaError::Interrupted { url: url.to_string(), received: partial.bytes.len(), source });
+    }
+    let Partial { bytes, etag, last_modified, .. } = std::mem::take(partial);
+    info!("  Downloaded {} bytes", bytes.len());
+    Ok(Fetch::Body { bytes, etag, last_modified })
+}
+
+/// Load the sidecar for `url`, ignoring it if it belongs to another URL or
+/// sheet selection, or the cached file is gone.
+fn read_cache(cache_path: &Path, url: &str, sheets: Option<&SheetSelection>) -> Option<CacheMeta> {
+    let content = fs::read_to_string(cache_path).ok()?;
+    let meta: CacheMeta = serde_json::from_str(&content).ok()?;
+    if meta.url == url && meta.sheets.as_ref() == sheets && Path::new(&meta.file).exists()
+        && (meta.etag.is_some() || meta.last_modified.is_some())
+    {
+        Some(meta)
+    } else {
+        None
+    }
+}
+
+fn write_cache(cache_path: &Path, meta: &CacheMeta) -> Result<()> {
+    let pretty = serde_json::to_string_pretty(meta)?;
+    write_output(cache_path, pretty.as_bytes(), false)
+}
+
+/// Conditionally download `url` of `source`. Returns the fresh body, or `None`
+/// together with the cached file path when the server answered 304. `sheets`
+/// is the selection the cached file must have been converted with, if any.
+fn fetch_cached(
+    client: &Client,
+    source: &str,
+    url: &str,
+    sheets: Option<&SheetSelection>,
+    cache_path: &Path,
+    opts: &DownloadOptions,
+) -> Result<(Option<Vec<u8>>, CacheMeta)> {
+    let cached = if opts.use_cache { read_cache(cache_path, url, sheets) } else { None };
+    match fetch_with_retry(client, url, Some(source), cached.as_ref(), opts.retries, RETRY_BASE_DELAY)? {
+        Fetch::NotModified => {
+            let meta = cached.unwrap_or_default();
+            info!("Up to date, using cached file.");
+            Ok((None, meta))
+        }
+        Fetch::Body { bytes, etag, last_modified } => {
+            let meta = CacheMeta { url: url.to_string(), file: String::new(), etag, last_modified, sheets: sheets.cloned() };
+            Ok((Some(bytes), meta))
+        }
+    }
+}
+
+/// Report what downloading `url` to `dest` would fetch, using the
+/// `Content-Length` of a `HEAD` request as the size estimate.
+fn report_planned_download(client: &Client, url: &str, dest: &Path) {
+    // The size is only informative; a failing HEAD request doesn't fail the dry run
+    let size = match client.head(url).send() {
+        Ok(response) if response.status().is_success() => response.headers().get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .map(|len| format!("{} bytes", len)),
+        Ok(response) => {
+            warn!("[dry-run] HEAD {} returned {}", url, response.status());
+            None
+        }
+        Err(e) => {
+            warn!("[dry-run] HEAD {} failed: {}", url, e);
+            None
+        }
+    };
+    let size = size.unwrap_or_else(|| "unknown size".to_string());
+    info!("[dry-run] Would download {} ({}) to {}", url, size, dest.display());
+}
+
+/// Network errors and 5xx responses are worth retrying; anything else is not.
+fn is_transient(err: &PharmaError) -> bool {
+    match err {
+        PharmaError::Http(_) | PharmaError::Interrupted { .. } => true,
+        PharmaError::HttpStatus { status, .. } => status.is_server_error(),
+        _ => false,
+    }
+}
+
+/// Up to a quarter of `base` as jitter, so parallel clients don't retry in lockstep.
+fn jitter(base: Duration) -> Duration {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    let max_ms = (base.as_millis() as u64 / 4).max(1);
+    Duration::from_millis(nanos as u64 % max_ms)
+}
+
+/// Like `download_url`, but retries transient failures up to `max_retries`
+/// times, doubling the wait (plus jitter) after each attempt. An interrupted
+/// body is resumed with a `Range` request where the server supports it.
+pub fn download_url_with_retry(client: &Client, url: &str, max_retries: u32, base_delay: Duration) -> Result<Vec<u8>> {
+    match fetch_with_retry(client, url, None, None, max_retries, base_delay)? {
+        Fetch::Body { bytes, .. } => Ok(bytes),
+        Fetch::NotModified => Err(PharmaError::InvalidResponse {
+            url: url.to_string(),
+            message: "304 Not Modified for an unconditional request".to_string(),
+        }),
+    }
+}
+
+/// `fetch_resumable` with retries; the partial body carries over between attempts.
+fn fetch_with_retry(
+    client: &Client,
+    url: &str,
+    source: Option<&str>,
+    cached: Option<&CacheMeta>,
+    max_retries: u32,
+    base_delay: Duration,
+) -> Result<Fetch> {
+    let mut partial = Partial::default();
+    with_retry(max_retries, base_delay, || fetch_resumable(client, url, source, cached, &mut partial))
+}
+
+/// Run `op`, retrying transient failures with exponential backoff. Once the
+/// retries are used up the last error is wrapped with the attempt count.
+fn with_retry<T>(max_retries: u32, base_delay: Duration, mut op: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < max_retries && is_transient(&e) => {
+                let wait = base_delay * 2u32.pow(attempt) + jitter(base_delay);
+                attempt += 1;
+                warn!("Attempt {}/{} failed: {}", attempt, max_retries + 1, e);
+                info!("  Retrying in {:.1}s...", wait.as_secs_f64());
+                std::thread::sleep(wait);
+            }
+            Err(e) if attempt > 0 && is_transient(&e) => {
+                return Err(PharmaError::RetriesExhausted { attempts: attempt + 1, source: Box::new(e) });
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Convert an Excel serial date number to YYYY/MM/DD string.
+fn excel_serial_to_date_str(serial: f64) -> Option<String> {
+    let days = serial as i64;
+    let base = chrono::NaiveDate::from_ymd_opt(1899, 12, 30)?;
+    let date = base.checked_add_signed(chrono::Duration::days(days))?;
+    Some(format!("{}/{:02}/{:02}", date.year(), date.month(), date.day()))
+}
+
+/// Convert the first sheet of a Swissmedic xlsx to CSV and write it to `csv_path`.
+pub fn xlsx_to_csv(xlsx_bytes: &[u8], csv_path: &Path) -> Result<()> {
+    write_atomic(csv_path, &xlsx_to_csv_bytes(xlsx_bytes)?)?;
+    info!("  Converted to CSV: {}", csv_path.display());
+    Ok(())
+}
+
+/// Convert the first sheet of a Swissmedic xlsx to CSV in memory, with the
+/// serial dates of the date columns written as YYYY/MM/DD.
+pub fn xlsx_to_csv_bytes(xlsx_bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut sheets = xlsx_sheets_to_csv_bytes(xlsx_bytes, &SheetSelection::First)?;
+    Ok(sheets.remove(0).1)
+}
+
+/// Convert the selected sheets of a Swissmedic xlsx to CSV in memory, returning
+/// `(sheet name, CSV)` in workbook order. Date columns are detected per sheet.
+pub fn xlsx_sheets_to_csv_bytes(xlsx_bytes: &[u8], selection: &SheetSelection) -> Result<Vec<(String, Vec<u8>)>> {
+    let cursor = Cursor::new(xlsx_bytes);
+    let mut workbook: Xlsx<_> = open_workbook_from_rs(cursor)?;
+    let names = workbook.sheet_names();
+    let selected: Vec<String> = match selection {
+        SheetSelection::First => {
+            if names.len() > 1 {
+                info!("Workbook has sheets {}; converting the first (choose another with --sheet NAME)", names.join(", "));
+            }
+            names.first().cloned().into_iter().collect()
+        }
+        SheetSelection::Named(name) => {
+            if !names.contains(name) {
+                return Err(PharmaError::SheetNotFound { name: name.clone(), available: names.join(", ") });
+            }
+            vec![name.clone()]
+        }
+        SheetSelection::All => names,
+    };
+    if selected.is_empty() {
+        return Err(PharmaError::NoSheets);
+    }
+    selected.into_iter()
+        .map(|name| {
+            let range = workbook.worksheet_range(&name)?;
+            Ok((name, range_to_csv(&range)?))
+        })
+        .collect()
+}
+
+fn range_to_csv(range: &calamine::Range<calamine::Data>) -> Result<Vec<u8>> {
+    let mut writer = Vec::new();
+
+    // Date columns follow the header row once it is found, and only those whose header
+    // names a date; title rows before it, or a sheet without a header, use the defaults
+    let mut date_cols = ColumnLayout::default().date_columns().to_vec();
+    let mut header_found = false;
+
+    for row in range.rows() {
+        if !header_found {
+            let cells: Vec<String> = row.iter().map(|c| c.to_string()).collect();
+            if ColumnLayout::detect(&cells).is_some() {
+                date_cols = ColumnLayout::header_date_columns(&cells);
+                header_found = true;
+            }
+        }
+        let fields: Vec<String> = row.iter().enumerate().map(|(col_idx, cell)| {
+            let s = match cell {
+                calamine::Data::Empty => String::new(),
+                calamine::Data::String(s) => s.clone(),
+                calamine::Data::Float(f) => {
+                    if *f == (*f as i64) as f64 {
+                        let i = *f as i64;
+                        if i > 365 && i < 73050 && date_cols.contains(&col_idx) {
+                            excel_serial_to_date_str(*f).unwrap_or_else(|| format!("{}", i))
+                        } else {
+                            format!("{}", i)
+                        }
+                    } else {
+                        format!("{}", f)
+                    }
+                }
+                calamine::Data::Int(i) => {
+                    if *i > 365 && *i < 73050 && date_cols.contains(&col_idx) {
+                        excel_serial_to_date_str(*i as f64).unwrap_or_else(|| format!("{}", i))
+                    } else {
+                        format!("{}", i)
+                    }
+                }
+                calamine::Data::Bool(b) => format!("{}", b),
+                calamine::Data::DateTime(dt) => {
+                    let serial_str = format!("{}", dt);
+                    if let Ok(serial) = serial_str.parse::<f64>() {
+                        excel_serial_to_date_str(serial).unwrap_or(serial_str)
+                    } else {
+                        serial_str
+                    }
+                }
+                calamine::Data::DateTimeIso(s) => s.clone(),
+                calamine::Data::DurationIso(s) => s.clone(),
+                calamine::Data::Error(e) => format!("{:?}", e),
+            };
+            csv_escape(&s)
+        }).collect();
+        writeln!(writer, "{}", fields.join(","))?;
+    }
+    Ok(writer)
+}
+
+// ─── HTTP client ─────────────────────────────────────────────────────────────
+
+/// Proxy environment variables in order of precedence, per scheme.
+const PROXY_VARS: [(&str, [&str; 4]); 2] = [
+    ("https", ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]),
+    ("http", ["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"]),
+];
+
+/// The first non-empty variable of `names` and its value.
+fn proxy_from_env(names: &[&'static str]) -> Option<(&'static str, String)> {
+    names.iter().find_map(|name| env::var(name).ok().filter(|v| !v.trim().is_empty()).map(|v| (*name, v)))
+}
+
+/// HTTP client with the download timeout, the proxies from `HTTPS_PROXY` /
+/// `HTTP_PROXY` (hosts in `NO_PROXY` are reached directly) and the
+/// certificate of `ca_cert` trusted in addition to the system roots.
+pub fn build_client(opts: &DownloadOptions) -> Result<Client> {
+    let mut builder = Client::builder().timeout(opts.timeout);
+    for (scheme, names) in PROXY_VARS {
+        let Some((name, url)) = proxy_from_env(&names) else { continue };
+        let proxy = match scheme {
+            "https" => Proxy::https(&url)?,
+            _ => Proxy::http(&url)?,
+        };
+        // The URL may carry credentials, so only the variable is logged
+        info!("Using {} proxy from {}", scheme, name);
+        builder = builder.proxy(proxy.no_proxy(NoProxy::from_env()));
+    }
+    if let Some(path) = &opts.ca_cert {
+        let invalid = |message: String| PharmaError::InvalidCaCert { path: path.display().to_string(), message };
+        let pem = fs::read(path).map_err(|e| invalid(e.to_string()))?;
+        // reqwest's own message is just "builder error"; the cause is in its source
+        let cert = Certificate::from_pem(&pem)
+            .map_err(|e| invalid(std::error::Error::source(&e).map_or_else(|| e.to_string(), |cause| cause.to_string())))?;
+        info!("Trusting CA certificate from {}", path.display());
+        builder = builder.add_root_certificate(cert);
+    }
+    Ok(builder.build()?)
+}
+
+// ─── Run mode ────────────────────────────────────────────────────────────────
+
+/// Download the Swissmedic xlsx and convert it to `csv/swissmedic_<date>.csv`
+/// (or one CSV per sheet). Returns the CSV, `None` on a dry run.
+#[instrument(name = "swissmedic", skip_all)]
+fn download_swissmedic(client: &Client, date_str: &str, opts: &DownloadOptions, out_dir: &Path, stats: &mut TimingStats) -> Result<Option<PathBuf>> {
+    let csv_dir = out_dir.join("csv");
+    create_output_dir(&csv_dir, opts.dry_run)?;
+    let mut swissmedic_csv = csv_dir.join(format!("swissmedic_{}.csv", date_str));
+    let sheet_csv = |sheet: &str| {
+        let suffix: String = sheet.chars().map(|c| if c.is_alphanumeric() { c } else { '-' }).collect();
+        csv_dir.join(format!("swissmedic_{}_{}.csv", date_str, suffix))
+    };
+    let cache_path = csv_dir.join(".swissmedic.cache");
+    if opts.dry_run {
+        report_planned_download(client, SWISSMEDIC_URL, &swissmedic_csv);
+        return Ok(None);
+    }
+    let download_start = Instant::now();
+    let fetched = fetch_cached(client, "swissmedic", SWISSMEDIC_URL, Some(&opts.sheets), &cache_path, opts)?;
+    stats.download_ms += TimingStats::since(download_start);
+    match fetched {
+        (Some(xlsx_bytes), mut meta) => {
+            let parse_start = Instant::now();
+            let sheets = xlsx_sheets_to_csv_bytes(&xlsx_bytes, &opts.sheets)?;
+            let all_sheets = opts.sheets == SheetSelection::All;
+            for (i, (sheet, csv)) in sheets.iter().enumerate() {
+                let path = if all_sheets { sheet_csv(sheet) } else { swissmedic_csv.clone() };
+                write_atomic(&path, csv)?;
+                info!("  Converted sheet {} to CSV: {}", sheet, path.display());
+                // The cache sidecar remembers the first file
+                if i == 0 {
+                    swissmedic_csv = path;
+                }
+            }
+            stats.parse_ms += TimingStats::since(parse_start);
+            meta.file = swissmedic_csv.display().to_string();
+            write_cache(&cache_path, &meta)?;
+        }
+        (None, meta) => swissmedic_csv = PathBuf::from(meta.file),
+    }
+    info!(target: OUTPUT_LOG_TARGET, "Download completed: {}", swissmedic_csv.display());
+    Ok(Some(swissmedic_csv))
+}
+
+/// Resolve the current FOPH SL export and download it to
+/// `ndjson/sl_foph_<date>.ndjson[.gz]`. Returns the file, `None` on a dry run.
+#[instrument(name = "foph", skip_all)]
+fn download_foph(client: &Client, date_str: &str, opts: &DownloadOptions, out_dir: &Path, stats: &mut TimingStats) -> Result<Option<PathBuf>> {
+    let ndjson_dir = out_dir.join("ndjson");
+    create_output_dir(&ndjson_dir, opts.dry_run)?;
+    let cache_path = ndjson_dir.join(".sl_foph.cache");
+    let foph_url = resolve_foph_ndjson_url(client)?;
+    // Compressed exports are stored as-is; read_foph_bundles decompresses them
+    let ext = if foph_url.ends_with(".gz") { "ndjson.gz" } else { "ndjson" };
+    let mut foph_ndjson = ndjson_dir.join(format!("sl_foph_{}.{}", date_str, ext));
+    if opts.dry_run {
+        report_planned_download(client, &foph_url, &foph_ndjson);
+        return Ok(None);
+    }
+    let download_start = Instant::now();
+    let fetched = fetch_cached(client, "foph", &foph_url, None, &cache_path, opts)?;
+    stats.download_ms += TimingStats::since(download_start);
+    match fetched {
+        (Some(ndjson_bytes), mut meta) => {
+            let write_start = Instant::now();
+            write_output(&foph_ndjson, &ndjson_bytes, false)?;
+            stats.write_ms += TimingStats::since(write_start);
+            meta.file = foph_ndjson.display().to_string();
+            write_cache(&cache_path, &meta)?;
+        }
+        (None, meta) => foph_ndjson = PathBuf::from(meta.file),
+    }
+    info!(target: OUTPUT_LOG_TARGET, "Download completed: {}", foph_ndjson.display());
+    Ok(Some(foph_ndjson))
+}
+
+/// Download the selected sources side by side, so the run takes as long as
+/// the slower one. If one fails the other still completes; the failure is
+/// returned as `DownloadFailed` naming its source (and a second one logged).
+#[instrument(skip_all)]
+pub fn run_download(opts: &DownloadOptions, out_dir: &Path) -> Result<DownloadedFiles> {
+    let today = Local::now().date_naive();
+    let date_str = format!("{:02}.{:02}.{}", today.day(), today.month(), today.year());
+    let client = build_client(opts)?;
+
+    let (mut swissmedic_stats, mut foph_stats) = (TimingStats::default(), TimingStats::default());
+    let (swissmedic, foph) = rayon::join(
+        || opts.swissmedic.then(|| download_swissmedic(&client, &date_str, opts, out_dir, &mut swissmedic_stats)),
+        || opts.fhir.then(|| download_foph(&client, &date_str, opts, out_dir, &mut foph_stats)),
+    );
+
+    let mut downloaded = DownloadedFiles::default();
+    let mut errors = Vec::new();
+    for (source_name, result, file) in [
+        ("Swissmedic", swissmedic, &mut downloaded.swissmedic),
+        ("FOPH", foph, &mut downloaded.foph),
+    ] {
+        match result {
+            Some(Ok(path)) => *file = path,
+            Some(Err(e)) => errors.push(PharmaError::DownloadFailed { source_name, source: Box::new(e) }),
+            None => {}
+        }
+    }
+    if !errors.is_empty() {
+        for e in &errors[1..] {
+            error!("{}", e);
+        }
+        return Err(errors.swap_remove(0));
+    }
+
+    if !opts.dry_run {
+        // The downloads overlap, so only the longer one counts
+        let stats = TimingStats {
+            download_ms: swissmedic_stats.download_ms.max(foph_stats.download_ms),
+            parse_ms: swissmedic_stats.parse_ms + foph_stats.parse_ms,
+            write_ms: swissmedic_stats.write_ms + foph_stats.write_ms,
+            ..Default::default()
+        };
+        stats.log_summary();
+    }
+    Ok(downloaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve `responses` to one connection each, in order, on a local port.
+    /// Returns the base URL and a handle yielding the request lines seen.
+    fn mock_server(responses: Vec<Vec<u8>>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    request.push_str(&line);
+                }
+                requests.push(request);
+                stream.write_all(&response).unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    /// A client that reaches the mock server directly, whatever proxy is set.
+    fn local_client() -> Client {
+        Client::builder().no_proxy().build().unwrap()
+    }
+
+    fn response(status: &str, headers: &str, body: &[u8]) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {}\r\nConnection: close\r\n{}\r\n", status, headers).into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    #[test]
+    fn retry_succeeds_after_two_server_errors() {
+        let unavailable = response("503 Service Unavailable", "Content-Length: 0\r\n", b"");
+        let ok = response("200 OK", "Content-Length: 5\r\n", b"hello");
+        let (url, server) = mock_server(vec![unavailable.clone(), unavailable, ok]);
+        let bytes = download_url_with_retry(&local_client(), &url, 3, Duration::from_millis(1)).unwrap();
+        assert_eq!(bytes, b"hello");
+        assert_eq!(server.join().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn exhausted_retries_report_the_attempt_count() {
+        let unavailable = response("503 Service Unavailable", "Content-Length: 0\r\n", b"");
+        let (url, server) = mock_server(vec![unavailable.clone(), unavailable]);
+        let err = download_url_with_retry(&local_client(), &url, 1, Duration::from_millis(1)).unwrap_err();
+        assert!(matches!(err, PharmaError::RetriesExhausted { attempts: 2, .. }), "{}", err);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn interrupted_download_resumes_with_a_range_request() {
+        // Announces 10 bytes but closes after 5
+        let cut = response("200 OK", "Content-Length: 10\r\nAccept-Ranges: bytes\r\nETag: \"v1\"\r\n", b"01234");
+        let rest = response("206 Partial Content", "Content-Length: 5\r\nContent-Range: bytes 5-9/10\r\n", b"56789");
+        let (url, server) = mock_server(vec![cut, rest]);
+        let bytes = download_url_with_retry(&local_client(), &url, 3, Duration::from_millis(1)).unwrap();
+        assert_eq!(bytes, b"0123456789");
+        let requests = server.join().unwrap();
+        assert!(requests[1].to_ascii_lowercase().contains("range: bytes=5-"), "{}", requests[1]);
+    }
+
+    /// A minimal xlsx with one worksheet per `(name, rows)`, stored uncompressed.
+    /// Cells that parse as numbers are written as numbers, the rest as inline strings.
+    fn xlsx(sheets: &[(&str, &[&[&str]])]) -> Vec<u8> {
+        let column = |i: usize| char::from(b'A' + i as u8);
+        let mut files = vec![
+            ("[Content_Types].xml".to_string(), concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?><Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">"#,
+                r#"<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>"#,
+                r#"<Default Extension="xml" ContentType="application/xml"/></Types>"#).to_string()),
+            ("_rels/.rels".to_string(), concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+                r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" "#,
+                r#"Target="xl/workbook.xml"/></Relationships>"#).to_string()),
+        ];
+        let mut workbook = String::from(concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" "#,
+            r#"xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets>"#));
+        let mut rels = String::from(
+            r#"<?xml version="1.0" encoding="UTF-8"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#);
+        for (i, (name, rows)) in sheets.iter().enumerate() {
+            let id = i + 1;
+            workbook.push_str(&format!(r#"<sheet name="{}" sheetId="{}" r:id="rId{}"/>"#, name, id, id));
+            rels.push_str(&format!(concat!(
+                r#"<Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" "#,
+                r#"Target="worksheets/sheet{}.xml"/>"#), id, id));
+            let mut sheet = String::from(concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?><worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#,
+                "<sheetData>"));
+            for (r, row) in rows.iter().enumerate() {
+                sheet.push_str(&format!(r#"<row r="{}">"#, r + 1));
+                for (c, cell) in row.iter().enumerate() {
+                    let at = format!("{}{}", column(c), r + 1);
+                    if cell.parse::<f64>().is_ok() {
+                        sheet.push_str(&format!(r#"<c r="{}"><v>{}</v></c>"#, at, cell));
+                    } else {
+                        sheet.push_str(&format!(r#"<c r="{}" t="inlineStr"><is><t>{}</t></is></c>"#, at, cell));
+                    }
+                }
+                sheet.push_str("</row>");
+            }
+            sheet.push_str("</sheetData></worksheet>");
+            files.push((format!("xl/worksheets/sheet{}.xml", id), sheet));
+        }
+        workbook.push_str("</sheets></workbook>");
+        rels.push_str("</Relationships>");
+        files.push(("xl/workbook.xml".to_string(), workbook));
+        files.push(("xl/_rels/workbook.xml.rels".to_string(), rels));
+
+        // Local headers with the data, then the central directory and its end record
+        let (mut zip, mut central) = (Vec::new(), Vec::new());
+        for (name, data) in &files {
+            let mut crc = flate2::Crc::new();
+            crc.update(data.as_bytes());
+            let mut fields = Vec::new();
+            fields.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            fields.extend_from_slice(&crc.sum().to_le_bytes());
+            fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            fields.extend_from_slice(&[0, 0]);
+            central.extend_from_slice(&[0x50, 0x4b, 0x01, 0x02, 20, 0]);
+            central.extend_from_slice(&fields);
+            central.extend_from_slice(&[0; 10]);
+            central.extend_from_slice(&(zip.len() as u32).to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(&[0x50, 0x4b, 0x03, 0x04]);
+            zip.extend_from_slice(&fields);
+            zip.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(data.as_bytes());
+        }
+        let offset = zip.len() as u32;
+        zip.extend_from_slice(&central);
+        zip.extend_from_slice(&[0x50, 0x4b, 0x05, 0x06, 0, 0, 0, 0]);
+        zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&offset.to_le_bytes());
+        zip.extend_from_slice(&[0, 0]);
+        zip
+    }
+
+    #[test]
+    fn all_sheets_of_a_workbook_are_converted() {
+        let header: &[&str] = &["Zulassungs-Nummer", "Packungscode", "Bezeichnung", "Gültigkeitsdauer"];
+        let human: &[&[&str]] = &[header, &["12345", "1", "Aspirin", "45000"]];
+        let vet: &[&[&str]] = &[header, &["54321", "2", "Canidryl", "45001"]];
+        let workbook = xlsx(&[("Humanarzneimittel", human), ("Tierarzneimittel", vet)]);
+
+        let sheets = xlsx_sheets_to_csv_bytes(&workbook, &SheetSelection::All).unwrap();
+        let names: Vec<&str> = sheets.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["Humanarzneimittel", "Tierarzneimittel"]);
+        let csv = |i: usize| String::from_utf8(sheets[i].1.clone()).unwrap();
+        assert!(csv(0).contains(&format!("12345,1,Aspirin,{}", excel_serial_to_date_str(45000.0).unwrap())), "{}", csv(0));
+        assert!(csv(1).contains(&format!("54321,2,Canidryl,{}", excel_serial_to_date_str(45001.0).unwrap())), "{}", csv(1));
+
+        let named = xlsx_sheets_to_csv_bytes(&workbook, &SheetSelection::Named("Tierarzneimittel".into())).unwrap();
+        assert_eq!(named.len(), 1);
+        assert_eq!(named[0].0, "Tierarzneimittel");
+        assert!(matches!(xlsx_sheets_to_csv_bytes(&workbook, &SheetSelection::Named("Other".into())),
+            Err(PharmaError::SheetNotFound { .. })));
+    }
+
+    #[test]
+    fn id_in_a_default_date_column_stays_a_number() {
+        // Column 8 is a date column by default, but here its header names an ID
+        let header = ["Zulassungs-Nummer", "Dosisstärke-nummer", "Bezeichnung", "Zulassungsinhaber", "Heilmittelcode",
+            "Packungscode", "ATC-Code", "Erstzulassungsdatum", "Interne ID", "Gültigkeitsdauer"];
+        let mut range = calamine::Range::new((0, 0), (1, header.len() as u32 - 1));
+        for (col, name) in header.iter().enumerate() {
+            range.set_value((0, col as u32), calamine::Data::String(name.to_string()));
+        }
+        range.set_value((1, 0), calamine::Data::Float(12345.0));
+        range.set_value((1, 7), calamine::Data::Float(45000.0));
+        range.set_value((1, 8), calamine::Data::Float(54321.0));
+        range.set_value((1, 9), calamine::Data::Float(45001.0));
+
+        let csv = String::from_utf8(range_to_csv(&range).unwrap()).unwrap();
+        let row: Vec<&str> = csv.lines().nth(1).unwrap().split(',').collect();
+        assert_eq!(row[8], "54321");
+        assert_eq!(row[7], excel_serial_to_date_str(45000.0).unwrap());
+        assert_eq!(row[9], excel_serial_to_date_str(45001.0).unwrap());
+    }
+
+    #[test]
+    fn cache_of_another_sheet_selection_is_ignored() {
+        let dir = std::env::temp_dir().join(format!("pharma2merge-download-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let csv = dir.join("swissmedic_01.02.2026.csv");
+        fs::write(&csv, "").unwrap();
+        let cache_path = dir.join(".swissmedic.cache");
+        let meta = CacheMeta {
+            url: SWISSMEDIC_URL.to_string(),
+            file: csv.display().to_string(),
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+            sheets: Some(SheetSelection::Named("Tierarzneimittel".into())),
+        };
+        write_cache(&cache_path, &meta).unwrap();
+
+        let read = |sheets: &SheetSelection| read_cache(&cache_path, SWISSMEDIC_URL, Some(sheets)).is_some();
+        assert!(read(&SheetSelection::Named("Tierarzneimittel".into())));
+        assert!(!read(&SheetSelection::First));
+        assert!(!read(&SheetSelection::All));
+        // A sidecar written before the selection was recorded is not trusted either
+        write_cache(&cache_path, &CacheMeta { sheets: None, ..meta }).unwrap();
+        assert!(!read(&SheetSelection::First));
+        assert!(read_cache(&cache_path, SWISSMEDIC_URL, None).is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
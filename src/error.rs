@@ -0,0 +1,88 @@
+use thiserror::Error;
+
+/// Errors returned by the pharma2merge library.
+#[derive(Debug, Error)]
+pub enum PharmaError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("HTTP {status} for {url}")]
+    HttpStatus { status: reqwest::StatusCode, url: String },
+
+    #[error("download of {url} interrupted after {received} bytes: {source}")]
+    Interrupted { url: String, received: usize, source: std::io::Error },
+
+    #[error("giving up after {attempts} attempts: {source}")]
+    RetriesExhausted { attempts: u32, source: Box<PharmaError> },
+
+    #[error("{source_name} download failed: {source}")]
+    DownloadFailed { source_name: &'static str, source: Box<PharmaError> },
+
+    #[error("unexpected response from {url}: {message}")]
+    InvalidResponse { url: String, message: String },
+
+    #[error("invalid CA certificate {path}: {message}")]
+    InvalidCaCert { path: String, message: String },
+
+    #[error("invalid config {path}: {message}")]
+    Config { path: String, message: String },
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[error("XLSX error: {0}")]
+    Xlsx(#[from] calamine::XlsxError),
+
+    #[error("no sheets found in xlsx")]
+    NoSheets,
+
+    #[error("no sheet named {name} in xlsx (available: {available})")]
+    SheetNotFound { name: String, available: String },
+
+    #[error("invalid GTIN {gtin}: {reason}")]
+    InvalidGtin { gtin: String, reason: String },
+
+    #[error("no valid FHIR Bundles in {0}")]
+    NoBundlesFound(String),
+
+    #[error("could not parse date: {0}")]
+    DateParseError(String),
+
+    #[error("invalid merge input {file}: {message}")]
+    InvalidMergeInput { file: String, message: String },
+
+    #[error("invalid diff {file}: {message}")]
+    InvalidDiff { file: String, message: String },
+
+    #[error("invalid history file {path}: {message}")]
+    InvalidHistory { path: String, message: String },
+
+    #[error("no previous {pattern} in {dir} to diff against; run `download` once first")]
+    NoPreviousSnapshot { dir: String, pattern: String },
+
+    #[error("--latest needs at least two dated {pattern} files in {dir}")]
+    NotEnoughSnapshots { dir: String, pattern: String },
+
+    #[error("new file has {new} packages, {percent:.1}% fewer than the {old} of the old file (limit {max}%); it may be truncated")]
+    SuspiciousShrink { old: usize, new: usize, percent: f64, max: f64 },
+
+    #[error("unknown category '{name}' (valid: {valid})")]
+    UnknownCategory { name: String, valid: String },
+
+    #[error("diff hash {actual} does not match the expected {expected}")]
+    HashMismatch { expected: String, actual: String },
+
+    #[error("{problems} problem(s) found in {file}")]
+    CheckFailed { file: String, problems: usize },
+
+    #[error("invalid CSV row {line} in {file}: {reason}")]
+    InvalidCsvRow { file: String, line: usize, reason: String },
+}
+
+pub type Result<T> = std::result::Result<T, PharmaError>;
@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::{info, instrument};
+
+use crate::download::{run_download, DownloadOptions};
+use crate::error::{PharmaError, Result};
+use crate::foph_diff::{run_foph_diff, DateTuple, FophDiffOptions};
+use crate::get_file_mod_date;
+use crate::swissmedic_diff::{extract_swissmedic_date, run_swissmedic_diff, SwissmedicDiffOptions};
+
+// ─── Fetch and diff ──────────────────────────────────────────────────────────
+
+/// Filename prefix and extensions of the FOPH exports in `ndjson/`.
+pub const FOPH_SNAPSHOTS: (&str, &[&str]) = ("sl_foph_", &[".ndjson", ".ndjson.gz"]);
+/// Filename prefix and extension of the Swissmedic CSVs in `csv/`.
+pub const SWISSMEDIC_SNAPSHOTS: (&str, &[&str]) = ("swissmedic_", &[".csv"]);
+
+/// Settings for `run_fetch_and_diff`; `download.swissmedic` / `download.fhir`
+/// select which sources are fetched and diffed.
+#[derive(Clone, Debug, Default)]
+pub struct FetchAndDiffOptions {
+    pub download: DownloadOptions,
+    pub foph: FophDiffOptions,
+    pub swissmedic: SwissmedicDiffOptions,
+}
+
+/// Date of a snapshot as (year, month, day), from its filename
+/// (`…_DD.MM.YYYY…`, `Packungen-YYYY.MM.DD`) or, with `use_mod_date`, its
+/// modification date.
+fn snapshot_date(path: &Path, use_mod_date: bool) -> Option<DateTuple> {
+    let filename = path.to_str()?;
+    // Strip .gz so the date is the last part of the stem
+    let date = extract_swissmedic_date(filename.trim_end_matches(".gz"))
+        .or_else(|| use_mod_date.then(|| get_file_mod_date(filename)))?;
+    let parts: Vec<i32> = date.split('.').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    match parts[..] {
+        [y, m, d] if y > 31 => Some((y, m, d)),
+        [d, m, y] => Some((y, m, d)),
+        _ => None,
+    }
+}
+
+/// Files in `dir` named `<prefix>…` with one of `extensions`, oldest first.
+fn dated_snapshots(dir: &Path, prefix: &str, extensions: &[&str], use_mod_date: bool) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+    let mut snapshots: Vec<(DateTuple, PathBuf)> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            name.starts_with(prefix) && extensions.iter().any(|ext| name.ends_with(ext))
+        })
+        .filter_map(|path| snapshot_date(&path, use_mod_date).map(|date| (date, path)))
+        .collect();
+    snapshots.sort();
+    snapshots.into_iter().map(|(_, path)| path).collect()
+}
+
+/// The most recent file in `dir` named `<prefix>…` with one of `extensions`,
+/// other than `exclude`.
+pub fn find_previous_snapshot(dir: &Path, prefix: &str, extensions: &[&str], exclude: &Path) -> Option<PathBuf> {
+    dated_snapshots(dir, prefix, extensions, true).into_iter()
+        .rfind(|path| path.file_name() != exclude.file_name())
+}
+
+/// The two most recent files in `dir` named `<prefix><DD.MM.YYYY>…` with one
+/// of `extensions`, as (older, newer), sorted by the date in their name.
+pub fn latest_snapshots(dir: &Path, prefix: &str, extensions: &[&str]) -> Result<(PathBuf, PathBuf)> {
+    let mut snapshots = dated_snapshots(dir, prefix, extensions, false);
+    match (snapshots.pop(), snapshots.pop()) {
+        (Some(new), Some(old)) => {
+            info!("Latest snapshots: {} → {}", old.display(), new.display());
+            Ok((old, new))
+        }
+        _ => Err(PharmaError::NotEnoughSnapshots {
+            dir: dir.display().to_string(),
+            pattern: format!("{}DD.MM.YYYY{}", prefix, extensions[0]),
+        }),
+    }
+}
+
+/// Download the current exports and diff each against the most recent earlier
+/// download in the output directory. Fails if there is none.
+#[instrument(skip_all)]
+pub fn run_fetch_and_diff(opts: &FetchAndDiffOptions, out_dir: &Path) -> Result<()> {
+    let downloaded = run_download(&opts.download, out_dir)?;
+    if opts.download.dry_run {
+        info!("[dry-run] Nothing downloaded, skipping the diff.");
+        return Ok(());
+    }
+
+    if let Some(new_csv) = &downloaded.swissmedic {
+        let dir = out_dir.join("csv");
+        let (prefix, extensions) = SWISSMEDIC_SNAPSHOTS;
+        let old_csv = find_previous_snapshot(&dir, prefix, extensions, new_csv)
+            .ok_or_else(|| PharmaError::NoPreviousSnapshot { dir: dir.display().to_string(), pattern: "swissmedic_*.csv".into() })?;
+        info!("Diffing {} against {}", new_csv.display(), old_csv.display());
+        run_swissmedic_diff(&old_csv.to_string_lossy(), &new_csv.to_string_lossy(), &opts.swissmedic, out_dir)?;
+    }
+    if let Some(new_ndjson) = &downloaded.foph {
+        let dir = out_dir.join("ndjson");
+        let (prefix, extensions) = FOPH_SNAPSHOTS;
+        let old_ndjson = find_previous_snapshot(&dir, prefix, extensions, new_ndjson)
+            .ok_or_else(|| PharmaError::NoPreviousSnapshot { dir: dir.display().to_string(), pattern: "sl_foph_*.ndjson".into() })?;
+        info!("Diffing {} against {}", new_ndjson.display(), old_ndjson.display());
+        let (old_files, new_files) = ([old_ndjson.display().to_string()], [new_ndjson.display().to_string()]);
+        run_foph_diff(&old_files, &new_files, &opts.foph, out_dir)?;
+    }
+    Ok(())
+}
@@ -1,9 +1,23 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::time::Instant;
 
+use flate2::read::MultiGzDecoder;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    create_output_dir, flag_legend, print_summary, report_unknown_filter_gtins, retain_atc_prefix,
+    write_json, OutputOptions, TimingStats, OUTPUT_LOG_TARGET,
+};
+use crate::error::{PharmaError, Result};
+use crate::progress;
+use crate::report::{DiffReport, DiffSource};
+use crate::swissmedic_diff::gtin_check_digit_ok;
 
 // ─── Numeric flags (matching Ruby ODDB::OuwerkerkPlugin::NUMERIC_FLAGS) ─────
 
@@ -19,7 +33,8 @@ pub mod numeric_flags {
     pub const NAME_BASE: u8        = 3;
     // pub const ADDRESS: u8       = 4;  // Swissmedic-side only (owner)
     // pub const IKSCAT: u8        = 5;  // Swissmedic-side only
-    // pub const COMPOSITION: u8   = 6;  // Swissmedic-side only
+    /// Active ingredients of the package's product changed.
+    pub const COMPOSITION: u8      = 6;
     // pub const INDICATION: u8    = 7;  // Swissmedic-side only
     // pub const SEQUENCE: u8      = 8;  // Swissmedic-side only
     // pub const EXPIRY_DATE: u8   = 9;  // Swissmedic-side only
@@ -29,57 +44,421 @@ pub mod numeric_flags {
     pub const DELETE: u8           = 14;
     pub const PRICE_CUT: u8        = 15;
     pub const NOT_SPECIFIED: u8    = 16;
+    /// Not part of the Ruby flags; limitation status or points changed.
+    pub const LIMITATION: u8       = 18;
+}
+
+/// The numeric flag of a `FophDiff` category key.
+pub(crate) fn category_flag(key: &str) -> u8 {
+    match key {
+        "new" => numeric_flags::NEW,
+        "del" => numeric_flags::DELETE,
+        "sl_entry" => numeric_flags::SL_ENTRY,
+        "sl_entry_delete" => numeric_flags::SL_ENTRY_DELETE,
+        "limitation_changed" => numeric_flags::LIMITATION,
+        "name_base" => numeric_flags::NAME_BASE,
+        "ingredient_changes" => numeric_flags::COMPOSITION,
+        price if price.ends_with("_up") => numeric_flags::PRICE_RISE,
+        price if price.ends_with("_down") => numeric_flags::PRICE_CUT,
+        _ => numeric_flags::NOT_SPECIFIED,
+    }
+}
+
+// ─── Price types ─────────────────────────────────────────────────────────────
+
+/// FOPH `productPrice` type codes.
+pub mod price_types {
+    pub const RETAIL: &str    = "756002005001";
+    pub const EXFACTORY: &str = "756002005002";
+}
+
+/// Label used in output keys and the `type` field for a price type code:
+/// `retail` / `exfactory` for the known codes, `price_<code>` otherwise.
+pub fn price_type_label(code: &str) -> String {
+    match code {
+        price_types::RETAIL => "retail".to_string(),
+        price_types::EXFACTORY => "exfactory".to_string(),
+        other => format!("price_{}", other),
+    }
 }
 
 // ─── Types ───────────────────────────────────────────────────────────────────
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct PackageInfo {
     pub name: String,
-    pub retail_price: f64,
-    pub exfactory_price: f64,
+    /// Effective price per FOPH price type code; only positive prices are kept.
+    pub prices: BTreeMap<String, f64>,
+    /// `changeDate` of each effective price, keyed like `prices`.
+    #[serde(default)]
+    pub price_dates: BTreeMap<String, DateTuple>,
     pub has_sl_entry: bool,
+    /// Reimbursed only under a limitation of the SL authorization.
+    #[serde(default)]
+    pub has_limitation: bool,
+    /// Limitation points of the SL authorization, 0 if none are given.
+    #[serde(default)]
+    pub limitation_points: u32,
+    /// Limitation text(s) of the SL authorization, one per line.
+    #[serde(default)]
+    pub limitation_text: Option<String>,
+    /// Name of the manufacturer `Organization`, or of the authorization holder.
+    #[serde(default)]
+    pub manufacturer: Option<String>,
+    /// WHO ATC code of the `MedicinalProductDefinition` the package belongs to.
+    #[serde(default)]
+    pub atc_code: Option<String>,
+    /// Active ingredients of that product, sorted by name.
+    #[serde(default)]
+    pub ingredients: Vec<Ingredient>,
+    /// The export line the package's bundle was read from, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Provenance>,
+}
+
+/// Where a bundle was read from: the export file and its (1-based) line.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    pub file: String,
+    pub line: usize,
+}
+
+/// The bundles of one or more exports, with `sources[i]` naming where
+/// `bundles[i]` was read from.
+#[derive(Debug, Default)]
+pub(crate) struct FophBundles {
+    pub bundles: Vec<Value>,
+    pub sources: Vec<Provenance>,
+}
+
+impl FophBundles {
+    fn push(&mut self, bundle: Value, file: &str, line: usize) {
+        self.bundles.push(bundle);
+        self.sources.push(Provenance { file: file.to_string(), line });
+    }
+}
+
+/// An active ingredient from an FHIR `Ingredient` resource.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ingredient {
+    pub name: String,
+    /// Strength per unit of presentation, e.g. `500 mg`.
+    pub strength: Option<String>,
+}
+
+// Prices come from JSON numbers, which cannot be NaN.
+impl Eq for PackageInfo {}
+
+impl PackageInfo {
+    /// Effective price for a type code, 0.0 if the package has none.
+    pub fn price(&self, code: &str) -> f64 {
+        self.prices.get(code).copied().unwrap_or(0.0)
+    }
+
+    pub fn retail_price(&self) -> f64 {
+        self.price(price_types::RETAIL)
+    }
+
+    pub fn exfactory_price(&self) -> f64 {
+        self.price(price_types::EXFACTORY)
+    }
+
+    /// Prices other than retail and ex-factory, keyed by label.
+    fn other_prices(&self) -> BTreeMap<String, f64> {
+        self.prices.iter()
+            .filter(|(code, _)| !matches!(code.as_str(), price_types::RETAIL | price_types::EXFACTORY))
+            .map(|(code, p)| (price_type_label(code), *p))
+            .collect()
+    }
 }
 
-pub type DateTuple = (i32, i32, i32); // (year, month, day)
+pub type DateTuple = (i32, i32, i32); // (year, month, day); serializes as a JSON array
 pub type PackageMap = BTreeMap<String, PackageInfo>;
 
+/// GTIN prefix of Swiss packages, the only one accepted by default.
+pub const SWISS_GTIN_PREFIX: &str = "7680";
+
+/// GS1 identifiers of packages that were not accepted as GTIN, by reason.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct GtinRejections {
+    /// Not 13 characters long.
+    pub wrong_length: usize,
+    /// 13 characters, but not all of them digits.
+    pub not_numeric: usize,
+    /// None of the accepted prefixes (`7680` and `--allow-prefix`).
+    pub wrong_prefix: usize,
+    /// Last digit is not the GS1 check digit.
+    pub bad_checksum: usize,
+}
+
+impl GtinRejections {
+    pub fn total(&self) -> usize {
+        self.wrong_length + self.not_numeric + self.wrong_prefix + self.bad_checksum
+    }
+
+    fn add(&mut self, other: &GtinRejections) {
+        self.wrong_length += other.wrong_length;
+        self.not_numeric += other.not_numeric;
+        self.wrong_prefix += other.wrong_prefix;
+        self.bad_checksum += other.bad_checksum;
+    }
+
+    /// Log the counts, as a warning if any identifier was rejected.
+    pub fn log(&self, label: &str) {
+        let message = format!("{}: {} GTINs rejected ({} wrong length, {} not numeric, {} wrong prefix, {} bad checksum)",
+            label, self.total(), self.wrong_length, self.not_numeric, self.wrong_prefix, self.bad_checksum);
+        if self.total() > 0 { warn!("{}", message) } else { info!("{}", message) }
+    }
+}
+
+/// A package that was added or deleted (categories `new` / `del`).
+#[derive(Clone, Debug, Serialize)]
+pub struct PackageRecord {
+    pub gtin: String,
+    pub name: String,
+    pub flags: Vec<u8>,
+    pub retail_price: Option<f64>,
+    pub exfactory_price: Option<f64>,
+    /// Any further price types, keyed by label.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub other_prices: BTreeMap<String, f64>,
+    pub has_limitation: bool,
+    pub limitation_points: u32,
+    pub manufacturer: Option<String>,
+}
+
+/// A package that gained or lost its SL entry.
+#[derive(Clone, Debug, Serialize)]
+pub struct SlEntryChange {
+    pub gtin: String,
+    pub name: String,
+    pub flags: Vec<u8>,
+    pub has_limitation: bool,
+    pub limitation_points: u32,
+    pub manufacturer: Option<String>,
+}
+
+/// A package whose SL limitation was added, removed or re-scored.
+#[derive(Clone, Debug, Serialize)]
+pub struct LimitationChange {
+    pub gtin: String,
+    pub name: String,
+    pub flags: Vec<u8>,
+    pub old_has_limitation: bool,
+    pub new_has_limitation: bool,
+    pub old_points: u32,
+    pub new_points: u32,
+    pub old_text: Option<String>,
+    pub new_text: Option<String>,
+    pub manufacturer: Option<String>,
+}
+
+/// A package whose active ingredients or their strengths changed.
+#[derive(Clone, Debug, Serialize)]
+pub struct IngredientChange {
+    pub gtin: String,
+    pub name: String,
+    pub flags: Vec<u8>,
+    pub old_ingredients: Vec<Ingredient>,
+    pub new_ingredients: Vec<Ingredient>,
+    pub manufacturer: Option<String>,
+}
+
+/// A package whose name changed.
+#[derive(Clone, Debug, Serialize)]
+pub struct NameChange {
+    pub gtin: String,
+    pub name: String,
+    pub flags: Vec<u8>,
+    pub old_name: String,
+    pub new_name: String,
+    pub has_limitation: bool,
+    pub limitation_points: u32,
+    pub manufacturer: Option<String>,
+}
+
+/// A price movement of one price type.
+#[derive(Clone, Debug, Serialize)]
+pub struct PriceChange {
+    pub gtin: String,
+    pub name: String,
+    pub flags: Vec<u8>,
+    /// `"retail"`, `"exfactory"` or the label of another price type.
+    #[serde(rename = "type")]
+    pub price_type: String,
+    pub old_price: Option<f64>,
+    pub new_price: Option<f64>,
+    pub difference: f64,
+    /// Change relative to the old price in percent, `None` if there was no old price.
+    pub pct_change: Option<f64>,
+    /// `changeDate` of the new price as YYYY-MM-DD, `None` if the price was dropped.
+    pub change_date: Option<String>,
+    pub has_limitation: bool,
+    pub limitation_points: u32,
+    pub manufacturer: Option<String>,
+}
+
+/// All change categories between two FOPH snapshots.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FophDiff {
+    pub new: Vec<PackageRecord>,
+    pub del: Vec<PackageRecord>,
+    pub sl_entry: Vec<SlEntryChange>,
+    pub sl_entry_delete: Vec<SlEntryChange>,
+    pub limitation_changed: Vec<LimitationChange>,
+    pub name_base: Vec<NameChange>,
+    pub ingredient_changes: Vec<IngredientChange>,
+    pub retail_up: Vec<PriceChange>,
+    pub retail_down: Vec<PriceChange>,
+    pub exfactory_up: Vec<PriceChange>,
+    pub exfactory_down: Vec<PriceChange>,
+    /// `<label>_up` / `<label>_down` for the remaining price types.
+    #[serde(flatten)]
+    pub other_prices: BTreeMap<String, Vec<PriceChange>>,
+}
+
+impl FophDiff {
+    /// Every price change category, retail and ex-factory first.
+    pub fn price_changes_mut(&mut self) -> impl Iterator<Item = &mut Vec<PriceChange>> {
+        [&mut self.retail_up, &mut self.retail_down, &mut self.exfactory_up, &mut self.exfactory_down]
+            .into_iter()
+            .chain(self.other_prices.values_mut())
+    }
+
+    /// Drop price changes whose absolute difference is below `min_delta` CHF,
+    /// returning how many were dropped.
+    pub fn drop_minor_price_changes(&mut self, min_delta: f64) -> usize {
+        self.retain_price_changes(|c| c.difference.abs() >= min_delta)
+    }
+
+    /// Drop price changes smaller than `min_pct` percent of the old price,
+    /// returning how many were dropped.
+    /// Changes from a zero price have no percentage and are always kept.
+    pub fn drop_small_pct_changes(&mut self, min_pct: f64) -> usize {
+        self.retain_price_changes(|c| c.pct_change.is_none_or(|pct| pct.abs() >= min_pct))
+    }
+
+    /// Keep the `n` price changes with the largest absolute difference in each
+    /// price category, sorted by it, returning how many were dropped.
+    pub fn keep_top_price_changes(&mut self, n: usize) -> usize {
+        let mut dropped = 0;
+        for changes in self.price_changes_mut() {
+            changes.sort_by(|a, b| b.difference.abs().total_cmp(&a.difference.abs()));
+            dropped += changes.len().saturating_sub(n);
+            changes.truncate(n);
+        }
+        dropped
+    }
+
+    /// Drop price changes whose `change_date` is before `since`, and, if the new
+    /// snapshot's `effective_date` is before `since`, the added and deleted packages.
+    /// Changes without a date are kept.
+    pub fn retain_since(&mut self, since: DateTuple, effective_date: DateTuple) -> usize {
+        let mut dropped = self.retain_price_changes(|c| {
+            c.change_date.as_deref().and_then(parse_date_str).is_none_or(|date| date >= since)
+        });
+        if effective_date < since {
+            dropped += self.new.len() + self.del.len();
+            self.new.clear();
+            self.del.clear();
+        }
+        dropped
+    }
+
+    fn retain_price_changes(&mut self, keep: impl Fn(&PriceChange) -> bool) -> usize {
+        let mut dropped = 0;
+        for changes in self.price_changes_mut() {
+            let before = changes.len();
+            changes.retain(&keep);
+            dropped += before - changes.len();
+        }
+        dropped
+    }
+
+    /// Drop every record whose GTIN is not in `gtins`.
+    pub fn retain_gtins(&mut self, gtins: &BTreeSet<String>) {
+        self.new.retain(|r| gtins.contains(&r.gtin));
+        self.del.retain(|r| gtins.contains(&r.gtin));
+        self.sl_entry.retain(|r| gtins.contains(&r.gtin));
+        self.sl_entry_delete.retain(|r| gtins.contains(&r.gtin));
+        self.limitation_changed.retain(|r| gtins.contains(&r.gtin));
+        self.name_base.retain(|r| gtins.contains(&r.gtin));
+        self.ingredient_changes.retain(|r| gtins.contains(&r.gtin));
+        for changes in self.price_changes_mut() {
+            changes.retain(|c| gtins.contains(&c.gtin));
+        }
+    }
+
+    /// The category arrays keyed as in the output JSON.
+    pub fn to_json_map(&self) -> Result<Map<String, Value>> {
+        match serde_json::to_value(self)? {
+            Value::Object(map) => Ok(map),
+            _ => unreachable!("FophDiff serializes to an object"),
+        }
+    }
+}
+
 // ─── NDJSON reading ──────────────────────────────────────────────────────────
 
-/// Read FOPH ndjson file: each line is a Bundle.
+/// Open a FOPH export for reading, transparently decompressing gzip input
+/// (detected by a `.gz` extension or the 0x1f 0x8b magic bytes).
+fn open_foph_reader(filename: &str) -> std::io::Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(fs::File::open(filename)?);
+    let is_gzip = filename.ends_with(".gz") || reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    if is_gzip {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Read FOPH ndjson file (plain or gzip-compressed): each line is a Bundle.
 /// Also handles concatenated JSON (no newlines between objects) as fallback.
-fn read_foph_bundles(filename: &str) -> Result<Vec<Value>, Box<dyn std::error::Error + Send + Sync>> {
-    let mut content = String::new();
-    std::fs::File::open(filename)
-        .map_err(|e| -> Box<dyn std::error::Error + Send + Sync> { Box::new(e) })?
-        .read_to_string(&mut content)
-        .map_err(|e| -> Box<dyn std::error::Error + Send + Sync> { Box::new(e) })?;
-
-    let mut bundles = Vec::new();
-
-    // Try line-by-line NDJSON first
-    for line in content.lines() {
-        let line = line.trim();
-        if line.is_empty() { continue; }
-        match serde_json::from_str::<Value>(line) {
-            Ok(val) => {
+#[instrument(skip_all, fields(file = filename))]
+pub(crate) fn read_foph_bundles(filename: &str) -> Result<FophBundles> {
+    let mut bundles = FophBundles::default();
+
+    // Try line-by-line NDJSON first, streaming so only one line is held in memory
+    let bar = progress::spinner(format!("Parsing {}", filename));
+    let mut reader = open_foph_reader(filename)?;
+    let mut line = String::new();
+    let mut line_number = 0;
+    while reader.read_line(&mut line)? > 0 {
+        line_number += 1;
+        bar.inc(1);
+        let trimmed = line.trim();
+        if !trimmed.is_empty() {
+            if let Ok(val) = serde_json::from_str::<Value>(trimmed) {
                 if val.get("resourceType").and_then(|v| v.as_str()) == Some("Bundle") {
-                    bundles.push(val);
+                    bundles.push(val, filename, line_number);
                 }
             }
-            Err(_) => {}
         }
+        line.clear();
     }
+    bar.finish_and_clear();
 
-    // Fallback: if no bundles found via line-by-line, try concatenated JSON splitting
-    if bundles.is_empty() {
-        content.retain(|c| c != '\n' && c != '\r');
+    // Fallback: if no bundles found via line-by-line, re-read the whole file
+    // and try concatenated JSON splitting
+    if bundles.bundles.is_empty() {
+        let mut content = String::new();
+        open_foph_reader(filename)?.read_to_string(&mut content)?;
         let mut depth = 0i32;
         let mut in_string = false;
         let mut escape = false;
         let mut start = None;
+        let mut line_number = 1;
+        let mut start_line = 1;
 
         for (i, ch) in content.char_indices() {
+            // Line breaks are dropped from the objects, so they don't end strings
+            if ch == '\n' {
+                line_number += 1;
+                continue;
+            }
+            if ch == '\r' {
+                continue;
+            }
             if escape {
                 escape = false;
                 continue;
@@ -95,17 +474,20 @@ fn read_foph_bundles(filename: &str) -> Result<Vec<Value>, Box<dyn std::error::E
             match ch {
                 '"' => in_string = true,
                 '{' => {
-                    if depth == 0 { start = Some(i); }
+                    if depth == 0 {
+                        start = Some(i);
+                        start_line = line_number;
+                    }
                     depth += 1;
                 }
                 '}' => {
                     depth -= 1;
                     if depth == 0 {
                         if let Some(s) = start {
-                            let obj_str = &content[s..=i];
-                            if let Ok(val) = serde_json::from_str::<Value>(obj_str) {
+                            let obj_str: String = content[s..=i].chars().filter(|c| *c != '\n' && *c != '\r').collect();
+                            if let Ok(val) = serde_json::from_str::<Value>(&obj_str) {
                                 if val.get("resourceType").and_then(|v| v.as_str()) == Some("Bundle") {
-                                    bundles.push(val);
+                                    bundles.push(val, filename, start_line);
                                 }
                             }
                             start = None;
@@ -117,34 +499,10 @@ fn read_foph_bundles(filename: &str) -> Result<Vec<Value>, Box<dyn std::error::E
         }
     }
 
-    // Count unique GTINs across all bundles
-    let mut gtin_count = std::collections::HashSet::new();
-    for bundle in &bundles {
-        if let Some(entries) = bundle.get("entry").and_then(|v| v.as_array()) {
-            for entry in entries {
-                if let Some(res) = entry.get("resource") {
-                    if res.get("resourceType").and_then(|v| v.as_str()) == Some("PackagedProductDefinition") {
-                        if let Some(ids) = res.get("packaging")
-                            .and_then(|p| p.get("identifier"))
-                            .and_then(|ids| ids.as_array())
-                        {
-                            for id in ids {
-                                if let Some(val) = id.get("value").and_then(|v| v.as_str()) {
-                                    if val.len() == 13 && val.starts_with("7680") {
-                                        gtin_count.insert(val.to_string());
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    println!("Loaded {} bundles, {} packages from {}", bundles.len(), gtin_count.len(), filename);
-    if bundles.is_empty() {
-        return Err(format!("No valid FHIR Bundles in {}", filename).into());
+    // The package count follows once the bundles are processed with the accepted prefixes.
+    info!("Loaded {} bundles from {}", bundles.bundles.len(), filename);
+    if bundles.bundles.is_empty() {
+        return Err(PharmaError::NoBundlesFound(filename.to_string()));
     }
     Ok(bundles)
 }
@@ -176,34 +534,258 @@ pub fn extract_date_from_bundles(bundles: &[Value], fallback: DateTuple) -> Date
     }
 
     if date_counts.is_empty() {
-        println!("Info: No bundle timestamp found, using fallback date.");
+        warn!("No bundle timestamp found, using fallback date.");
         return fallback;
     }
 
     let most_common = date_counts.iter().max_by_key(|(_, count)| *count).unwrap();
     let (y, m, d) = most_common.0;
-    println!("Using bundle effective date: {}.{}.{} for price evaluation.", d, m, y);
+    info!("Using bundle effective date: {}.{}.{} for price evaluation.", d, m, y);
     *most_common.0
 }
 
 // ─── Price extraction logic ──────────────────────────────────────────────────
 
-fn get_effective_price(prices: &BTreeMap<DateTuple, f64>, current: &DateTuple) -> f64 {
-    let mut best: Option<&DateTuple> = None;
-    let mut price = 0.0;
-    for (dt, p) in prices {
-        if dt <= current && (best.is_none() || dt > best.unwrap()) {
-            best = Some(dt);
-            price = *p;
+/// The latest price whose change date is not after `current`, with that date.
+fn get_effective_price(prices: &BTreeMap<DateTuple, f64>, current: &DateTuple) -> Option<(DateTuple, f64)> {
+    prices.range(..=*current).next_back().map(|(dt, p)| (*dt, *p))
+}
+
+/// Decode common HTML entities, trim and collapse runs of whitespace, so that names
+/// taken from the `text.div` narrative compare equal to their plain form.
+pub fn normalize_name(name: &str) -> String {
+    let mut decoded = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(pos) = rest.find('&') {
+        decoded.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let entity = rest.find(';').map(|end| (&rest[1..end], end));
+        let ch = entity.and_then(|(entity, _)| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X"))
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|dec| dec.parse()))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        });
+        match (ch, entity) {
+            (Some(ch), Some((_, end))) => {
+                decoded.push(ch);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Name of the `Organization` a FHIR reference like `{"reference": "Organization/1"}` points to.
+fn organization_name(resources: &BTreeMap<String, &Value>, reference: Option<&Value>) -> Option<String> {
+    let key = reference?.get("reference")?.as_str()?;
+    let org = resources.get(key)?;
+    org.get("name").and_then(|v| v.as_str()).map(str::to_string)
+}
+
+/// The `MedicinalProductDefinition` a package belongs to, following its
+/// `packageFor` (or `subject`) reference, or else the only one in the bundle.
+fn medicinal_product<'a>(resources: &BTreeMap<String, &'a Value>, ppd: &Value) -> Option<&'a Value> {
+    let referenced = ["packageFor", "subject"].iter()
+        .filter_map(|key| ppd.get(*key).and_then(|v| v.as_array()))
+        .flatten()
+        .filter_map(|r| r.get("reference").and_then(|v| v.as_str()))
+        .find_map(|key| resources.get(key).copied());
+    referenced.or_else(|| {
+        let mut products = resources.iter()
+            .filter(|(key, _)| key.starts_with("MedicinalProductDefinition/"))
+            .map(|(_, res)| *res);
+        match (products.next(), products.next()) {
+            (Some(only), None) => Some(only),
+            _ => None,
+        }
+    })
+}
+
+/// ATC code from the `http://www.whocc.no/atc` coding of a product's `classification`.
+fn atc_code(product: &Value) -> Option<String> {
+    product.get("classification").and_then(|v| v.as_array()).into_iter().flatten()
+        .filter_map(|c| c.get("coding").and_then(|v| v.as_array()))
+        .flatten()
+        .find(|coding| coding.get("system").and_then(|v| v.as_str()) == Some("http://www.whocc.no/atc"))
+        .and_then(|coding| coding.get("code").and_then(|v| v.as_str()))
+        .map(str::to_string)
+}
+
+/// Active ingredients of `product`: the `Ingredient` resources whose `for`
+/// references it, without those whose role is not `active` (EMA SPOR
+/// 100000072072), sorted by name.
+fn active_ingredients(resources: &BTreeMap<String, &Value>, product: &Value) -> Vec<Ingredient> {
+    let (Some(rtype), Some(id)) = (product["resourceType"].as_str(), product["id"].as_str()) else { return Vec::new() };
+    let product_key = format!("{}/{}", rtype, id);
+    let mut ingredients: Vec<Ingredient> = resources.iter()
+        .filter(|(key, _)| key.starts_with("Ingredient/"))
+        .map(|(_, res)| *res)
+        .filter(|res| res["for"].as_array().into_iter().flatten()
+            .any(|r| r["reference"].as_str() == Some(product_key.as_str())))
+        .filter(|res| {
+            let codings = res["role"]["coding"].as_array().map(|a| a.as_slice()).unwrap_or(&[]);
+            codings.is_empty() || codings.iter().any(|c| {
+                c["code"].as_str() == Some("100000072072")
+                    || c["display"].as_str().is_some_and(|d| d.to_ascii_lowercase().contains("active"))
+            })
+        })
+        .filter_map(|res| {
+            let substance = &res["substance"];
+            let concept = &substance["code"]["concept"];
+            let name = concept["text"].as_str()
+                .or_else(|| concept["coding"].as_array()?.iter().find_map(|c| c["display"].as_str()))?;
+            let strength = substance["strength"].as_array().and_then(|s| s.first()).and_then(strength_text);
+            Some(Ingredient { name: name.trim().to_string(), strength })
+        })
+        .collect();
+    ingredients.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.strength.cmp(&b.strength)));
+    ingredients
+}
+
+/// An `Ingredient.substance.strength` as text: its `textPresentation`, or
+/// the presentation (else concentration) quantity or ratio, e.g. `500 mg` or `5 mg/1 ml`.
+fn strength_text(strength: &Value) -> Option<String> {
+    if let Some(text) = strength["textPresentation"].as_str() {
+        return Some(text.trim().to_string());
+    }
+    let quantity = |q: &Value| -> Option<String> {
+        let value = q["value"].as_f64()?;
+        Some(match q["unit"].as_str().or_else(|| q["code"].as_str()) {
+            Some(unit) => format!("{} {}", value, unit),
+            None => value.to_string(),
+        })
+    };
+    ["presentation", "concentration"].iter().find_map(|kind| {
+        if let Some(q) = strength.get(format!("{}Quantity", kind)) {
+            return quantity(q);
+        }
+        let ratio = strength.get(format!("{}Ratio", kind))?;
+        let numerator = quantity(&ratio["numerator"])?;
+        match quantity(&ratio["denominator"]) {
+            // "500 mg/1" says nothing the numerator doesn't
+            Some(denominator) if denominator != "1" => Some(format!("{}/{}", numerator, denominator)),
+            _ => Some(numerator),
+        }
+    })
+}
+
+/// Limitation extensions found on an SL authorization.
+#[derive(Default)]
+struct Limitation {
+    present: bool,
+    points: u32,
+    texts: Vec<String>,
+}
+
+/// Look for extensions whose URL mentions `limitation` anywhere below `value`,
+/// taking the highest `…points` sub-extension as the limitation points and
+/// collecting the `…text` sub-extensions.
+fn collect_limitations(value: &Value, limitation: &mut Limitation) {
+    match value {
+        Value::Object(obj) => {
+            let url = obj.get("url").and_then(|v| v.as_str()).unwrap_or("");
+            if url.to_ascii_lowercase().contains("limitation") {
+                limitation.present = true;
+                for sub in obj.get("extension").and_then(|v| v.as_array()).into_iter().flatten() {
+                    let sub_url = sub.get("url").and_then(|v| v.as_str()).unwrap_or("").to_ascii_lowercase();
+                    if sub_url.contains("points") {
+                        let value = ["valueInteger", "valuePositiveInt", "valueUnsignedInt", "valueDecimal"].iter()
+                            .find_map(|key| sub.get(*key).and_then(|v| v.as_f64()));
+                        if let Some(v) = value.filter(|v| *v > 0.0) {
+                            limitation.points = limitation.points.max(v as u32);
+                        }
+                    } else if sub_url.contains("text") {
+                        let text = ["valueString", "valueMarkdown"].iter()
+                            .find_map(|key| sub.get(*key).and_then(|v| v.as_str()))
+                            .map(str::trim)
+                            .filter(|t| !t.is_empty());
+                        if let Some(text) = text {
+                            limitation.texts.push(text.to_string());
+                        }
+                    }
+                }
+            }
+            for child in obj.values() {
+                collect_limitations(child, limitation);
+            }
         }
+        Value::Array(items) => {
+            for item in items {
+                collect_limitations(item, limitation);
+            }
+        }
+        _ => {}
     }
-    price
 }
 
+/// True if the `validityPeriod` of an authorization covers `current_dt`. A
+/// missing or unparseable start or end leaves that side open.
+fn authorization_active(auth: &Value, current_dt: &DateTuple) -> bool {
+    let period = &auth["validityPeriod"];
+    let date = |key: &str| period[key].as_str().and_then(parse_date_str);
+    date("start").is_none_or(|start| start <= *current_dt) && date("end").is_none_or(|end| end >= *current_dt)
+}
+
+/// The GS1 GTIN of a PackagedProductDefinition: the first identifier that
+/// has 13 digits, one of `prefixes` and a valid check digit. Every other GS1
+/// identifier is counted in `rejected`.
+pub fn package_gtin(res: &Value, prefixes: &[String], rejected: &mut GtinRejections) -> Option<String> {
+    let ids = res.get("packaging")
+        .and_then(|p| p.get("identifier"))
+        .and_then(|ids| ids.as_array())?;
+    let mut gtin = None;
+    for id in ids {
+        if id.get("system").and_then(|v| v.as_str()) != Some("urn:oid:2.51.1.1") {
+            continue;
+        }
+        let value = id.get("value").and_then(|v| v.as_str()).unwrap_or("");
+        if value.chars().count() != 13 {
+            rejected.wrong_length += 1;
+        } else if !value.chars().all(|c| c.is_ascii_digit()) {
+            rejected.not_numeric += 1;
+        } else if !prefixes.iter().any(|prefix| value.starts_with(prefix.as_str())) {
+            rejected.wrong_prefix += 1;
+        } else if !gtin_check_digit_ok(value) {
+            rejected.bad_checksum += 1;
+        } else if gtin.is_none() {
+            gtin = Some(value.to_string());
+        }
+    }
+    gtin
+}
+
+/// `process_bundles_with` accepting only Swiss (`7680…`) GTINs.
 pub fn process_bundles(bundles: &[Value], current_dt: &DateTuple) -> PackageMap {
+    process_bundles_with(bundles, &[], current_dt, &[SWISS_GTIN_PREFIX.to_string()]).0
+}
+
+/// Extract every package with a GTIN starting with one of `prefixes` from
+/// `bundles`, with its prices effective at `current_dt`, and count the
+/// rejected identifiers. `sources[i]`, if given, is recorded as the
+/// `source` of the packages of `bundles[i]`.
+pub fn process_bundles_with(
+    bundles: &[Value],
+    sources: &[Provenance],
+    current_dt: &DateTuple,
+    prefixes: &[String],
+) -> (PackageMap, GtinRejections) {
     let mut packages = PackageMap::new();
+    let mut rejected = GtinRejections::default();
 
-    for bundle in bundles {
+    for (index, bundle) in bundles.iter().enumerate() {
         let entries = match bundle.get("entry").and_then(|v| v.as_array()) {
             Some(arr) => arr,
             None => continue,
@@ -232,26 +814,7 @@ pub fn process_bundles(bundles: &[Value], current_dt: &DateTuple) -> PackageMap
         for ppd_key in &ppd_keys {
             let res = resources[ppd_key];
 
-            // Extract GTIN
-            let gtin = res.get("packaging")
-                .and_then(|p| p.get("identifier"))
-                .and_then(|ids| ids.as_array())
-                .and_then(|ids| {
-                    ids.iter().find_map(|id| {
-                        let system = id.get("system").and_then(|v| v.as_str()).unwrap_or("");
-                        let value = id.get("value").and_then(|v| v.as_str()).unwrap_or("");
-                        if system == "urn:oid:2.51.1.1" && value.len() == 13 && value.starts_with("7680") {
-                            Some(value.to_string())
-                        } else {
-                            None
-                        }
-                    })
-                });
-
-            let gtin = match gtin {
-                Some(g) => g,
-                None => continue,
-            };
+            let Some(gtin) = package_gtin(res, prefixes, &mut rejected) else { continue };
 
             // Extract name
             let name = res.get("description").and_then(|v| v.as_str())
@@ -259,11 +822,22 @@ pub fn process_bundles(bundles: &[Value], current_dt: &DateTuple) -> PackageMap
                 .unwrap_or("Unknown Product")
                 .to_string();
 
+            let manufacturer = res.get("manufacturer")
+                .and_then(|m| m.as_array())
+                .and_then(|refs| refs.iter().find_map(|r| organization_name(&resources, Some(r))));
+
+            let product = medicinal_product(&resources, res);
+            let atc_code = product.and_then(atc_code);
+            let ingredients = product.map(|p| active_ingredients(&resources, p)).unwrap_or_default();
+
             // Collect prices and SL status from RegulatedAuthorization resources
             let mut price_by_type: BTreeMap<String, BTreeMap<DateTuple, f64>> = BTreeMap::new();
             let mut has_sl_entry = false;
+            let mut has_inactive_sl_entry = false;
+            let mut limitation = Limitation::default();
+            let mut holder = None;
 
-            for (_, auth) in &resources {
+            for auth in resources.values() {
                 if auth.get("resourceType").and_then(|v| v.as_str()) != Some("RegulatedAuthorization") {
                     continue;
                 }
@@ -289,8 +863,20 @@ pub fn process_bundles(bundles: &[Value], current_dt: &DateTuple) -> PackageMap
 
                 if subject_ref != ppd_key { continue; }
 
+                holder = holder.or_else(|| organization_name(&resources, auth.get("holder")));
+
+                // An SL entry that lapsed before (or starts after) the evaluation
+                // date leaves the package listed, but without SL entry or prices,
+                // so a lapse shows up as sl_entry_delete and a reactivation as sl_entry
+                if !authorization_active(auth, current_dt) {
+                    debug!("{}: SL authorization not valid on {}-{:02}-{:02}", gtin, current_dt.0, current_dt.1, current_dt.2);
+                    has_inactive_sl_entry = true;
+                    continue;
+                }
+
                 // This package has an SL entry via RegulatedAuthorization
                 has_sl_entry = true;
+                collect_limitations(auth, &mut limitation);
 
                 // Extract price extensions
                 let extensions = match auth.get("extension").and_then(|v| v.as_array()) {
@@ -340,15 +926,11 @@ pub fn process_bundles(bundles: &[Value], current_dt: &DateTuple) -> PackageMap
                         }
                     }
 
-                    let price_type = match type_code.as_str() {
-                        "756002005001" => "retail",
-                        "756002005002" => "exfactory",
-                        _ => continue,
-                    };
+                    if type_code.is_empty() { continue; }
 
                     if value > 0.0 && !change_date.is_empty() {
                         if let Some(dt) = parse_date_str(&change_date) {
-                            price_by_type.entry(price_type.to_string())
+                            price_by_type.entry(type_code)
                                 .or_default()
                                 .insert(dt, value);
                         }
@@ -356,155 +938,138 @@ pub fn process_bundles(bundles: &[Value], current_dt: &DateTuple) -> PackageMap
                 }
             }
 
-            let retail = get_effective_price(
-                price_by_type.get("retail").unwrap_or(&BTreeMap::new()),
-                current_dt,
-            );
-            let exfactory = get_effective_price(
-                price_by_type.get("exfactory").unwrap_or(&BTreeMap::new()),
-                current_dt,
-            );
-
-            // Include packages even without prices if they have an SL entry,
-            // so we can track SL status changes
-            if retail > 0.0 || exfactory > 0.0 || has_sl_entry {
-                packages.insert(gtin, PackageInfo {
+            let mut prices = BTreeMap::new();
+            let mut price_dates = BTreeMap::new();
+            for (code, history) in price_by_type {
+                if let Some((date, price)) = get_effective_price(&history, current_dt) {
+                    prices.insert(code.clone(), price);
+                    price_dates.insert(code, date);
+                }
+            }
+
+            // Include packages even without prices if they have an SL entry, active
+            // or not, so we can track SL status changes
+            if !prices.is_empty() || has_sl_entry || has_inactive_sl_entry {
+                insert_package(&mut packages, gtin, PackageInfo {
                     name,
-                    retail_price: retail,
-                    exfactory_price: exfactory,
+                    prices,
+                    price_dates,
                     has_sl_entry,
+                    has_limitation: limitation.present,
+                    limitation_points: limitation.points,
+                    limitation_text: (!limitation.texts.is_empty()).then(|| limitation.texts.join("\n")),
+                    manufacturer: manufacturer.or(holder),
+                    atc_code,
+                    ingredients,
+                    source: sources.get(index).cloned(),
                 });
             }
         }
     }
-    packages
+    (packages, rejected)
 }
 
-// ─── Public entry point ──────────────────────────────────────────────────────
-
-pub fn run_foph_diff(old_file: &str, new_file: &str, filter: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
-    // Extract date strings from input filenames
-    let extract_date_from_filename = |path: &str| -> String {
-        let stem = std::path::Path::new(path)
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("");
-        for part in stem.split('_') {
-            let segments: Vec<&str> = part.split('.').collect();
-            if segments.len() == 3
-                && segments[0].len() <= 2
-                && segments[1].len() <= 2
-                && segments[2].len() == 4
-                && segments.iter().all(|s| s.chars().all(|c| c.is_ascii_digit()))
-            {
-                return part.to_string();
-            }
-        }
-        crate::get_file_mod_date(path)
-    };
+/// Insert `info` under `gtin`; a later package with the same GTIN replaces
+/// the earlier one.
+fn insert_package(packages: &mut PackageMap, gtin: String, info: PackageInfo) {
+    if let Some(previous) = packages.get(&gtin) {
+        warn!("Duplicate GTIN {} ({} / {}), keeping the later entry", gtin, previous.name, info.name);
+    }
+    packages.insert(gtin, info);
+}
 
-    // Parse dd.mm.yyyy string to DateTuple
-    let date_str_to_tuple = |s: &str| -> DateTuple {
-        let parts: Vec<&str> = s.split('.').collect();
-        if parts.len() == 3 {
-            let d: i32 = parts[0].parse().unwrap_or(1);
-            let m: i32 = parts[1].parse().unwrap_or(1);
-            let y: i32 = parts[2].parse().unwrap_or(2026);
-            (y, m, d)
-        } else {
-            (2026, 1, 6)
+/// `process_bundles_with` over chunks of `bundles` in parallel, merged in
+/// bundle order so duplicates resolve the same way as in a single pass.
+fn process_bundles_parallel(bundles: &FophBundles, current_dt: &DateTuple, prefixes: &[String]) -> (PackageMap, GtinRejections) {
+    let chunk_size = std::cmp::max(1, bundles.bundles.len() / rayon::current_num_threads());
+    let results: Vec<(PackageMap, GtinRejections)> = bundles.bundles.par_chunks(chunk_size)
+        .zip(bundles.sources.par_chunks(chunk_size))
+        .map(|(chunk, sources)| process_bundles_with(chunk, sources, current_dt, prefixes))
+        .collect();
+    let mut packages = PackageMap::new();
+    let mut rejected = GtinRejections::default();
+    for (chunk, chunk_rejected) in results {
+        rejected.add(&chunk_rejected);
+        for (gtin, info) in chunk {
+            insert_package(&mut packages, gtin, info);
         }
-    };
-
-    let old_date_str = extract_date_from_filename(old_file);
-    let new_date_str = extract_date_from_filename(new_file);
-    let old_fallback_dt = date_str_to_tuple(&old_date_str);
-    let new_fallback_dt = date_str_to_tuple(&new_date_str);
-    println!("Old date: {}", old_date_str);
-    println!("New date: {}", new_date_str);
-
-    // Load both files in parallel
-    let old_file_owned = old_file.to_string();
-    let new_file_owned = new_file.to_string();
-
-    let (old_result, new_result) = rayon::join(
-        || -> Result<(Vec<Value>, DateTuple), Box<dyn std::error::Error + Send + Sync>> {
-            println!("Loading old file...");
-            let bundles = read_foph_bundles(&old_file_owned)?;
-            let effective_date = extract_date_from_bundles(&bundles, old_fallback_dt);
-            Ok((bundles, effective_date))
-        },
-        || -> Result<(Vec<Value>, DateTuple), Box<dyn std::error::Error + Send + Sync>> {
-            println!("Loading new file...");
-            let bundles = read_foph_bundles(&new_file_owned)?;
-            let effective_date = extract_date_from_bundles(&bundles, new_fallback_dt);
-            Ok((bundles, effective_date))
-        },
-    );
+    }
+    (packages, rejected)
+}
 
-    let (old_bundles, old_effective_date) = old_result.map_err(|e| -> Box<dyn std::error::Error> { e })?;
-    let (new_bundles, new_effective_date) = new_result.map_err(|e| -> Box<dyn std::error::Error> { e })?;
+/// Read the bundles of one or more FOPH exports (e.g. a list split by
+/// therapeutic area), in the order given.
+fn read_foph_bundle_files(files: &[String]) -> Result<FophBundles> {
+    let mut bundles = FophBundles::default();
+    for file in files {
+        let FophBundles { bundles: read, sources } = read_foph_bundles(file)?;
+        bundles.bundles.extend(read);
+        bundles.sources.extend(sources);
+    }
+    if files.len() > 1 {
+        info!("Read {} bundles from {} files", bundles.bundles.len(), files.len());
+    }
+    Ok(bundles)
+}
 
-    // Process bundles in parallel
-    let (old_pkg, new_pkg) = rayon::join(
-        || {
-            let chunk_size = std::cmp::max(1, old_bundles.len() / rayon::current_num_threads());
-            let results: Vec<PackageMap> = old_bundles.par_chunks(chunk_size)
-                .map(|chunk| process_bundles(chunk, &old_effective_date))
-                .collect();
-            let mut m = PackageMap::new();
-            for r in results { m.extend(r); }
-            m
-        },
-        || {
-            let chunk_size = std::cmp::max(1, new_bundles.len() / rayon::current_num_threads());
-            let results: Vec<PackageMap> = new_bundles.par_chunks(chunk_size)
-                .map(|chunk| process_bundles(chunk, &new_effective_date))
-                .collect();
-            let mut m = PackageMap::new();
-            for r in results { m.extend(r); }
-            m
-        },
-    );
+// ─── Diff computation ────────────────────────────────────────────────────────
 
-    println!("Found {} packages (old), {} (new).", old_pkg.len(), new_pkg.len());
+fn positive(p: f64) -> Option<f64> {
+    if p > 0.0 { Some(p) } else { None }
+}
 
-    // ── Compute all diff categories ──────────────────────────────────────────
+/// `difference` in percent of `old_price`, rounded to one decimal.
+pub(crate) fn pct_change(difference: f64, old_price: f64) -> f64 {
+    (difference / old_price * 1000.0).round() / 10.0
+}
 
+/// Compare two package maps and collect every change category.
+pub fn compute_foph_diff(old_pkg: &PackageMap, new_pkg: &PackageMap) -> FophDiff {
     // 1. New packages (flag 1: new)
-    let new_packages: Vec<Value> = new_pkg.par_iter()
+    let new_packages: Vec<PackageRecord> = new_pkg.par_iter()
         .filter(|(gtin, _)| !old_pkg.contains_key(*gtin))
-        .map(|(gtin, info)| json!({
-            "gtin": gtin,
-            "name": info.name,
-            "flags": [numeric_flags::NEW],
-            "retail_price": if info.retail_price > 0.0 { json!(info.retail_price) } else { Value::Null },
-            "exfactory_price": if info.exfactory_price > 0.0 { json!(info.exfactory_price) } else { Value::Null },
-        }))
+        .map(|(gtin, info)| PackageRecord {
+            gtin: gtin.clone(),
+            name: info.name.clone(),
+            flags: vec![numeric_flags::NEW],
+            retail_price: positive(info.retail_price()),
+            exfactory_price: positive(info.exfactory_price()),
+            other_prices: info.other_prices(),
+            has_limitation: info.has_limitation,
+            limitation_points: info.limitation_points,
+            manufacturer: info.manufacturer.clone(),
+        })
         .collect();
 
     // 14. Package deletions (flag 14: delete)
-    let package_deletions: Vec<Value> = old_pkg.par_iter()
+    let package_deletions: Vec<PackageRecord> = old_pkg.par_iter()
         .filter(|(gtin, _)| !new_pkg.contains_key(*gtin))
-        .map(|(gtin, info)| json!({
-            "gtin": gtin,
-            "name": info.name,
-            "flags": [numeric_flags::DELETE],
-            "retail_price": if info.retail_price > 0.0 { json!(info.retail_price) } else { Value::Null },
-            "exfactory_price": if info.exfactory_price > 0.0 { json!(info.exfactory_price) } else { Value::Null },
-        }))
+        .map(|(gtin, info)| PackageRecord {
+            gtin: gtin.clone(),
+            name: info.name.clone(),
+            flags: vec![numeric_flags::DELETE],
+            retail_price: positive(info.retail_price()),
+            exfactory_price: positive(info.exfactory_price()),
+            other_prices: info.other_prices(),
+            has_limitation: info.has_limitation,
+            limitation_points: info.limitation_points,
+            manufacturer: info.manufacturer.clone(),
+        })
         .collect();
 
     // 10. SL entry additions (flag 10: sl_entry) — package exists in both but gained SL
-    let sl_entry_additions: Vec<Value> = new_pkg.par_iter()
+    let sl_entry_additions: Vec<SlEntryChange> = new_pkg.par_iter()
         .filter_map(|(gtin, new_info)| {
             old_pkg.get(gtin).and_then(|old_info| {
                 if !old_info.has_sl_entry && new_info.has_sl_entry {
-                    Some(json!({
-                        "gtin": gtin,
-                        "name": new_info.name,
-                        "flags": [numeric_flags::SL_ENTRY],
-                    }))
+                    Some(SlEntryChange {
+                        gtin: gtin.clone(),
+                        name: new_info.name.clone(),
+                        flags: vec![numeric_flags::SL_ENTRY],
+                        has_limitation: new_info.has_limitation,
+                        limitation_points: new_info.limitation_points,
+                        manufacturer: new_info.manufacturer.clone(),
+                    })
                 } else {
                     None
                 }
@@ -513,15 +1078,18 @@ pub fn run_foph_diff(old_file: &str, new_file: &str, filter: Option<&str>) -> Re
         .collect();
 
     // 2. SL entry deletions (flag 2: sl_entry_delete) — package exists in both but lost SL
-    let sl_entry_deletions: Vec<Value> = new_pkg.par_iter()
+    let sl_entry_deletions: Vec<SlEntryChange> = new_pkg.par_iter()
         .filter_map(|(gtin, new_info)| {
             old_pkg.get(gtin).and_then(|old_info| {
                 if old_info.has_sl_entry && !new_info.has_sl_entry {
-                    Some(json!({
-                        "gtin": gtin,
-                        "name": new_info.name,
-                        "flags": [numeric_flags::SL_ENTRY_DELETE],
-                    }))
+                    Some(SlEntryChange {
+                        gtin: gtin.clone(),
+                        name: new_info.name.clone(),
+                        flags: vec![numeric_flags::SL_ENTRY_DELETE],
+                        has_limitation: new_info.has_limitation,
+                        limitation_points: new_info.limitation_points,
+                        manufacturer: new_info.manufacturer.clone(),
+                    })
                 } else {
                     None
                 }
@@ -529,18 +1097,44 @@ pub fn run_foph_diff(old_file: &str, new_file: &str, filter: Option<&str>) -> Re
         })
         .collect();
 
+    // 18. Limitation changes — package on the SL in both, limitation flipped, points or text changed
+    let limitation_changes: Vec<LimitationChange> = new_pkg.par_iter()
+        .filter_map(|(gtin, new_info)| {
+            let old_info = old_pkg.get(gtin).filter(|old_info| old_info.has_sl_entry == new_info.has_sl_entry)?;
+            let text = |info: &PackageInfo| info.limitation_text.as_deref().map(normalize_name);
+            let changed = old_info.has_limitation != new_info.has_limitation
+                || old_info.limitation_points != new_info.limitation_points
+                || text(old_info) != text(new_info);
+            changed.then(|| LimitationChange {
+                gtin: gtin.clone(),
+                name: new_info.name.clone(),
+                flags: vec![numeric_flags::LIMITATION],
+                old_has_limitation: old_info.has_limitation,
+                new_has_limitation: new_info.has_limitation,
+                old_points: old_info.limitation_points,
+                new_points: new_info.limitation_points,
+                old_text: old_info.limitation_text.clone(),
+                new_text: new_info.limitation_text.clone(),
+                manufacturer: new_info.manufacturer.clone(),
+            })
+        })
+        .collect();
+
     // 3. Name changes (flag 3: name_base)
-    let name_changes: Vec<Value> = new_pkg.par_iter()
+    let name_changes: Vec<NameChange> = new_pkg.par_iter()
         .filter_map(|(gtin, new_info)| {
             old_pkg.get(gtin).and_then(|old_info| {
-                if old_info.name != new_info.name {
-                    Some(json!({
-                        "gtin": gtin,
-                        "name": new_info.name,
-                        "flags": [numeric_flags::NAME_BASE],
-                        "old_name": old_info.name,
-                        "new_name": new_info.name,
-                    }))
+                if normalize_name(&old_info.name) != normalize_name(&new_info.name) {
+                    Some(NameChange {
+                        gtin: gtin.clone(),
+                        name: new_info.name.clone(),
+                        flags: vec![numeric_flags::NAME_BASE],
+                        old_name: old_info.name.clone(),
+                        new_name: new_info.name.clone(),
+                        has_limitation: new_info.has_limitation,
+                        limitation_points: new_info.limitation_points,
+                        manufacturer: new_info.manufacturer.clone(),
+                    })
                 } else {
                     None
                 }
@@ -548,15 +1142,39 @@ pub fn run_foph_diff(old_file: &str, new_file: &str, filter: Option<&str>) -> Re
         })
         .collect();
 
-    // 11/13/15. Price changes with directional flags
-    let price_changes: Vec<Value> = new_pkg.par_iter()
+    // 6. Ingredient changes (flag 6: composition) — only where both exports list
+    // ingredients, so an export without them doesn't flag every package
+    let ingredient_key = |info: &PackageInfo| -> Vec<(String, Option<String>)> {
+        info.ingredients.iter()
+            .map(|i| (normalize_name(&i.name), i.strength.as_deref().map(normalize_name)))
+            .collect()
+    };
+    let ingredient_changes: Vec<IngredientChange> = new_pkg.par_iter()
+        .filter_map(|(gtin, new_info)| {
+            let old_info = old_pkg.get(gtin)?;
+            let changed = !old_info.ingredients.is_empty()
+                && !new_info.ingredients.is_empty()
+                && ingredient_key(old_info) != ingredient_key(new_info);
+            changed.then(|| IngredientChange {
+                gtin: gtin.clone(),
+                name: new_info.name.clone(),
+                flags: vec![numeric_flags::COMPOSITION],
+                old_ingredients: old_info.ingredients.clone(),
+                new_ingredients: new_info.ingredients.clone(),
+                manufacturer: new_info.manufacturer.clone(),
+            })
+        })
+        .collect();
+
+    // 11/13/15. Price changes with directional flags. Like limitations, prices come and
+    // go with the SL entry, which sl_entry and sl_entry_delete already report
+    let price_changes: Vec<PriceChange> = new_pkg.par_iter()
         .filter_map(|(gtin, new_info)| {
-            old_pkg.get(gtin).map(|old_info| {
+            old_pkg.get(gtin).filter(|old_info| old_info.has_sl_entry == new_info.has_sl_entry).map(|old_info| {
                 let mut changes = Vec::new();
-                for (ptype, old_p, new_p) in [
-                    ("retail", old_info.retail_price, new_info.retail_price),
-                    ("exfactory", old_info.exfactory_price, new_info.exfactory_price),
-                ] {
+                let codes: BTreeSet<&String> = old_info.prices.keys().chain(new_info.prices.keys()).collect();
+                for code in codes {
+                    let (old_p, new_p) = (old_info.price(code), new_info.price(code));
                     if (new_p - old_p).abs() > 0.001 {
                         let diff = new_p - old_p;
                         // flag 11 (price) always present, plus 13 (price_rise) or 15 (price_cut)
@@ -565,15 +1183,21 @@ pub fn run_foph_diff(old_file: &str, new_file: &str, filter: Option<&str>) -> Re
                         } else {
                             vec![numeric_flags::PRICE, numeric_flags::PRICE_CUT]
                         };
-                        changes.push(json!({
-                            "gtin": gtin,
-                            "name": new_info.name,
-                            "flags": flags,
-                            "type": ptype,
-                            "old_price": if old_p > 0.0 { json!(old_p) } else { Value::Null },
-                            "new_price": if new_p > 0.0 { json!(new_p) } else { Value::Null },
-                            "difference": diff,
-                        }));
+                        changes.push(PriceChange {
+                            gtin: gtin.clone(),
+                            name: new_info.name.clone(),
+                            flags,
+                            price_type: price_type_label(code),
+                            old_price: positive(old_p),
+                            new_price: positive(new_p),
+                            difference: diff,
+                            pct_change: positive(old_p).map(|old| pct_change(diff, old)),
+                            change_date: new_info.price_dates.get(code)
+                                .map(|(y, m, d)| format!("{:04}-{:02}-{:02}", y, m, d)),
+                            has_limitation: new_info.has_limitation,
+                            limitation_points: new_info.limitation_points,
+                            manufacturer: new_info.manufacturer.clone(),
+                        });
                     }
                 }
                 changes
@@ -582,113 +1206,768 @@ pub fn run_foph_diff(old_file: &str, new_file: &str, filter: Option<&str>) -> Re
         .flatten()
         .collect();
 
-    let mut retail_up = Vec::new();
-    let mut retail_down = Vec::new();
-    let mut exfactory_up = Vec::new();
-    let mut exfactory_down = Vec::new();
+    let mut diff = FophDiff {
+        new: new_packages,
+        del: package_deletions,
+        sl_entry: sl_entry_additions,
+        sl_entry_delete: sl_entry_deletions,
+        limitation_changed: limitation_changes,
+        name_base: name_changes,
+        ingredient_changes,
+        ..FophDiff::default()
+    };
 
     for change in price_changes {
-        let ptype = change["type"].as_str().unwrap_or("");
-        let diff = change["difference"].as_f64().unwrap_or(0.0);
-        match (ptype, diff > 0.0) {
-            ("retail", true) => retail_up.push(change),
-            ("retail", false) => retail_down.push(change),
-            ("exfactory", true) => exfactory_up.push(change),
-            ("exfactory", false) => exfactory_down.push(change),
-            _ => {}
-        }
-    }
-
-    let n_new = new_packages.len();
-    let n_del = package_deletions.len();
-    let n_sl_add = sl_entry_additions.len();
-    let n_sl_del = sl_entry_deletions.len();
-    let n_name = name_changes.len();
-    let n_ru = retail_up.len();
-    let n_rd = retail_down.len();
-    let n_eu = exfactory_up.len();
-    let n_ed = exfactory_down.len();
-
-    // If a filter is set, just print GTINs for that category and exit
-    if let Some(cat) = filter {
-        let items: &[Value] = match cat {
-            "new" => &new_packages,
-            "del" | "delete" => &package_deletions,
-            "sl_entry" => &sl_entry_additions,
-            "sl_entry_delete" => &sl_entry_deletions,
-            "name" | "name_base" | "productname" => &name_changes,
-            "retail_up" | "price_rise_retail" => &retail_up,
-            "retail_down" | "price_cut_retail" => &retail_down,
-            "exfactory_up" | "price_rise_exfactory" => &exfactory_up,
-            "exfactory_down" | "price_cut_exfactory" => &exfactory_down,
-            _ => {
-                eprintln!("Unknown category '{}'.", cat);
-                eprintln!("Valid: new, del, sl_entry, sl_entry_delete, name,");
-                eprintln!("       retail_up, retail_down, exfactory_up, exfactory_down");
-                std::process::exit(1);
+        match (change.price_type.as_str(), change.difference > 0.0) {
+            ("retail", true) => diff.retail_up.push(change),
+            ("retail", false) => diff.retail_down.push(change),
+            ("exfactory", true) => diff.exfactory_up.push(change),
+            ("exfactory", false) => diff.exfactory_down.push(change),
+            (label, up) => {
+                let key = format!("{}_{}", label, if up { "up" } else { "down" });
+                diff.other_prices.entry(key).or_default().push(change);
             }
-        };
+        }
+    }
+
+    diff
+}
+
+// ─── Public entry point ──────────────────────────────────────────────────────
+
+/// Settings for `run_foph_diff`.
+#[derive(Clone, Debug, Default)]
+pub struct FophDiffOptions {
+    /// Only print the GTINs of this category instead of writing the diff.
+    pub filter: Option<String>,
+    /// Suppress price changes smaller than this many CHF.
+    pub min_price_delta: Option<f64>,
+    /// Suppress price changes smaller than this percentage of the old price.
+    pub min_pct_change: Option<f64>,
+    /// Keep only this many of the largest price changes per price category.
+    pub top_by_price_change: Option<usize>,
+    /// Evaluate prices at this date instead of the bundle timestamps.
+    pub as_of: Option<DateTuple>,
+    /// Evaluate the old file's prices at this date; takes precedence over `as_of`.
+    pub old_date: Option<DateTuple>,
+    /// Evaluate the new file's prices at this date; takes precedence over `as_of`.
+    pub new_date: Option<DateTuple>,
+    /// Also write one CSV per category next to the JSON.
+    pub csv: bool,
+    /// Add a `by_manufacturer` object listing the affected GTINs per manufacturer.
+    pub group_by_manufacturer: bool,
+    /// Add a `price_changes_by_package` array with all price changes of a GTIN in one record.
+    pub group_prices: bool,
+    /// Skip the JSON output (only meaningful together with `csv`).
+    pub no_json: bool,
+    /// Write the JSON keyed by GTIN instead of by category.
+    pub group_by_gtin: bool,
+    /// Drop price changes dated, and added/deleted packages of a snapshot dated, before this day.
+    pub since: Option<DateTuple>,
+    /// Only diff packages whose ATC code starts with this prefix.
+    pub filter_atc: Option<String>,
+    /// Only report changes of these GTINs (see `load_gtin_filter`).
+    pub filter_gtin: Option<BTreeSet<String>>,
+    /// GTIN prefixes accepted in addition to `7680`, e.g. for test data.
+    pub allow_prefixes: Vec<String>,
+    /// Add `source_file` and `source_line` to every record.
+    pub with_provenance: bool,
+    pub output: OutputOptions,
+}
+
+impl FophDiffOptions {
+    /// `7680` followed by `allow_prefixes`.
+    pub fn gtin_prefixes(&self) -> Vec<String> {
+        std::iter::once(SWISS_GTIN_PREFIX.to_string()).chain(self.allow_prefixes.iter().cloned()).collect()
+    }
+}
+
+/// Regroup the category arrays into one object keyed by GTIN, each record
+/// tagged with its `"category"`, plus `_stats` with the per-category counts.
+pub fn group_by_gtin(categories: &Map<String, Value>) -> Map<String, Value> {
+    let mut grouped = Map::new();
+    let mut stats = Map::new();
+    for (category, items) in categories {
+        let items = items.as_array().map(|a| a.as_slice()).unwrap_or(&[]);
+        stats.insert(category.clone(), items.len().into());
         for item in items {
-            if let Some(gtin) = item["gtin"].as_str() {
-                println!("{}", gtin);
+            let Some(gtin) = item["gtin"].as_str() else { continue };
+            let mut record = item.clone();
+            if let Value::Object(fields) = &mut record {
+                fields.insert("category".into(), Value::String(category.clone()));
+            }
+            if let Value::Array(changes) = grouped.entry(gtin).or_insert_with(|| Value::Array(Vec::new())) {
+                changes.push(record);
             }
         }
-        return Ok(());
     }
+    grouped.insert("_stats".into(), Value::Object(stats));
+    grouped
+}
 
-    let mut output = Map::new();
+/// GTINs of every record in `categories` grouped by `manufacturer`
+/// (`"unknown"` where there is none), each list sorted and deduplicated.
+pub fn group_by_manufacturer(categories: &Map<String, Value>) -> Map<String, Value> {
+    let mut grouped: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for item in categories.values().filter_map(|v| v.as_array()).flatten() {
+        let Some(gtin) = item["gtin"].as_str() else { continue };
+        let manufacturer = item["manufacturer"].as_str().unwrap_or("unknown");
+        grouped.entry(manufacturer.to_string()).or_default().insert(gtin.to_string());
+    }
+    grouped.into_iter()
+        .map(|(manufacturer, gtins)| (manufacturer, gtins.into_iter().collect::<Vec<_>>().into()))
+        .collect()
+}
 
-    // Include numeric flag legend for downstream consumers
-    let legend = json!({
-        "1":  "new",
-        "2":  "sl_entry_delete",
-        "3":  "name_base",
-        "4":  "address",
-        "5":  "ikscat",
-        "6":  "composition",
-        "7":  "indication",
-        "8":  "sequence",
-        "9":  "expiry_date",
-        "10": "sl_entry",
-        "11": "price",
-        "12": "comment",
-        "13": "price_rise",
-        "14": "delete",
-        "15": "price_cut",
-        "16": "not_specified"
-    });
-    output.insert("_flag_legend".into(), legend);
+/// All price changes in `categories` combined into one record per GTIN, with
+/// the old/new price, difference, percentage and date of each price type
+/// under `prices.<type>` and the flags of all of them.
+pub fn group_price_changes(categories: &Map<String, Value>) -> Vec<Value> {
+    let mut grouped: BTreeMap<String, Value> = BTreeMap::new();
+    for item in categories.values().filter_map(|v| v.as_array()).flatten() {
+        // Only price change records carry a price type
+        let (Some(gtin), Some(price_type)) = (item["gtin"].as_str(), item["type"].as_str()) else { continue };
+        let entry = grouped.entry(gtin.to_string()).or_insert_with(|| json!({
+            "gtin": gtin,
+            "name": item["name"],
+            "manufacturer": item["manufacturer"],
+            "flags": [],
+            "prices": {},
+        }));
+        if let Some(flags) = entry["flags"].as_array_mut() {
+            for flag in item["flags"].as_array().into_iter().flatten() {
+                if !flags.contains(flag) {
+                    flags.push(flag.clone());
+                }
+            }
+            flags.sort_by_key(|f| f.as_u64());
+        }
+        entry["prices"][price_type] = json!({
+            "old_price": item["old_price"],
+            "new_price": item["new_price"],
+            "difference": item["difference"],
+            "pct_change": item["pct_change"],
+            "change_date": item["change_date"],
+        });
+    }
+    grouped.into_values().collect()
+}
+
+/// Add the export file and line each record's package was read from, the
+/// new export's unless the package is only in the old one.
+fn add_provenance(categories: &mut Map<String, Value>, old_pkg: &PackageMap, new_pkg: &PackageMap) {
+    for item in categories.values_mut().filter_map(Value::as_array_mut).flatten() {
+        let Some(gtin) = item["gtin"].as_str() else { continue };
+        let source = new_pkg.get(gtin).or_else(|| old_pkg.get(gtin)).and_then(|info| info.source.clone());
+        if let (Some(source), Value::Object(fields)) = (source, item) {
+            fields.insert("source_file".into(), source.file.into());
+            fields.insert("source_line".into(), source.line.into());
+        }
+    }
+}
+
+/// The category names `category_key` accepts, for error messages.
+pub const CATEGORY_NAMES: &str = "new, del, sl_entry, sl_entry_delete, limitation_changed, name, ingredient_changes, \
+                                  retail_up, retail_down, exfactory_up, exfactory_down, price_<type>_up/_down";
+
+/// Like `category_key`, but also accepting the `price_<type>_up`/`_down`
+/// categories of further price types.
+pub fn output_key(cat: &str) -> Option<String> {
+    let other_price = cat.starts_with("price_") && (cat.ends_with("_up") || cat.ends_with("_down"));
+    category_key(cat).map(str::to_string).or_else(|| other_price.then(|| cat.to_string()))
+}
+
+/// Map a category name (or one of its aliases) to the output JSON key.
+pub fn category_key(cat: &str) -> Option<&'static str> {
+    match cat {
+        "new" => Some("new"),
+        "del" | "delete" => Some("del"),
+        "sl_entry" => Some("sl_entry"),
+        "sl_entry_delete" => Some("sl_entry_delete"),
+        "limitation_changed" | "limitation_changes" | "limitation" => Some("limitation_changed"),
+        "name" | "name_base" | "productname" => Some("name_base"),
+        "ingredient_changes" | "ingredients" | "composition" => Some("ingredient_changes"),
+        "retail_up" | "price_rise_retail" => Some("retail_up"),
+        "retail_down" | "price_cut_retail" => Some("retail_down"),
+        "exfactory_up" | "price_rise_exfactory" => Some("exfactory_up"),
+        "exfactory_down" | "price_cut_exfactory" => Some("exfactory_down"),
+        _ => None,
+    }
+}
+
+/// The DD.MM.YYYY date in the name of an export (`sl_foph_DD.MM.YYYY.ndjson`),
+/// or else its modification date.
+pub(crate) fn extract_date_from_filename(path: &str) -> String {
+    let stem = Path::new(path.trim_end_matches(".gz"))
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("");
+    for part in stem.split('_') {
+        let segments: Vec<&str> = part.split('.').collect();
+        if segments.len() == 3
+            && segments[0].len() <= 2
+            && segments[1].len() <= 2
+            && segments[2].len() == 4
+            && segments.iter().all(|s| s.chars().all(|c| c.is_ascii_digit()))
+        {
+            return part.to_string();
+        }
+    }
+    crate::get_file_mod_date(path)
+}
+
+/// Parse a DD.MM.YYYY string to a DateTuple.
+pub(crate) fn date_str_to_tuple(s: &str) -> DateTuple {
+    let parts: Vec<&str> = s.split('.').collect();
+    if parts.len() == 3 {
+        let d: i32 = parts[0].parse().unwrap_or(1);
+        let m: i32 = parts[1].parse().unwrap_or(1);
+        let y: i32 = parts[2].parse().unwrap_or(2026);
+        (y, m, d)
+    } else {
+        (2026, 1, 6)
+    }
+}
+
+/// Drop the price changes below `min_price_delta` / `min_pct_change`, one
+/// after the other so a change is kept only if it passes both. Returns how
+/// many were dropped, `None` if neither threshold is set.
+fn apply_price_thresholds(diff: &mut FophDiff, opts: &FophDiffOptions) -> Option<usize> {
+    let mut suppressed = None;
+    if let Some(min_delta) = opts.min_price_delta {
+        *suppressed.get_or_insert(0) += diff.drop_minor_price_changes(min_delta);
+    }
+    if let Some(min_pct) = opts.min_pct_change {
+        *suppressed.get_or_insert(0) += diff.drop_small_pct_changes(min_pct);
+    }
+    suppressed
+}
 
-    output.insert("new".into(), Value::Array(new_packages));
-    output.insert("del".into(), Value::Array(package_deletions));
-    output.insert("sl_entry".into(), Value::Array(sl_entry_additions));
-    output.insert("sl_entry_delete".into(), Value::Array(sl_entry_deletions));
-    output.insert("name_base".into(), Value::Array(name_changes));
-    output.insert("retail_up".into(), Value::Array(retail_up));
-    output.insert("retail_down".into(), Value::Array(retail_down));
-    output.insert("exfactory_up".into(), Value::Array(exfactory_up));
-    output.insert("exfactory_down".into(), Value::Array(exfactory_down));
+/// Diff the FOPH exports `old_files` against `new_files`. Each side may be
+/// split across several files, which are read as one dataset; the dates are
+/// taken from the first file of each side.
+#[instrument(skip_all)]
+pub fn run_foph_diff(old_files: &[String], new_files: &[String], opts: &FophDiffOptions, out_dir: &Path) -> Result<()> {
+    let only = opts.output.only_keys(output_key, CATEGORY_NAMES)?;
+    // Checked against every category, not just those --only keeps, and before
+    // anything is loaded
+    let filter_key = opts.filter.as_deref()
+        .map(|cat| output_key(cat).ok_or_else(|| PharmaError::UnknownCategory {
+            name: cat.to_string(),
+            valid: CATEGORY_NAMES.to_string(),
+        }))
+        .transpose()?;
+    let old_date_str = extract_date_from_filename(&old_files[0]);
+    let new_date_str = extract_date_from_filename(&new_files[0]);
+    let old_fallback_dt = date_str_to_tuple(&old_date_str);
+    let new_fallback_dt = date_str_to_tuple(&new_date_str);
+    info!("Old date: {}", old_date_str);
+    info!("New date: {}", new_date_str);
+
+    let old_override = opts.old_date.or(opts.as_of);
+    let new_override = opts.new_date.or(opts.as_of);
+    if let Some((y, m, d)) = old_override {
+        info!("Old file: evaluating prices as of {}.{}.{} (overridden on the command line).", d, m, y);
+    }
+    if let Some((y, m, d)) = new_override {
+        info!("New file: evaluating prices as of {}.{}.{} (overridden on the command line).", d, m, y);
+    }
 
-    fs::create_dir_all("ndjson")?;
+    let mut stats = TimingStats::default();
+    let parse_start = Instant::now();
 
-    let output_filename = format!("ndjson/diff_{}-{}.json",
+    // Load both sides in parallel
+    let (old_result, new_result) = rayon::join(
+        || -> Result<(FophBundles, DateTuple)> {
+            info!("Loading old file...");
+            let bundles = read_foph_bundle_files(old_files)?;
+            let effective_date = old_override.unwrap_or_else(|| extract_date_from_bundles(&bundles.bundles, old_fallback_dt));
+            Ok((bundles, effective_date))
+        },
+        || -> Result<(FophBundles, DateTuple)> {
+            info!("Loading new file...");
+            let bundles = read_foph_bundle_files(new_files)?;
+            let effective_date = new_override.unwrap_or_else(|| extract_date_from_bundles(&bundles.bundles, new_fallback_dt));
+            Ok((bundles, effective_date))
+        },
+    );
+
+    let (old_bundles, old_effective_date) = old_result?;
+    let (new_bundles, new_effective_date) = new_result?;
+
+    // Process bundles in parallel
+    let prefixes = opts.gtin_prefixes();
+    let ((mut old_pkg, old_rejected), (mut new_pkg, new_rejected)) = rayon::join(
+        || process_bundles_parallel(&old_bundles, &old_effective_date, &prefixes),
+        || process_bundles_parallel(&new_bundles, &new_effective_date, &prefixes),
+    );
+    old_rejected.log("Old file");
+    new_rejected.log("New file");
+
+    info!("Found {} packages (old), {} (new).", old_pkg.len(), new_pkg.len());
+    opts.output.check_shrink(old_pkg.len(), new_pkg.len())?;
+    if let Some(prefix) = &opts.filter_atc {
+        retain_atc_prefix(&mut [&mut old_pkg, &mut new_pkg], prefix, |p| p.atc_code.as_deref());
+    }
+    stats.parse_ms = TimingStats::since(parse_start);
+
+    let diff_start = Instant::now();
+    let mut diff = compute_foph_diff(&old_pkg, &new_pkg);
+    let suppressed = apply_price_thresholds(&mut diff, opts);
+    if let Some(count) = suppressed {
+        info!("Suppressed {} price changes below the minimum change.", count);
+    }
+    if let Some(since) = opts.since {
+        let dropped = diff.retain_since(since, new_effective_date);
+        info!("Dropped {} changes effective before {}-{:02}-{:02}.", dropped, since.0, since.1, since.2);
+    }
+    if let Some(gtins) = &opts.filter_gtin {
+        report_unknown_filter_gtins(gtins, |gtin| old_pkg.contains_key(gtin) || new_pkg.contains_key(gtin));
+        diff.retain_gtins(gtins);
+    }
+    // After the thresholds, so the top N are taken from the changes that pass them
+    let beyond_top = opts.top_by_price_change.map(|n| {
+        let count = diff.keep_top_price_changes(n);
+        info!("Dropped {} price changes outside the top {} per category.", count, n);
+        count
+    });
+    let mut categories = diff.to_json_map()?;
+    if opts.with_provenance {
+        add_provenance(&mut categories, &old_pkg, &new_pkg);
+    }
+    if let Some(only) = &only {
+        categories.retain(|key, _| only.contains(key));
+    }
+    if let Some(order) = opts.output.order {
+        order.sort_categories(&mut categories);
+    }
+    stats.diff_ms = TimingStats::since(diff_start);
+
+    // If a filter is set, just print GTINs for that category and exit
+    if let Some(key) = &filter_key {
+        // A category left out by --only, or a price type without changes, has no records
+        let items = categories.get(key).and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+        let gtins = items.iter().filter_map(|item| item["gtin"].as_str());
+        if opts.output.machine_output {
+            println!("{}", Value::from(gtins.collect::<Vec<_>>()));
+        } else {
+            gtins.for_each(|gtin| println!("{}", gtin));
+        }
+        return Ok(());
+    }
+
+    let dates = format!("{}-{}",
         if old_date_str == "unknown" { "old".to_string() } else { old_date_str },
         if new_date_str == "unknown" { "new".to_string() } else { new_date_str },
     );
+    let stem = format!("diff_{}", dates);
+    let mut report = DiffReport::new(DiffSource::Foph, dates, categories);
+    if let Some(limit) = opts.output.limit {
+        report.limit(limit);
+    }
+    let summary = opts.output.json_summary.then(|| report.summary_counts());
+    if opts.group_by_manufacturer {
+        report.views.insert("by_manufacturer".into(), Value::Object(group_by_manufacturer(&report.categories)));
+    }
+    if opts.group_prices {
+        report.views.insert("price_changes_by_package".into(), Value::Array(group_price_changes(&report.categories)));
+    }
+    if report.is_empty() && !opts.output.report_no_changes {
+        info!("No changes detected");
+        if let Some(summary) = summary {
+            print_summary(summary, None, &[]);
+        }
+        return opts.output.finish(&report, old_files, new_files);
+    }
 
-    let pretty = serde_json::to_string_pretty(&Value::Object(output))?;
-    std::fs::File::create(&output_filename)?.write_all(pretty.as_bytes())?;
+    let ndjson_dir = out_dir.join("ndjson");
+    create_output_dir(&ndjson_dir, opts.output.dry_run)?;
+    let write_start = Instant::now();
+    let mut written = Vec::new();
+    let mut json_file = None;
+    if opts.output.split && !opts.no_json {
+        let files = report.write_split(&ndjson_dir, &opts.output)?;
+        if !opts.output.dry_run {
+            info!(target: OUTPUT_LOG_TARGET, "Diff written to {} ({} files, diff_<category>_{}.json)", ndjson_dir.display(), files.len(), report.dates);
+        }
+        written.extend(files);
+    } else if !opts.no_json {
+        let (output, output_filename) = if opts.group_by_gtin {
+            (report.to_json_by_gtin(), ndjson_dir.join(format!("{}_by_gtin.json", stem)))
+        } else {
+            (report.to_json(), ndjson_dir.join(format!("{}.json", stem)))
+        };
+        let Value::Object(mut output) = output else { unreachable!("DiffReport serializes to an object") };
+        if !opts.output.no_stats {
+            if let Some(count) = suppressed {
+                let stats = output.entry("_stats").or_insert_with(|| Value::Object(Map::new()));
+                stats["suppressed_minor_price_changes"] = count.into();
+            }
+            if let Some(count) = beyond_top {
+                let stats = output.entry("_stats").or_insert_with(|| Value::Object(Map::new()));
+                stats["suppressed_beyond_top_price_changes"] = count.into();
+            }
+            report.insert_totals(&mut output);
+            stats.write_ms = TimingStats::since(write_start);
+            stats.insert_into(&mut output)?;
+        }
+        write_json(&output_filename, &Value::Object(output), opts.output.compact_json, opts.output.dry_run)?;
+        if !opts.output.dry_run {
+            info!(target: OUTPUT_LOG_TARGET, "Diff written to {}", output_filename.display());
+        }
+        written.push(output_filename.clone());
+        json_file = Some(output_filename);
+    }
+    if opts.csv {
+        let files = report.to_csv_dir(&ndjson_dir, opts.output.dry_run)?;
+        if !opts.output.dry_run {
+            info!(target: OUTPUT_LOG_TARGET, "CSV written to {} ({} files, {}_<category>.csv)", ndjson_dir.display(), files.len(), stem);
+        }
+        written.extend(files);
+    }
+    if opts.output.xml {
+        let path = report.write_xml(&ndjson_dir, opts.output.dry_run)?;
+        if !opts.output.dry_run {
+            info!(target: OUTPUT_LOG_TARGET, "XML written to {}", path.display());
+        }
+        written.push(path);
+    }
+    // The categories left by --only, counted before --limit as in `_stats.total_records`
+    let Value::Object(counts) = report.counts() else { unreachable!("counts are an object") };
+    for (key, count) in &counts {
+        info!("  flag {:>2} {:<20} {}", category_flag(key), format!("{}:", key), count);
+    }
+    stats.write_ms = TimingStats::since(write_start);
+    stats.log_summary();
+    if let Some(summary) = summary {
+        print_summary(summary, json_file.as_deref(), &written);
+    }
+    opts.output.finish(&report, old_files, new_files)
+}
 
-    println!("Diff written to {}", output_filename);
-    println!("  flag  1 new:              {}", n_new);
-    println!("  flag 14 del:              {}", n_del);
-    println!("  flag 10 sl_entry:         {}", n_sl_add);
-    println!("  flag  2 sl_entry_delete:  {}", n_sl_del);
-    println!("  flag  3 name_base:        {}", n_name);
-    println!("  flag 13 retail_up:        {}", n_ru);
-    println!("  flag 15 retail_down:      {}", n_rd);
-    println!("  flag 13 exfactory_up:     {}", n_eu);
-    println!("  flag 15 exfactory_down:   {}", n_ed);
+// ─── Diff series ─────────────────────────────────────────────────────────────
+
+/// Diff each FOPH export in `files` (oldest first) against the one before it
+/// and write `ndjson/diff_series_<first date>-<last date>.json`: every pairwise
+/// diff under `diffs`, keyed by its date pair, and a per-GTIN `timeline` of the
+/// price changes across the series. Of `opts`, the price thresholds, `as_of`
+/// and the ATC and GTIN filters apply.
+#[instrument(skip_all)]
+pub fn run_foph_diff_series(files: &[String], opts: &FophDiffOptions, out_dir: &Path) -> Result<()> {
+    let mut stats = TimingStats::default();
+    let parse_start = Instant::now();
+
+    // One file at a time, so only one set of bundles is held in memory
+    let prefixes = opts.gtin_prefixes();
+    let mut snapshots: Vec<(String, PackageMap)> = Vec::with_capacity(files.len());
+    for file in files {
+        let date_str = extract_date_from_filename(file);
+        info!("Loading {} ({})...", file, date_str);
+        let bundles = read_foph_bundles(file)?;
+        let effective_date = opts.as_of.unwrap_or_else(|| extract_date_from_bundles(&bundles.bundles, date_str_to_tuple(&date_str)));
+        let (packages, rejected) = process_bundles_parallel(&bundles, &effective_date, &prefixes);
+        rejected.log(&date_str);
+        snapshots.push((date_str, packages));
+    }
+    if let Some(prefix) = &opts.filter_atc {
+        let mut maps: Vec<&mut PackageMap> = snapshots.iter_mut().map(|(_, packages)| packages).collect();
+        retain_atc_prefix(&mut maps, prefix, |p| p.atc_code.as_deref());
+    }
+    if let Some(gtins) = &opts.filter_gtin {
+        report_unknown_filter_gtins(gtins, |gtin| snapshots.iter().any(|(_, packages)| packages.contains_key(gtin)));
+    }
+    stats.parse_ms = TimingStats::since(parse_start);
+
+    let diff_start = Instant::now();
+    let mut diffs = Map::new();
+    let mut timeline: BTreeMap<String, Value> = BTreeMap::new();
+    let mut suppressed = None;
+    for pair in snapshots.windows(2) {
+        let [(old_date, old_pkg), (new_date, new_pkg)] = pair else { unreachable!("windows of two") };
+        let period = format!("{}-{}", old_date, new_date);
+        let mut diff = compute_foph_diff(old_pkg, new_pkg);
+        if let Some(count) = apply_price_thresholds(&mut diff, opts) {
+            *suppressed.get_or_insert(0) += count;
+        }
+        if let Some(gtins) = &opts.filter_gtin {
+            diff.retain_gtins(gtins);
+        }
+        let categories = diff.to_json_map()?;
+        // Only price change records carry a price type
+        for item in categories.values().filter_map(|v| v.as_array()).flatten() {
+            let (Some(gtin), Some(price_type)) = (item["gtin"].as_str(), item["type"].as_str()) else { continue };
+            let entry = timeline.entry(gtin.to_string())
+                .or_insert_with(|| json!({ "name": item["name"], "changes": [] }));
+            if let Some(changes) = entry["changes"].as_array_mut() {
+                changes.push(json!({
+                    "period": period,
+                    "type": price_type,
+                    "old_price": item["old_price"],
+                    "new_price": item["new_price"],
+                    "difference": item["difference"],
+                    "pct_change": item["pct_change"],
+                    "change_date": item["change_date"],
+                }));
+            }
+        }
+        let total: usize = categories.values().filter_map(|v| v.as_array()).map(|a| a.len()).sum();
+        info!("{}: {} changes", period, total);
+        diffs.insert(period, Value::Object(categories));
+    }
+    if let Some(count) = suppressed {
+        info!("Suppressed {} price changes below the minimum change.", count);
+    }
+    stats.diff_ms = TimingStats::since(diff_start);
+
+    let write_start = Instant::now();
+    let ndjson_dir = out_dir.join("ndjson");
+    create_output_dir(&ndjson_dir, opts.output.dry_run)?;
+    let (first, last) = (&snapshots[0].0, &snapshots[snapshots.len() - 1].0);
+    let output_filename = ndjson_dir.join(format!("diff_series_{}-{}.json", first, last));
 
+    let mut output = Map::new();
+    output.insert("_flag_legend".into(), flag_legend());
+    output.insert("series".into(), snapshots.iter().map(|(date, _)| Value::String(date.clone())).collect());
+    output.insert("diffs".into(), Value::Object(diffs));
+    info!("Timeline: {} GTINs with price changes", timeline.len());
+    output.insert("timeline".into(), Value::Object(timeline.into_iter().collect()));
+    if !opts.output.no_stats {
+        if let Some(count) = suppressed {
+            output.insert("_stats".into(), json!({ "suppressed_minor_price_changes": count }));
+        }
+        stats.write_ms = TimingStats::since(write_start);
+        stats.insert_into(&mut output)?;
+    }
+    write_json(&output_filename, &Value::Object(output), opts.output.compact_json, opts.output.dry_run)?;
+    if !opts.output.dry_run {
+        info!(target: OUTPUT_LOG_TARGET, "Diff series written to {}", output_filename.display());
+    }
+    stats.write_ms = TimingStats::since(write_start);
+    stats.log_summary();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bundle with one SL-listed package and its retail price.
+    fn sl_bundle(gtin: &str, name: &str, retail: f64, change_date: &str) -> Value {
+        json!({
+            "resourceType": "Bundle",
+            "timestamp": "2026-02-01T00:00:00Z",
+            "entry": [
+                {"resource": {
+                    "resourceType": "PackagedProductDefinition",
+                    "id": "p1",
+                    "description": name,
+                    "packaging": {"identifier": [{"system": "urn:oid:2.51.1.1", "value": gtin}]},
+                }},
+                {"resource": {
+                    "resourceType": "RegulatedAuthorization",
+                    "id": "a1",
+                    "type": {"coding": [{"code": "756000002003"}]},
+                    "subject": [{"reference": "PackagedProductDefinition/p1"}],
+                    "extension": [{
+                        "url": "http://fhir.ch/ig/ch-epl/StructureDefinition/productPrice",
+                        "extension": [
+                            {"url": "type", "valueCodeableConcept": {"coding": [{"code": price_types::RETAIL}]}},
+                            {"url": "value", "valueMoney": {"value": retail}},
+                            {"url": "changeDate", "valueDate": change_date},
+                        ],
+                    }],
+                }},
+            ],
+        })
+    }
+
+    /// Add a retail price effective from `change_date` to the authorization of `sl_bundle`.
+    fn add_retail_price(bundle: &mut Value, retail: f64, change_date: &str) {
+        let mut price = bundle["entry"][1]["resource"]["extension"][0].clone();
+        price["extension"][1]["valueMoney"]["value"] = json!(retail);
+        price["extension"][2]["valueDate"] = json!(change_date);
+        bundle["entry"][1]["resource"]["extension"].as_array_mut().unwrap().push(price);
+    }
+
+    /// A scratch directory for one test, emptied first.
+    fn scratch_dir(test: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("pharma2merge-foph-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write `bundles` as an NDJSON export named `sl_foph_<date>.ndjson` to `dir`.
+    fn write_export(dir: &Path, date: &str, bundles: &[Value]) -> String {
+        let path = dir.join(format!("sl_foph_{}.ndjson", date));
+        let lines: Vec<String> = bundles.iter().map(Value::to_string).collect();
+        fs::write(&path, lines.join("\n")).unwrap();
+        path.display().to_string()
+    }
+
+    #[test]
+    fn unknown_category_is_an_error() {
+        let opts = FophDiffOptions { filter: Some("retail_sideways".into()), ..Default::default() };
+        // Rejected before the (missing) files are read
+        let missing = vec!["no_such_file.ndjson".to_string()];
+        let err = run_foph_diff(&missing, &missing, &opts, Path::new(".")).unwrap_err();
+        assert!(matches!(err, PharmaError::UnknownCategory { ref name, .. } if name == "retail_sideways"));
+    }
+
+    #[test]
+    fn category_left_out_by_only_is_not_unknown() {
+        let dir = scratch_dir("category-only");
+        let old = write_export(&dir, "01.01.2026", &[sl_bundle("7680123450017", "ASPIRIN 500", 10.0, "2025-01-01")]);
+        let new = write_export(&dir, "01.02.2026", &[sl_bundle("7680123450017", "ASPIRIN 500", 12.0, "2026-01-15")]);
+        let opts = FophDiffOptions {
+            filter: Some("retail_up".into()),
+            output: OutputOptions { only: Some(vec!["new".into()]), ..Default::default() },
+            ..Default::default()
+        };
+        run_foph_diff(&[old], &[new], &opts, &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn streamed_ndjson_reads_as_many_bundles_as_the_concatenated_fallback() {
+        let dir = scratch_dir("streaming");
+        let bundles: Vec<String> = (0..10_000)
+            .map(|i| sl_bundle("7680123450017", &format!("PRODUCT {}", i), 10.0, "2025-01-01").to_string())
+            .collect();
+        let ndjson = dir.join("lines.ndjson");
+        fs::write(&ndjson, bundles.join("\n")).unwrap();
+        // No line breaks between the objects, so only the fallback finds them
+        let concatenated = dir.join("concatenated.ndjson");
+        fs::write(&concatenated, bundles.concat()).unwrap();
+
+        let streamed = read_foph_bundles(&ndjson.display().to_string()).unwrap();
+        let fallback = read_foph_bundles(&concatenated.display().to_string()).unwrap();
+        assert_eq!(streamed.bundles.len(), 10_000);
+        assert_eq!(fallback.bundles.len(), streamed.bundles.len());
+        assert_eq!(streamed.sources[9_999].line, 10_000);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn as_of_date_picks_the_price_effective_then() {
+        let mut bundle = sl_bundle("7680123450017", "ASPIRIN 500", 10.0, "2025-01-01");
+        add_retail_price(&mut bundle, 12.0, "2026-03-01");
+        let bundles = [bundle];
+        let retail_as_of = |date: DateTuple| process_bundles(&bundles, &date)["7680123450017"].retail_price();
+        assert_eq!(retail_as_of((2026, 2, 1)), 10.0);
+        assert_eq!(retail_as_of((2026, 3, 1)), 12.0);
+        assert_eq!(retail_as_of((2026, 4, 1)), 12.0);
+    }
+
+    #[test]
+    fn as_of_overrides_the_bundle_timestamp_in_the_diff() {
+        let dir = scratch_dir("as-of");
+        let old = [write_export(&dir, "01.01.2026", &[sl_bundle("7680123450017", "ASPIRIN 500", 10.0, "2025-01-01")])];
+        let mut bundle = sl_bundle("7680123450017", "ASPIRIN 500", 10.0, "2025-01-01");
+        add_retail_price(&mut bundle, 12.0, "2026-03-01");
+        let new = [write_export(&dir, "01.02.2026", &[bundle])];
+        let retail_up = |as_of: Option<DateTuple>| {
+            let opts = FophDiffOptions { as_of, output: OutputOptions { no_stats: true, ..Default::default() }, ..Default::default() };
+            run_foph_diff(&old, &new, &opts, &dir).unwrap();
+            let diff_path = dir.join("ndjson").join("diff_01.01.2026-01.02.2026.json");
+            let diff: Value = serde_json::from_slice(&fs::read(&diff_path).unwrap_or_default()).unwrap_or_default();
+            let _ = fs::remove_file(&diff_path);
+            diff["retail_up"].as_array().map_or(0, Vec::len)
+        };
+        // The bundles are dated 2026-02-01, before the new price applies
+        assert_eq!(retail_up(None), 0);
+        assert_eq!(retail_up(Some((2026, 3, 15))), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pct_change_is_rounded_to_one_decimal_with_the_sign_of_the_difference() {
+        let date = (2026, 2, 1);
+        let old = process_bundles(&[sl_bundle("7680123450017", "ASPIRIN 500", 3.0, "2025-01-01")], &date);
+        let up = process_bundles(&[sl_bundle("7680123450017", "ASPIRIN 500", 3.1, "2026-01-15")], &date);
+        let down = process_bundles(&[sl_bundle("7680123450017", "ASPIRIN 500", 2.9, "2026-01-15")], &date);
+        assert_eq!(compute_foph_diff(&old, &up).retail_up[0].pct_change, Some(3.3));
+        assert_eq!(compute_foph_diff(&old, &down).retail_down[0].pct_change, Some(-3.3));
+    }
+
+    #[test]
+    fn normalize_name_collapses_whitespace_and_decodes_entities() {
+        assert_eq!(normalize_name("  ASPIRIN  500\n mg "), "ASPIRIN 500 mg");
+        assert_eq!(normalize_name("Co&amp;Co &lt;x&gt; &#233;&#xE9;&nbsp;B"), "Co&Co <x> éé B");
+        assert_eq!(normalize_name("A &unknown; B & C"), "A &unknown; B & C");
+    }
+
+    #[test]
+    fn whitespace_only_rename_is_not_a_name_change() {
+        let date = (2026, 2, 1);
+        let old = process_bundles(&[sl_bundle("7680123450017", "ASPIRIN  500", 10.0, "2025-01-01")], &date);
+        let same = process_bundles(&[sl_bundle("7680123450017", "ASPIRIN 500", 10.0, "2025-01-01")], &date);
+        let renamed = process_bundles(&[sl_bundle("7680123450017", "ASPIRIN 500 mg", 10.0, "2025-01-01")], &date);
+        assert!(compute_foph_diff(&old, &same).name_base.is_empty());
+        let change = &compute_foph_diff(&old, &renamed).name_base[0];
+        assert_eq!((change.old_name.as_str(), change.new_name.as_str()), ("ASPIRIN  500", "ASPIRIN 500 mg"));
+    }
+
+    /// Limit the SL authorization of `sl_bundle` to `start`..=`end`.
+    fn set_validity(bundle: &mut Value, start: &str, end: &str) {
+        bundle["entry"][1]["resource"]["validityPeriod"] = json!({"start": start, "end": end});
+    }
+
+    #[test]
+    fn every_category_has_its_flag() {
+        let mut diff = FophDiff::default();
+        diff.other_prices.insert("price_756002005003_up".into(), Vec::new());
+        for key in diff.to_json_map().unwrap().keys() {
+            assert_ne!(category_flag(key), numeric_flags::NOT_SPECIFIED, "{}", key);
+        }
+        assert_eq!(category_flag("exfactory_down"), numeric_flags::PRICE_CUT);
+        assert_eq!(category_flag("price_756002005003_up"), numeric_flags::PRICE_RISE);
+    }
+
+    #[test]
+    fn package_gtin_counts_each_rejection_reason() {
+        let identifiers = ["768012345001", "7680I2345OO17", "4012345000016", "7680123450018", "7680123450017"];
+        let res = json!({"packaging": {"identifier": identifiers.iter()
+            .map(|value| json!({"system": "urn:oid:2.51.1.1", "value": value}))
+            .collect::<Vec<_>>()}});
+        let mut rejected = GtinRejections::default();
+        let gtin = package_gtin(&res, &[SWISS_GTIN_PREFIX.to_string()], &mut rejected);
+        assert_eq!(gtin.as_deref(), Some("7680123450017"));
+        assert_eq!(rejected, GtinRejections { wrong_length: 1, not_numeric: 1, wrong_prefix: 1, bad_checksum: 1 });
+    }
+
+    #[test]
+    fn lapsed_sl_authorization_keeps_the_package_without_sl_entry() {
+        let date = (2026, 2, 1);
+        let old = process_bundles(&[sl_bundle("7680123450017", "ASPIRIN 500", 10.0, "2025-01-01")], &date);
+        let mut expired = sl_bundle("7680123450017", "ASPIRIN 500", 10.0, "2025-01-01");
+        set_validity(&mut expired, "2020-01-01", "2026-01-15");
+        let new = process_bundles(&[expired], &date);
+
+        let package = &new["7680123450017"];
+        assert!(!package.has_sl_entry);
+        assert!(package.prices.is_empty());
+        let diff = compute_foph_diff(&old, &new);
+        assert_eq!(diff.sl_entry_delete.len(), 1);
+        assert!(diff.del.is_empty() && diff.retail_down.is_empty());
+        // Reactivated: back on the SL, not a new package
+        let diff = compute_foph_diff(&new, &old);
+        assert_eq!(diff.sl_entry.len(), 1);
+        assert!(diff.new.is_empty());
+    }
+
+    #[test]
+    fn sl_authorization_starting_later_is_a_new_sl_entry_once_it_starts() {
+        let mut future = sl_bundle("7680123450017", "ASPIRIN 500", 10.0, "2025-01-01");
+        set_validity(&mut future, "2026-03-01", "2030-12-31");
+        let bundles = [future];
+        let before = process_bundles(&bundles, &(2026, 2, 1));
+        let after = process_bundles(&bundles, &(2026, 3, 1));
+
+        assert!(!before["7680123450017"].has_sl_entry);
+        assert_eq!(after["7680123450017"].retail_price(), 10.0);
+        let diff = compute_foph_diff(&before, &after);
+        assert_eq!(diff.sl_entry.len(), 1);
+        assert!(diff.new.is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::{Local, SecondsFormat};
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::error::{PharmaError, Result};
+use crate::report::{DiffReport, DiffSource};
+use crate::{write_output, OUTPUT_LOG_TARGET};
+
+// ─── Change history ──────────────────────────────────────────────────────────
+
+/// The entries of the history file at `path`; none if it doesn't exist yet.
+fn read_history(path: &Path) -> Result<Vec<Value>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let invalid = |message: String| PharmaError::InvalidHistory { path: path.display().to_string(), message };
+    match serde_json::from_str(&fs::read_to_string(path)?) {
+        Ok(Value::Array(entries)) => Ok(entries),
+        Ok(_) => Err(invalid("not a JSON array".into())),
+        Err(err) => Err(invalid(err.to_string())),
+    }
+}
+
+/// Append one entry for `report` to the history file at `path` (created if
+/// missing): the input files, when it ran, the `diff_hash` and the record
+/// count per category, not the records themselves.
+pub fn append_history(path: &Path, report: &DiffReport, old_files: &[String], new_files: &[String], dry_run: bool) -> Result<()> {
+    let mut entries = read_history(path)?;
+    entries.push(json!({
+        "source": match report.source {
+            DiffSource::Foph => "foph",
+            DiffSource::Swissmedic => "swissmedic",
+        },
+        "old_file": old_files.join(","),
+        "new_file": new_files.join(","),
+        "dates": report.dates,
+        "generated_on": Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+        "diff_hash": report.diff_hash(),
+        "counts": report.counts(),
+    }));
+    let pretty = serde_json::to_string_pretty(&entries)?;
+    write_output(path, pretty.as_bytes(), dry_run)?;
+    if !dry_run {
+        info!(target: OUTPUT_LOG_TARGET, "History entry {} appended to {}", entries.len(), path.display());
+    }
+    Ok(())
+}
+
+/// Print the history file at `path` to stdout, one line per diff: when it
+/// ran, the source and dates, the start of its hash and the non-empty
+/// categories.
+pub fn run_print_history(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Err(PharmaError::InvalidHistory { path: path.display().to_string(), message: "no such file".into() });
+    }
+    for entry in read_history(path)? {
+        let str_field = |key: &str| entry[key].as_str().unwrap_or("").to_string();
+        let counts: Vec<(&String, u64)> = entry["counts"].as_object()
+            .map(|counts| counts.iter().filter_map(|(k, v)| v.as_u64().filter(|n| *n > 0).map(|n| (k, n))).collect())
+            .unwrap_or_default();
+        let total: u64 = counts.iter().map(|(_, n)| n).sum();
+        let listed: Vec<String> = counts.iter().map(|(k, n)| format!("{} {}", k, n)).collect();
+        let hash = str_field("diff_hash");
+        println!("{:<25}  {:<10}  {:<21}  {:<12}  {} changes{}",
+            str_field("generated_on"),
+            str_field("source"),
+            str_field("dates"),
+            &hash[..hash.len().min(12)],
+            total,
+            if listed.is_empty() { String::new() } else { format!(" ({})", listed.join(", ")) },
+        );
+    }
+    Ok(())
+}
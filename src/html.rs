@@ -0,0 +1,778 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::error::Result;
+use crate::report::DiffReport;
+use crate::write_atomic;
+
+// ─── HTML diff output ───────────────────────────────────────────────────────
+
+pub fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Settings for the HTML report.
+#[derive(Clone, Debug, Default)]
+pub struct HtmlOptions {
+    /// Leave out the inline JavaScript (filter boxes, sorting and the
+    /// "show changed only" toggle) for a static page.
+    pub no_js: bool,
+    /// Show at most this many records per table; the summary still counts all.
+    pub limit: Option<usize>,
+}
+
+/// Render the merged diff as a standalone HTML page.
+pub fn render_html_diff(merged: &Value, opts: &HtmlOptions) -> String {
+    let generated_on = merged.get("metadata").and_then(|m| m["generated_on"].as_str()).unwrap_or("unknown");
+    render_page(generated_on, merged.get("price_data"), merged.get("swissmedic_data"), opts)
+}
+
+/// Render a FOPH and a Swissmedic diff, as written by `foph-diff` and
+/// `swissmedic-diff`, as one HTML page, the same as `render_html_diff` does
+/// for their merge.
+pub fn render_html_report(foph: &Value, swissmedic: &Value, generated_on: &str, opts: &HtmlOptions) -> String {
+    render_page(generated_on, Some(foph), Some(swissmedic), opts)
+}
+
+fn render_page(generated_on: &str, price_data: Option<&Value>, sm_data: Option<&Value>, opts: &HtmlOptions) -> String {
+    let mut html = String::with_capacity(64 * 1024);
+    html.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>Pharma Diff Report – ");
+    html.push_str(&html_escape(generated_on));
+    html.push_str("</title>\n<style>\n");
+    html.push_str(&format!(":root {{{}}}\n", LIGHT_PALETTE));
+    // The OS preference applies unless the toggle picked a theme
+    html.push_str(&format!("@media (prefers-color-scheme: dark) {{ :root:not([data-theme=\"light\"]) {{{}}} }}\n", DARK_PALETTE));
+    html.push_str(&format!(":root[data-theme=\"dark\"] {{{}}}\n", DARK_PALETTE));
+    html.push_str(r#"
+body { font-family: -apple-system, BlinkMacSystemFont, "Segoe UI", Helvetica, Arial, sans-serif; margin: 2em; color: var(--fg); background: var(--bg); }
+h1 { border-bottom: 2px solid var(--rule); padding-bottom: .3em; }
+h2 { margin-top: 2em; color: var(--link); }
+h3 { margin-top: 1.5em; }
+table { border-collapse: collapse; width: 100%; margin: .5em 0 1.5em; font-size: 0.92em; }
+th, td { border: 1px solid var(--border); padding: 6px 10px; text-align: left; vertical-align: top; }
+th { background: var(--panel); font-weight: 600; }
+.added { background: var(--added-bg); }
+.deleted { background: var(--deleted-bg); }
+.old { color: var(--worse); text-decoration: line-through; }
+.new { color: var(--better); font-weight: 500; }
+.gtin { font-family: monospace; white-space: nowrap; }
+.summary-table td:last-child { text-align: right; font-weight: 600; }
+.manufacturer-table td + td { text-align: right; }
+.price-up { color: var(--worse); }
+.price-down { color: var(--better); }
+.toc { background: var(--panel); padding: 1em 1.5em; border-radius: 6px; margin-bottom: 2em; }
+.toc a { text-decoration: none; color: var(--link); }
+.toc ul { margin: .3em 0; padding-left: 1.5em; }
+input { color: var(--fg); background: var(--bg); }
+#filter { width: 100%; max-width: 30em; padding: 6px 10px; margin-bottom: 1.5em; font-size: 1em; border: 1px solid var(--border); border-radius: 6px; }
+.table-filter { width: 100%; max-width: 20em; padding: 4px 8px; font-size: .92em; border: 1px solid var(--border); border-radius: 6px; }
+#theme-toggle { float: right; font-size: 1.3em; padding: 2px 8px; cursor: pointer; background: var(--panel); border: 1px solid var(--border); border-radius: 6px; }
+#gtin-search { width: 100%; max-width: 12em; padding: 6px 10px; margin: 0 0 1.5em .5em; font-size: 1em; border: 1px solid var(--border); border-radius: 6px; }
+#gtin-search.not-found { border-color: var(--worse); }
+.limit-note { font-style: italic; }
+details.owner summary { cursor: pointer; font-weight: 600; margin: .4em 0; }
+.cross-ref { text-decoration: none; color: var(--link); }
+tr:target td, tr.highlight td { background: var(--highlight); }
+.filtered, .table-filtered, .changed-only tr.unchanged { display: none; }
+th.sortable { cursor: pointer; user-select: none; }
+th[data-sort="asc"]::after { content: " ▲"; }
+th[data-sort="desc"]::after { content: " ▼"; }
+"#);
+    html.push_str("</style>\n</head>\n<body>\n");
+
+    // Header
+    if !opts.no_js {
+        html.push_str("<button type=\"button\" id=\"theme-toggle\" title=\"Toggle dark mode\">🌙</button>\n");
+    }
+    html.push_str(&format!("<h1>Pharma Diff Report – {}</h1>\n", html_escape(generated_on)));
+    if !opts.no_js {
+        html.push_str("<input type=\"search\" id=\"filter\" placeholder=\"Filter by GTIN or name\" autocomplete=\"off\">\n");
+        html.push_str("<input type=\"search\" id=\"gtin-search\" placeholder=\"Jump to GTIN\" autocomplete=\"off\">\n");
+    }
+
+    let rows = GtinRows::default();
+
+    // Swissmedic tables show the dosage strength number next to the name
+    let strength_cell = |item: &Value| format!("<td>{}</td>", html_escape(item["strength"].as_str().unwrap_or("")));
+
+    // Helper: render a simple added/deleted table
+    let render_add_del_table = |html: &mut String, items: &[Value], css_class: &str, show_prices: bool, show_strength: bool| {
+        html.push_str("<table class=\"data\">\n<tr><th>GTIN</th><th>Name</th>");
+        if show_strength {
+            html.push_str("<th>Strength</th>");
+        }
+        if show_prices {
+            html.push_str("<th>Retail</th><th>Ex-factory</th>");
+        }
+        html.push_str("</tr>\n");
+        for item in items {
+            let gtin = item["gtin"].as_str().unwrap_or("");
+            let name = item["name"].as_str().unwrap_or("");
+            html.push_str(&format!("{}<td>{}</td>", rows.open(gtin, Some(css_class)), html_escape(name)));
+            if show_strength {
+                html.push_str(&strength_cell(item));
+            }
+            if show_prices {
+                let retail = item.get("retail_price").and_then(|v| v.as_f64());
+                let exf = item.get("exfactory_price").and_then(|v| v.as_f64());
+                html.push_str(&format!("<td>{}</td><td>{}</td>",
+                    retail.map(|p| format!("{:.2}", p)).unwrap_or_default(),
+                    exf.map(|p| format!("{:.2}", p)).unwrap_or_default(),
+                ));
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</table>\n");
+    };
+
+    // Helper: render a field-change table (old→new)
+    let render_change_table = |html: &mut String, items: &[Value], old_key: &str, new_key: &str, show_strength: bool| {
+        html.push_str("<table class=\"data\">\n<tr><th>GTIN</th><th>Name</th>");
+        html.push_str(if show_strength { "<th>Strength</th>" } else { "" });
+        html.push_str("<th>Old</th><th>New</th></tr>\n");
+        for item in items {
+            let gtin = item["gtin"].as_str().unwrap_or("");
+            let name = item["name"].as_str()
+                .or_else(|| item["product_name"].as_str())
+                .unwrap_or("");
+            let old_v = item[old_key].as_str().unwrap_or("");
+            let new_v = item[new_key].as_str().unwrap_or("");
+            html.push_str(&format!(
+                "{}<td>{}</td>{}<td class=\"old\">{}</td><td class=\"new\">{}</td></tr>\n",
+                rows.open(gtin, None), html_escape(name),
+                if show_strength { strength_cell(item) } else { String::new() },
+                html_escape(old_v), html_escape(new_v)
+            ));
+        }
+        html.push_str("</table>\n");
+    };
+
+    // Helper: render price-change table
+    let render_price_table = |html: &mut String, items: &[Value], direction: &str| {
+        let css = if direction == "up" { "price-up" } else { "price-down" };
+        html.push_str("<table class=\"data\">\n<tr><th>GTIN</th><th>Name</th><th>Type</th><th>Old Price</th><th>New Price</th><th>Difference</th><th>Change</th></tr>\n");
+        for item in items {
+            let gtin = item["gtin"].as_str().unwrap_or("");
+            let name = item["name"].as_str().unwrap_or("");
+            let ptype = item["type"].as_str().unwrap_or("");
+            let old_p = item["old_price"].as_f64();
+            let new_p = item["new_price"].as_f64();
+            let diff = item["difference"].as_f64().unwrap_or(0.0);
+            // Same sign as the difference; empty when the old price was zero
+            let pct = item["pct_change"].as_f64();
+            html.push_str(&format!(
+                "{}<td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"{}\">{:+.2}</td><td class=\"{}\">{}</td></tr>\n",
+                rows.open(gtin, None), html_escape(name), ptype,
+                old_p.map(|p| format!("{:.2}", p)).unwrap_or_default(),
+                new_p.map(|p| format!("{:.2}", p)).unwrap_or_default(),
+                css, diff,
+                css, pct.map(|p| format!("{:+.1} %", p)).unwrap_or_default(),
+            ));
+        }
+        html.push_str("</table>\n");
+    };
+
+    // ── Table of Contents ────────────────────────────────────────────────
+    html.push_str("<div class=\"toc\"><strong>Contents</strong>\n<ul>\n");
+    html.push_str("<li><a href=\"#summary\">Summary</a></li>\n");
+    html.push_str("<li><a href=\"#manufacturer-stats\">Changes by manufacturer</a></li>\n");
+    html.push_str("<li><a href=\"#foph\">FOPH / BAG Price Data</a></li>\n");
+    html.push_str("<li><a href=\"#swissmedic\">Swissmedic Data</a></li>\n");
+    if sm_data.is_some() {
+        html.push_str("<li><a href=\"#swissmedic-by-owner\">Swissmedic changes by company</a></li>\n");
+    }
+    html.push_str("</ul></div>\n");
+
+    // ── Summary table ────────────────────────────────────────────────────
+    // A diff written with --limit keeps its full counts in `_stats.total_records`
+    let count = |data: Option<&Value>, key: &str| -> usize {
+        let Some(data) = data else { return 0 };
+        data["_stats"]["total_records"][key].as_u64().map(|n| n as usize)
+            .or_else(|| data.get(key).and_then(|v| v.as_array()).map(|a| a.len()))
+            .unwrap_or(0)
+    };
+    let limit = opts.limit.unwrap_or(usize::MAX);
+    let truncated = |data: Option<&Value>| data.and_then(|d| d["_truncated"].as_object()).is_some_and(|t| !t.is_empty());
+
+    html.push_str("<h2 id=\"summary\">Summary</h2>\n");
+    // Without JavaScript there is no toggle, so empty categories are left out
+    let summary_class = if opts.no_js { "summary-table" } else { "summary-table changed-only" };
+    html.push_str(&format!("<table class=\"{}\">\n<tr><th>Flag</th><th>Category</th><th>Source</th><th>Count</th></tr>\n", summary_class));
+
+    let summary_rows: Vec<(&str, &str, &str, &str, usize)> = vec![
+        ("1",  "New packages",         "FOPH",       "foph-new",                        count(price_data, "new")),
+        ("14", "Deleted packages",     "FOPH",       "foph-del",                        count(price_data, "del")),
+        ("10", "SL entry additions",   "FOPH",       "foph-sl_entry",                   count(price_data, "sl_entry")),
+        ("2",  "SL entry deletions",   "FOPH",       "foph-sl_entry_delete",            count(price_data, "sl_entry_delete")),
+        ("18", "Limitation changes",   "FOPH",       "foph-limitation_changed",         count(price_data, "limitation_changed")),
+        ("3",  "Name changes",         "FOPH",       "foph-name_base",                  count(price_data, "name_base")),
+        ("6",  "Ingredient changes",   "FOPH",       "foph-ingredient_changes",         count(price_data, "ingredient_changes")),
+        ("13", "Retail price ↑",       "FOPH",       "foph-retail_up",                  count(price_data, "retail_up")),
+        ("15", "Retail price ↓",       "FOPH",       "foph-retail_down",                count(price_data, "retail_down")),
+        ("13", "Ex-factory price ↑",   "FOPH",       "foph-exfactory_up",               count(price_data, "exfactory_up")),
+        ("15", "Ex-factory price ↓",   "FOPH",       "foph-exfactory_down",             count(price_data, "exfactory_down")),
+        ("1",  "Added packs",          "Swissmedic", "swissmedic-added",                count(sm_data, "added")),
+        ("14", "Deleted packs",        "Swissmedic", "swissmedic-deleted",              count(sm_data, "deleted")),
+        ("3",  "Name",                 "Swissmedic", "swissmedic-Name",                 count(sm_data, "Name")),
+        ("19", "Strength",             "Swissmedic", "swissmedic-Strength",             count(sm_data, "Strength")),
+        ("4",  "Owner",                "Swissmedic", "swissmedic-Owner",                count(sm_data, "Owner")),
+        ("9",  "Date",                 "Swissmedic", "swissmedic-Date",                 count(sm_data, "Date")),
+        ("8",  "Handelsform",          "Swissmedic", "swissmedic-Handelsform",          count(sm_data, "Handelsform")),
+        ("5",  "Swissmedic Categorie", "Swissmedic", "swissmedic-Swissmedic_Categorie", count(sm_data, "Swissmedic_Categorie")),
+        ("6",  "Active Agent",         "Swissmedic", "swissmedic-Active_Agent",         count(sm_data, "Active_Agent")),
+        ("6",  "Composition",          "Swissmedic", "swissmedic-Composition",          count(sm_data, "Composition")),
+        ("7",  "Indikation",           "Swissmedic", "swissmedic-Indikation",           count(sm_data, "Indikation")),
+        ("17", "ATC",                  "Swissmedic", "swissmedic-ATC",                  count(sm_data, "ATC")),
+    ];
+
+    for (flag, cat, source, section, n) in &summary_rows {
+        if *n > 0 {
+            html.push_str(&format!("<tr><td>{}</td><td>{}</td><td>{}</td><td><a href=\"#{}\">{}</a></td></tr>\n",
+                flag, cat, source, section, n));
+        } else if !opts.no_js {
+            html.push_str(&format!("<tr class=\"unchanged\"><td>{}</td><td>{}</td><td>{}</td><td>0</td></tr>\n",
+                flag, cat, source));
+        }
+    }
+    html.push_str("</table>\n");
+    if let Some(limit) = opts.limit {
+        html.push_str(&format!("<p class=\"limit-note\">Each table shows at most {} records; the counts above include all.</p>\n", limit));
+    } else if truncated(price_data) || truncated(sm_data) {
+        html.push_str("<p class=\"limit-note\">The diffs were written with <code>--limit</code>, so some tables are incomplete; \
+                       the counts above include all records.</p>\n");
+    }
+
+    // ── Manufacturer statistics ──────────────────────────────────────────
+    html.push_str("<h2 id=\"manufacturer-stats\">Changes by manufacturer</h2>\n");
+    render_manufacturer_stats(&mut html, price_data, sm_data);
+
+    // ── FOPH Price Data ──────────────────────────────────────────────────
+    html.push_str("<h2 id=\"foph\">FOPH / BAG Price Data</h2>\n");
+
+    if let Some(pd) = price_data {
+        let arr = |key: &str| -> &[Value] {
+            let items = pd.get(key).and_then(|v| v.as_array()).map(|a| a.as_slice()).unwrap_or(&[]);
+            &items[..items.len().min(limit)]
+        };
+
+        let new_pkgs = arr("new");
+        if !new_pkgs.is_empty() {
+            html.push_str(&format!("<h3 id=\"foph-new\">New packages ({})</h3>\n", new_pkgs.len()));
+            render_add_del_table(&mut html, new_pkgs, "added", true, false);
+        }
+
+        let del_pkgs = arr("del");
+        if !del_pkgs.is_empty() {
+            html.push_str(&format!("<h3 id=\"foph-del\">Deleted packages ({})</h3>\n", del_pkgs.len()));
+            render_add_del_table(&mut html, del_pkgs, "deleted", true, false);
+        }
+
+        let sl_add = arr("sl_entry");
+        if !sl_add.is_empty() {
+            html.push_str(&format!("<h3 id=\"foph-sl_entry\">SL entry additions ({})</h3>\n", sl_add.len()));
+            render_add_del_table(&mut html, sl_add, "added", false, false);
+        }
+
+        let sl_del = arr("sl_entry_delete");
+        if !sl_del.is_empty() {
+            html.push_str(&format!("<h3 id=\"foph-sl_entry_delete\">SL entry deletions ({})</h3>\n", sl_del.len()));
+            render_add_del_table(&mut html, sl_del, "deleted", false, false);
+        }
+
+        let limitations = arr("limitation_changed");
+        if !limitations.is_empty() {
+            html.push_str(&format!("<h3 id=\"foph-limitation_changed\">Limitation changes ({})</h3>\n", limitations.len()));
+            html.push_str("<table class=\"data\">\n<tr><th>GTIN</th><th>Name</th><th>Old</th><th>New</th></tr>\n");
+            let describe = |has: &Value, points: &Value, text: &Value| -> String {
+                let status = match (has.as_bool().unwrap_or(false), points.as_u64().unwrap_or(0)) {
+                    (false, _) => "none".to_string(),
+                    (true, 0) => "limited".to_string(),
+                    (true, p) => format!("limited, {} points", p),
+                };
+                match text.as_str() {
+                    Some(text) => format!("{}<br>{}", status, html_escape(text).replace('\n', "<br>")),
+                    None => status,
+                }
+            };
+            for item in limitations {
+                html.push_str(&format!(
+                    "{}<td>{}</td><td class=\"old\">{}</td><td class=\"new\">{}</td></tr>\n",
+                    rows.open(item["gtin"].as_str().unwrap_or(""), None),
+                    html_escape(item["name"].as_str().unwrap_or("")),
+                    describe(&item["old_has_limitation"], &item["old_points"], &item["old_text"]),
+                    describe(&item["new_has_limitation"], &item["new_points"], &item["new_text"]),
+                ));
+            }
+            html.push_str("</table>\n");
+        }
+
+        let names = arr("name_base");
+        if !names.is_empty() {
+            html.push_str(&format!("<h3 id=\"foph-name_base\">Name changes ({})</h3>\n", names.len()));
+            render_change_table(&mut html, names, "old_name", "new_name", false);
+        }
+
+        let ingredients = arr("ingredient_changes");
+        if !ingredients.is_empty() {
+            html.push_str(&format!("<h3 id=\"foph-ingredient_changes\">Ingredient changes ({})</h3>\n", ingredients.len()));
+            html.push_str("<table class=\"data\">\n<tr><th>GTIN</th><th>Name</th><th>Old</th><th>New</th></tr>\n");
+            let describe = |list: &Value| -> String {
+                list.as_array().into_iter().flatten()
+                    .map(|i| match i["strength"].as_str() {
+                        Some(strength) => format!("{} {}", html_escape(i["name"].as_str().unwrap_or("")), html_escape(strength)),
+                        None => html_escape(i["name"].as_str().unwrap_or("")),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("<br>")
+            };
+            for item in ingredients {
+                html.push_str(&format!(
+                    "{}<td>{}</td><td class=\"old\">{}</td><td class=\"new\">{}</td></tr>\n",
+                    rows.open(item["gtin"].as_str().unwrap_or(""), None),
+                    html_escape(item["name"].as_str().unwrap_or("")),
+                    describe(&item["old_ingredients"]),
+                    describe(&item["new_ingredients"]),
+                ));
+            }
+            html.push_str("</table>\n");
+        }
+
+        let ru = arr("retail_up");
+        if !ru.is_empty() {
+            html.push_str(&format!("<h3 id=\"foph-retail_up\">Retail price increases ({})</h3>\n", ru.len()));
+            render_price_table(&mut html, ru, "up");
+        }
+
+        let rd = arr("retail_down");
+        if !rd.is_empty() {
+            html.push_str(&format!("<h3 id=\"foph-retail_down\">Retail price decreases ({})</h3>\n", rd.len()));
+            render_price_table(&mut html, rd, "down");
+        }
+
+        let eu = arr("exfactory_up");
+        if !eu.is_empty() {
+            html.push_str(&format!("<h3 id=\"foph-exfactory_up\">Ex-factory price increases ({})</h3>\n", eu.len()));
+            render_price_table(&mut html, eu, "up");
+        }
+
+        let ed = arr("exfactory_down");
+        if !ed.is_empty() {
+            html.push_str(&format!("<h3 id=\"foph-exfactory_down\">Ex-factory price decreases ({})</h3>\n", ed.len()));
+            render_price_table(&mut html, ed, "down");
+        }
+
+        let by_package = arr("price_changes_by_package");
+        if !by_package.is_empty() {
+            html.push_str(&format!("<h3 id=\"foph-price_changes_by_package\">Price changes by package ({})</h3>\n", by_package.len()));
+            render_grouped_price_table(&mut html, &rows, by_package);
+        }
+    }
+
+    // ── Swissmedic Data ──────────────────────────────────────────────────
+    html.push_str("<h2 id=\"swissmedic\">Swissmedic Data</h2>\n");
+
+    if let Some(sm) = sm_data {
+        let arr = |key: &str| -> &[Value] {
+            let items = sm.get(key).and_then(|v| v.as_array()).map(|a| a.as_slice()).unwrap_or(&[]);
+            &items[..items.len().min(limit)]
+        };
+
+        let added = arr("added");
+        if !added.is_empty() {
+            html.push_str(&format!("<h3 id=\"swissmedic-added\">Added packs ({})</h3>\n", added.len()));
+            render_add_del_table(&mut html, added, "added", false, true);
+        }
+
+        let deleted = arr("deleted");
+        if !deleted.is_empty() {
+            html.push_str(&format!("<h3 id=\"swissmedic-deleted\">Deleted packs ({})</h3>\n", deleted.len()));
+            render_add_del_table(&mut html, deleted, "deleted", false, true);
+        }
+
+        for (key, title) in SWISSMEDIC_FIELD_TABLES {
+            let items = arr(key);
+            if !items.is_empty() {
+                html.push_str(&format!("<h3 id=\"swissmedic-{}\">{} changes ({})</h3>\n", key, title, items.len()));
+                render_change_table(&mut html, items, "old", "new", true);
+            }
+        }
+
+        html.push_str("<h3 id=\"swissmedic-by-owner\">Changes by company</h3>\n");
+        render_owner_sections(&mut html, sm, limit);
+    }
+
+    if !opts.no_js {
+        html.push_str(TABLE_SCRIPT);
+        html.push_str(THEME_SCRIPT);
+    }
+    html.push_str("\n</body>\n</html>\n");
+    rows.link(&html)
+}
+
+/// Start of the cross-reference placeholders written by `GtinRows::open`.
+const CROSS_REF_MARK: &str = "\u{1}cross-ref:";
+const CROSS_REF_END: char = '\u{2}';
+
+/// Anchors of the data rows: the first row of a GTIN gets `id="gtin-<gtin>"`,
+/// its further rows `gtin-<gtin>-2`, `-3`, …
+#[derive(Default)]
+struct GtinRows {
+    seen: RefCell<HashMap<String, usize>>,
+}
+
+impl GtinRows {
+    fn id(gtin: &str, occurrence: usize) -> String {
+        match occurrence {
+            1 => format!("gtin-{}", gtin),
+            n => format!("gtin-{}-{}", gtin, n),
+        }
+    }
+
+    /// `<tr>` with the row's anchor and the GTIN cell. The cell ends in a
+    /// placeholder for the link to the GTIN's next row, which `link` fills in
+    /// once it is known how many rows the GTIN has.
+    fn open(&self, gtin: &str, css_class: Option<&str>) -> String {
+        let mut seen = self.seen.borrow_mut();
+        let occurrence = seen.entry(gtin.to_string()).or_insert(0);
+        *occurrence += 1;
+        format!("<tr id=\"{}\"{}><td class=\"gtin\">{}{}{}:{}{}</td>",
+            html_escape(&Self::id(gtin, *occurrence)),
+            css_class.map(|c| format!(" class=\"{}\"", c)).unwrap_or_default(),
+            html_escape(gtin), CROSS_REF_MARK, *occurrence, gtin, CROSS_REF_END)
+    }
+
+    /// Replace the placeholders of `html`: a GTIN with several rows links
+    /// each to the next one (the last back to the first), the others get none.
+    fn link(&self, html: &str) -> String {
+        let seen = self.seen.borrow();
+        let mut out = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(start) = rest.find(CROSS_REF_MARK) {
+            out.push_str(&rest[..start]);
+            let tail = &rest[start + CROSS_REF_MARK.len()..];
+            let Some(end) = tail.find(CROSS_REF_END) else {
+                rest = &rest[start..];
+                break;
+            };
+            if let Some((occurrence, gtin)) = tail[..end].split_once(':') {
+                let total = seen.get(gtin).copied().unwrap_or(0);
+                let occurrence: usize = occurrence.parse().unwrap_or(0);
+                if total > 1 {
+                    out.push_str(&format!(
+                        " <a class=\"cross-ref\" href=\"#{}\" title=\"Row {} of {} of this GTIN\">⇄</a>",
+                        html_escape(&Self::id(gtin, occurrence % total + 1)), occurrence % total + 1, total));
+                }
+            }
+            rest = &tail[end + CROSS_REF_END.len_utf8()..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+/// Columns of the manufacturer statistics.
+const MANUFACTURER_COLUMNS: [&str; 5] = ["New", "Deleted", "Name changes", "Price increases", "Price decreases"];
+
+/// Which `MANUFACTURER_COLUMNS` entry the records of a FOPH or Swissmedic
+/// category count towards, if any.
+fn manufacturer_column(key: &str) -> Option<usize> {
+    match key {
+        "new" | "added" => Some(0),
+        "del" | "deleted" => Some(1),
+        "name_base" | "Name" => Some(2),
+        _ if key.ends_with("_up") => Some(3),
+        _ if key.ends_with("_down") => Some(4),
+        _ => None,
+    }
+}
+
+/// Number of packages per manufacturer (FOPH `manufacturer`, Swissmedic
+/// `owner`) that are new, deleted, renamed or changed in price. A package
+/// with several price types changed counts once.
+fn render_manufacturer_stats(html: &mut String, price_data: Option<&Value>, sm_data: Option<&Value>) {
+    let mut stats: BTreeMap<&str, [BTreeSet<&str>; 5]> = BTreeMap::new();
+    for (data, name_key) in [(price_data, "manufacturer"), (sm_data, "owner")] {
+        let Some(categories) = data.and_then(|d| d.as_object()) else { continue };
+        for (key, items) in categories {
+            let (Some(column), Some(items)) = (manufacturer_column(key), items.as_array()) else { continue };
+            for item in items {
+                let manufacturer = item[name_key].as_str().filter(|m| !m.is_empty()).unwrap_or("unknown");
+                let gtin = item["gtin"].as_str().unwrap_or("");
+                stats.entry(manufacturer).or_default()[column].insert(gtin);
+            }
+        }
+    }
+    if stats.is_empty() {
+        html.push_str("<p>No new, deleted, renamed or repriced packages.</p>\n");
+        return;
+    }
+
+    html.push_str("<table class=\"manufacturer-table\">\n<tr><th>Manufacturer</th>");
+    for column in MANUFACTURER_COLUMNS {
+        html.push_str(&format!("<th>{}</th>", column));
+    }
+    html.push_str("</tr>\n");
+    for (manufacturer, counts) in &stats {
+        html.push_str(&format!("<tr><td>{}</td>", html_escape(manufacturer)));
+        for gtins in counts {
+            html.push_str(&format!("<td>{}</td>", gtins.len()));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+}
+
+/// Swissmedic field-change categories with their table titles.
+const SWISSMEDIC_FIELD_TABLES: [(&str, &str); 10] = [
+    ("Name", "Name"),
+    ("Strength", "Strength"),
+    ("Owner", "Owner"),
+    ("Date", "Date"),
+    ("Handelsform", "Handelsform"),
+    ("Swissmedic_Categorie", "Swissmedic Categorie"),
+    ("Active_Agent", "Active Agent"),
+    ("Composition", "Composition"),
+    ("Indikation", "Indikation"),
+    ("ATC", "ATC"),
+];
+
+/// The Swissmedic changes grouped by `owner`, one collapsible section per
+/// company in name order, its records in category order. The GTIN links to
+/// the record's row in the category tables above.
+fn render_owner_sections(html: &mut String, sm: &Value, limit: usize) {
+    let mut by_owner: BTreeMap<&str, Vec<(&str, &Value)>> = BTreeMap::new();
+    let categories = [("added", "Added"), ("deleted", "Deleted")].into_iter().chain(SWISSMEDIC_FIELD_TABLES);
+    for (key, title) in categories {
+        let items = sm.get(key).and_then(|v| v.as_array()).map(|a| a.as_slice()).unwrap_or(&[]);
+        for item in &items[..items.len().min(limit)] {
+            let owner = item["owner"].as_str().filter(|o| !o.is_empty()).unwrap_or("unknown");
+            by_owner.entry(owner).or_default().push((title, item));
+        }
+    }
+    if by_owner.is_empty() {
+        html.push_str("<p>No Swissmedic changes.</p>\n");
+        return;
+    }
+
+    for (owner, records) in &by_owner {
+        html.push_str(&format!("<details class=\"owner\"><summary>{} ({})</summary>\n", html_escape(owner), records.len()));
+        html.push_str("<table class=\"data\">\n<tr><th>GTIN</th><th>Name</th><th>Strength</th><th>Change</th><th>Old</th><th>New</th></tr>\n");
+        for (title, item) in records {
+            let gtin = item["gtin"].as_str().unwrap_or("");
+            let name = item["product_name"].as_str().or_else(|| item["name"].as_str()).unwrap_or("");
+            html.push_str(&format!(
+                "<tr><td class=\"gtin\"><a href=\"#{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td><td class=\"old\">{}</td><td class=\"new\">{}</td></tr>\n",
+                html_escape(&GtinRows::id(gtin, 1)), html_escape(gtin), html_escape(name),
+                html_escape(item["strength"].as_str().unwrap_or("")), title,
+                html_escape(item["old"].as_str().unwrap_or("")), html_escape(item["new"].as_str().unwrap_or("")),
+            ));
+        }
+        html.push_str("</table>\n</details>\n");
+    }
+}
+
+/// One row per package of `price_changes_by_package`, one column per price
+/// type (retail and ex-factory first) showing old → new and the change.
+fn render_grouped_price_table(html: &mut String, rows: &GtinRows, items: &[Value]) {
+    let mut types: Vec<&str> = vec!["retail", "exfactory"];
+    for item in items {
+        for price_type in item["prices"].as_object().into_iter().flat_map(|p| p.keys()) {
+            if !types.contains(&price_type.as_str()) {
+                types.push(price_type);
+            }
+        }
+    }
+    types[2..].sort_unstable();
+
+    html.push_str("<table class=\"data\">\n<tr><th>GTIN</th><th>Name</th>");
+    for price_type in &types {
+        let label = match *price_type {
+            "retail" => "Retail",
+            "exfactory" => "Ex-factory",
+            other => other,
+        };
+        html.push_str(&format!("<th>{}</th>", html_escape(label)));
+    }
+    html.push_str("</tr>\n");
+    for item in items {
+        html.push_str(&format!("{}<td>{}</td>",
+            rows.open(item["gtin"].as_str().unwrap_or(""), None), html_escape(item["name"].as_str().unwrap_or(""))));
+        for price_type in &types {
+            let change = &item["prices"][*price_type];
+            if change.is_null() {
+                html.push_str("<td></td>");
+                continue;
+            }
+            let diff = change["difference"].as_f64().unwrap_or(0.0);
+            let css = if diff >= 0.0 { "price-up" } else { "price-down" };
+            let price = |key: &str| change[key].as_f64().map(|p| format!("{:.2}", p)).unwrap_or_default();
+            html.push_str(&format!("<td>{} → {}<br><span class=\"{}\">{:+.2}{}</span></td>",
+                price("old_price"), price("new_price"), css, diff,
+                change["pct_change"].as_f64().map(|p| format!(" ({:+.1} %)", p)).unwrap_or_default(),
+            ));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+}
+
+/// Colours of the light theme as CSS custom properties.
+const LIGHT_PALETTE: &str = "
+  --fg: #24292e; --bg: #fff; --rule: #e1e4e8; --border: #d1d5da; --panel: #f6f8fa; --link: #0366d6;
+  --added-bg: #e6ffec; --deleted-bg: #ffeef0; --worse: #b31d28; --better: #22863a; --highlight: #fff5b1;
+  color-scheme: light;
+";
+
+/// The dark theme, with added/deleted and price up/down kept red and green
+/// but lightened (or, for row backgrounds, darkened) to stay readable.
+const DARK_PALETTE: &str = "
+  --fg: #c9d1d9; --bg: #0d1117; --rule: #30363d; --border: #3d444d; --panel: #161b22; --link: #58a6ff;
+  --added-bg: #12361f; --deleted-bg: #42191d; --worse: #ff7b72; --better: #56d364; --highlight: #5a4a0f;
+  color-scheme: dark;
+";
+
+/// Inline script for the interactive parts of the report, inline so reports
+/// opened from the filesystem work offline:
+/// - the `#filter` box hides data rows whose GTIN and name don't contain the
+///   query, and a table together with its `<h3>` when no row is left;
+/// - a search box above every data table hides rows not containing its query
+///   in any cell;
+/// - clicking a header sorts by that column, numerically where both cells
+///   start with a number, and again in reverse;
+/// - `#gtin-search` scrolls to the first row of the entered GTIN and
+///   highlights all of its rows;
+/// - a checkbox above the summary shows or hides the categories without changes.
+const TABLE_SCRIPT: &str = r#"
+<script>
+(function () {
+  var tables = document.querySelectorAll('table.data');
+
+  tables.forEach(function (table) {
+    var box = document.createElement('input');
+    box.type = 'search';
+    box.className = 'table-filter';
+    box.placeholder = 'Filter this table';
+    box.autocomplete = 'off';
+    table.parentNode.insertBefore(box, table);
+    box.addEventListener('input', function () {
+      var query = box.value.trim().toLowerCase();
+      table.querySelectorAll('tr').forEach(function (row) {
+        if (!row.querySelector('td')) return;
+        row.classList.toggle('table-filtered', row.textContent.toLowerCase().indexOf(query) === -1);
+      });
+    });
+  });
+
+  var input = document.getElementById('filter');
+  input.addEventListener('input', function () {
+    var query = input.value.trim().toLowerCase();
+    tables.forEach(function (table) {
+      var shown = 0;
+      table.querySelectorAll('tr').forEach(function (row) {
+        var gtin = row.querySelector('td.gtin');
+        if (!gtin) return;
+        var name = gtin.nextElementSibling;
+        var text = (gtin.textContent + ' ' + (name ? name.textContent : '')).toLowerCase();
+        var match = text.indexOf(query) !== -1;
+        row.classList.toggle('filtered', !match);
+        if (match) shown++;
+      });
+      var empty = query !== '' && shown === 0;
+      table.classList.toggle('filtered', empty);
+      var previous = table.previousElementSibling;
+      if (previous && previous.classList.contains('table-filter')) {
+        previous.classList.toggle('filtered', empty);
+        previous = previous.previousElementSibling;
+      }
+      if (previous && previous.tagName === 'H3') previous.classList.toggle('filtered', empty);
+    });
+  });
+
+  function compareCells(a, b) {
+    var x = parseFloat(a), y = parseFloat(b);
+    if (!isNaN(x) && !isNaN(y) && x !== y) return x - y;
+    return a.localeCompare(b, undefined, { numeric: true });
+  }
+
+  document.querySelectorAll('table.data, table.summary-table, table.manufacturer-table').forEach(function (table) {
+    var header = table.querySelector('tr');
+    header.querySelectorAll('th').forEach(function (th, column) {
+      th.classList.add('sortable');
+      th.addEventListener('click', function () {
+        var direction = th.getAttribute('data-sort') === 'asc' ? 'desc' : 'asc';
+        header.querySelectorAll('th').forEach(function (other) { other.removeAttribute('data-sort'); });
+        th.setAttribute('data-sort', direction);
+        var rows = Array.prototype.slice.call(table.querySelectorAll('tr')).filter(function (row) {
+          return row !== header;
+        });
+        rows.sort(function (a, b) {
+          var order = compareCells(a.cells[column].textContent.trim(), b.cells[column].textContent.trim());
+          return direction === 'asc' ? order : -order;
+        });
+        rows.forEach(function (row) { row.parentNode.appendChild(row); });
+      });
+    });
+  });
+
+  var search = document.getElementById('gtin-search');
+  search.addEventListener('change', function () {
+    var gtin = search.value.trim();
+    document.querySelectorAll('tr.highlight').forEach(function (row) { row.classList.remove('highlight'); });
+    var matches = gtin === '' ? [] : Array.prototype.filter.call(document.querySelectorAll('tr[id]'), function (row) {
+      return row.id === 'gtin-' + gtin || row.id.indexOf('gtin-' + gtin + '-') === 0;
+    });
+    search.classList.toggle('not-found', gtin !== '' && matches.length === 0);
+    matches.forEach(function (row) { row.classList.add('highlight'); });
+    if (matches.length > 0) matches[0].scrollIntoView({ block: 'center' });
+  });
+
+  var summary = document.querySelector('table.summary-table');
+  var label = document.createElement('label');
+  var toggle = document.createElement('input');
+  toggle.type = 'checkbox';
+  toggle.checked = true;
+  label.appendChild(toggle);
+  label.appendChild(document.createTextNode(' Show changed only'));
+  summary.parentNode.insertBefore(label, summary);
+  toggle.addEventListener('change', function () {
+    summary.classList.toggle('changed-only', toggle.checked);
+  });
+})();
+</script>
+"#;
+
+/// Inline script for `#theme-toggle`: switches to the theme not currently
+/// shown, overriding `prefers-color-scheme`, and shows the icon of the other one.
+const THEME_SCRIPT: &str = r#"
+<script>
+(function () {
+  var root = document.documentElement;
+  var button = document.getElementById('theme-toggle');
+  var prefersDark = window.matchMedia('(prefers-color-scheme: dark)');
+  function current() {
+    return root.getAttribute('data-theme') || (prefersDark.matches ? 'dark' : 'light');
+  }
+  function update() {
+    button.textContent = current() === 'dark' ? '☀️' : '🌙';
+  }
+  button.addEventListener('click', function () {
+    root.setAttribute('data-theme', current() === 'dark' ? 'light' : 'dark');
+    update();
+  });
+  prefersDark.addEventListener('change', update);
+  update();
+})();
+</script>
+"#;
+
+/// Render a single diff as HTML and write it to `html_path`.
+pub fn generate_html_diff(report: &DiffReport, opts: &HtmlOptions, html_path: &Path) -> Result<()> {
+    write_atomic(html_path, report.to_html(opts).as_bytes())
+}
@@ -0,0 +1,183 @@
+use std::path::Path;
+
+use serde_json::{Map, Value};
+use tracing::{info, instrument};
+
+use crate::error::{PharmaError, Result};
+use crate::foph_diff::{numeric_flags, pct_change};
+use crate::merge::{diff_kind, read_diff_json, DiffKind};
+use crate::report::diff_hash;
+use crate::{create_output_dir, write_json, OutputOptions, OUTPUT_LOG_TARGET};
+
+// ─── Invert ──────────────────────────────────────────────────────────────────
+
+/// Categories that trade places; `*_up` and `*_down` price categories do as well.
+const CATEGORY_PAIRS: &[(&str, &str)] = &[("new", "del"), ("sl_entry", "sl_entry_delete"), ("added", "deleted")];
+
+/// Flags that trade places.
+const FLAG_PAIRS: &[(u8, u8)] = &[
+    (numeric_flags::NEW, numeric_flags::DELETE),
+    (numeric_flags::SL_ENTRY, numeric_flags::SL_ENTRY_DELETE),
+    (numeric_flags::PRICE_RISE, numeric_flags::PRICE_CUT),
+];
+
+/// The category `key` becomes in the inverted diff, if it changes.
+fn inverse_category(key: &str) -> Option<String> {
+    if let Some((a, b)) = CATEGORY_PAIRS.iter().find(|(a, b)| key == *a || key == *b) {
+        return Some(if key == *a { b } else { a }.to_string());
+    }
+    key.strip_suffix("_up").map(|base| format!("{}_down", base))
+        .or_else(|| key.strip_suffix("_down").map(|base| format!("{}_up", base)))
+}
+
+fn inverse_flag(flag: &Value) -> Value {
+    let Some(code) = flag.as_u64() else { return flag.clone() };
+    FLAG_PAIRS.iter()
+        .find_map(|&(a, b)| match code {
+            c if c == u64::from(a) => Some(Value::from(b)),
+            c if c == u64::from(b) => Some(Value::from(a)),
+            _ => None,
+        })
+        .unwrap_or_else(|| flag.clone())
+}
+
+/// Invert one record (and the records nested in it, such as the `prices` of
+/// `price_changes_by_package`): `old`/`new` and every `old_*`/`new_*` pair
+/// are swapped, flags and a `category` traded, `difference` negated and
+/// `pct_change` recomputed from the swapped prices.
+fn invert_value(value: &Value) -> Value {
+    let fields = match value {
+        Value::Object(fields) => fields,
+        Value::Array(items) => return Value::Array(items.iter().map(invert_value).collect()),
+        scalar => return scalar.clone(),
+    };
+    let mut inverted = Map::new();
+    for (key, value) in fields {
+        let counterpart = match key.as_str() {
+            "old" => Some("new".to_string()),
+            "new" => Some("old".to_string()),
+            _ => key.strip_prefix("old_").map(|rest| format!("new_{}", rest))
+                .or_else(|| key.strip_prefix("new_").map(|rest| format!("old_{}", rest))),
+        };
+        let (key, value) = match (key.as_str(), counterpart) {
+            (_, Some(other)) if fields.contains_key(&other) => (other, invert_value(value)),
+            ("flags", _) => (key.clone(), value.as_array().map_or(value.clone(), |f| f.iter().map(inverse_flag).collect())),
+            ("category", _) => {
+                let category = value.as_str().and_then(inverse_category);
+                (key.clone(), category.map_or_else(|| value.clone(), Value::String))
+            }
+            ("difference", _) => (key.clone(), value.as_f64().map_or(value.clone(), |d| Value::from(-d))),
+            _ => (key.clone(), invert_value(value)),
+        };
+        inverted.insert(key, value);
+    }
+    if inverted.contains_key("pct_change") {
+        let difference = inverted.get("difference").and_then(Value::as_f64);
+        let old_price = inverted.get("old_price").and_then(Value::as_f64).filter(|p| *p > 0.0);
+        let pct = difference.zip(old_price).map(|(diff, old)| pct_change(diff, old));
+        inverted.insert("pct_change".into(), pct.map_or(Value::Null, Value::from));
+    }
+    Value::Object(inverted)
+}
+
+/// Rename the category keys of a metadata object such as `_truncated` or
+/// `_stats.total_records`.
+fn rename_categories(value: &Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(fields.iter()
+            .map(|(key, value)| (inverse_category(key).unwrap_or_else(|| key.clone()), rename_categories(value)))
+            .collect()),
+        other => other.clone(),
+    }
+}
+
+/// The diff that undoes `diff`: additions become deletions, rises become
+/// cuts and old and new values trade places, so inverting twice gives the
+/// original back. The flag legend is kept and `_diff_hash` recomputed.
+pub fn invert_diff(diff: &Map<String, Value>) -> Map<String, Value> {
+    let mut inverted = Map::new();
+    for (key, value) in diff {
+        let (key, value) = match key.as_str() {
+            "_diff_hash" => continue,
+            "_flag_legend" => (key.clone(), value.clone()),
+            _ if key.starts_with('_') => (key.clone(), rename_categories(value)),
+            _ => (inverse_category(key).unwrap_or_else(|| key.clone()), invert_value(value)),
+        };
+        inverted.insert(key, value);
+    }
+    if diff.contains_key("_diff_hash") {
+        let hash = diff_hash(&inverted);
+        inverted.insert("_diff_hash".into(), Value::String(hash));
+    }
+    inverted
+}
+
+/// Invert the FOPH or Swissmedic diff in `file` and write it next to the
+/// diffs of its kind, as `diff_<new date>-<old date>.json` (or
+/// `<name>_inverted.json` when the file name carries no dates).
+#[instrument(skip_all, fields(file = file))]
+pub fn run_invert(file: &str, opts: &OutputOptions, out_dir: &Path) -> Result<()> {
+    let value = read_diff_json(file)?;
+    let dir = match diff_kind(&value) {
+        DiffKind::Foph => out_dir.join("ndjson"),
+        DiffKind::Swissmedic => out_dir.join("csv"),
+        DiffKind::Unknown => return Err(PharmaError::InvalidDiff {
+            file: file.to_string(),
+            message: "none of the FOPH or Swissmedic diff keys (new, del, added, deleted, ...)".into(),
+        }),
+    };
+    let Value::Object(diff) = value else { unreachable!("only an object has diff keys") };
+    let stem = Path::new(file).file_stem().and_then(|s| s.to_str()).unwrap_or("diff");
+    let name = match stem.strip_prefix("diff_").and_then(|dates| dates.split_once('-')) {
+        // Keeps a suffix such as `_by_gtin`
+        Some((old, rest)) => {
+            let (new, suffix) = rest.split_at(rest.find('_').unwrap_or(rest.len()));
+            format!("diff_{}-{}{}.json", new, old, suffix)
+        }
+        None => format!("{}_inverted.json", stem),
+    };
+    create_output_dir(&dir, opts.dry_run)?;
+    let path = dir.join(name);
+    let inverted = invert_diff(&diff);
+    write_json(&path, &Value::Object(inverted), opts.compact_json, opts.dry_run)?;
+    if !opts.dry_run {
+        info!(target: OUTPUT_LOG_TARGET, "Inverted diff written to {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn inverting_twice_gives_the_original() {
+        let Value::Object(mut diff) = json!({
+            "_flag_legend": {"1": "new", "14": "delete"},
+            "_truncated": {"retail_up": true},
+            "new": [{"gtin": "7680123450017", "name": "ASPIRIN 500", "flags": [1]}],
+            "del": [],
+            "sl_entry_delete": [{"gtin": "7680654320018", "name": "PONSTAN", "flags": [2]}],
+            "retail_up": [{
+                "gtin": "7680543210017", "name": "DAFALGAN", "flags": [11, 13],
+                "old_price": 10.0, "new_price": 12.0, "difference": 2.0, "pct_change": 20.0,
+            }],
+            "retail_down": [],
+        }) else { unreachable!() };
+        let hash = diff_hash(&diff);
+        diff.insert("_diff_hash".into(), Value::String(hash));
+
+        let inverted = invert_diff(&diff);
+        assert_eq!(inverted["del"][0]["flags"], json!([14]));
+        assert_eq!(inverted["sl_entry"][0]["flags"], json!([10]));
+        let cut = &inverted["retail_down"][0];
+        assert_eq!((&cut["old_price"], &cut["new_price"], &cut["difference"]), (&json!(12.0), &json!(10.0), &json!(-2.0)));
+        assert_eq!(cut["pct_change"], -16.7);
+        assert_eq!(cut["flags"], json!([11, 15]));
+        assert_eq!(inverted["_truncated"], json!({"retail_down": true}));
+        assert_ne!(inverted["_diff_hash"], diff["_diff_hash"]);
+
+        assert_eq!(invert_diff(&inverted), diff);
+    }
+}
@@ -0,0 +1,487 @@
+//! Diff and merge Swiss pharmaceutical registration data from Swissmedic and
+//! the FOPH Spezialitätenliste (SL).
+//!
+//! The binary in `main.rs` is a thin CLI over the functions exported here.
+
+pub mod check;
+pub mod config;
+pub mod db;
+pub mod download;
+pub mod error;
+pub mod fetch;
+pub mod foph_diff;
+pub mod history;
+pub mod html;
+pub mod invert;
+pub mod merge;
+pub mod orphans;
+pub mod progress;
+pub mod report;
+pub mod swissmedic_diff;
+pub mod xml;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use chrono::Datelike;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use tracing::{info, warn};
+
+pub use error::{PharmaError, Result};
+pub use check::{check_bundles, run_check, FophCheck};
+pub use config::Config;
+pub use db::PackageDatabase;
+pub use download::{
+    build_client, download_url, download_url_with_retry, run_download, xlsx_sheets_to_csv_bytes, xlsx_to_csv,
+    xlsx_to_csv_bytes, DownloadOptions, DownloadedFiles, SheetSelection,
+};
+pub use fetch::{
+    find_previous_snapshot, latest_snapshots, run_fetch_and_diff, FetchAndDiffOptions, FOPH_SNAPSHOTS, SWISSMEDIC_SNAPSHOTS,
+};
+pub use foph_diff::{
+    compute_foph_diff, group_by_gtin, group_by_manufacturer, group_price_changes, normalize_name, package_gtin, process_bundles,
+    process_bundles_with, run_foph_diff, run_foph_diff_series, DateTuple, FophDiff, FophDiffOptions, GtinRejections,
+    Ingredient, PackageInfo, PackageMap, Provenance, SWISS_GTIN_PREFIX,
+};
+pub use history::{append_history, run_print_history};
+pub use html::{generate_html_diff, render_html_diff, render_html_report, HtmlOptions};
+pub use invert::{invert_diff, run_invert};
+pub use merge::{run_html_report, run_merge, validate_merge_inputs, HtmlReportOptions, MergeOptions};
+pub use orphans::run_orphans;
+pub use report::{assert_diff_hash, diff_hash, DiffReport, DiffSource, RecordOrder, SortKey};
+pub use swissmedic_diff::{
+    build_gtin, calculate_gtin_checksum, gtin_check_digit_ok, is_xlsx, load_swissmedic_csv, load_swissmedic_xlsx,
+    packless_key, run_swissmedic_diff, whitespace_only_change, ColumnLayout, ColumnMap, ColumnRef, InvalidGtinWarning,
+    LoadOptions, SkippedRow, SwissmedicCsv, SwissmedicDiffOptions, SwissmedicEntry, SwissmedicMap,
+};
+pub use xml::{render_xml, xml_escape};
+
+// ─── JSON sanitizer ──────────────────────────────────────────────────────────
+
+/// Make JSON with raw control characters inside strings parseable: tabs and
+/// line breaks are escaped, other control characters dropped. Valid escapes
+/// such as `\u0000` are kept as they are; a backslash that starts none is
+/// escaped itself.
+pub fn sanitize_json_string(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut in_string = false;
+    let mut chars = input.char_indices();
+
+    while let Some((i, ch)) = chars.next() {
+        if !in_string {
+            if ch == '"' { in_string = true; }
+            output.push(ch);
+            continue;
+        }
+        match ch {
+            '\\' => match escape_len(&input[i + 1..]) {
+                0 => output.push_str("\\\\"),
+                len => {
+                    output.push_str(&input[i..=i + len]);
+                    // The escape is ASCII, so its length in bytes is its length in chars
+                    chars.nth(len - 1);
+                }
+            },
+            '"'  => { output.push(ch); in_string = false; }
+            '\t' => output.push_str("\\t"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\x00'..='\x1F' => {}
+            _ => output.push(ch),
+        }
+    }
+    output
+}
+
+/// Length of the JSON escape sequence at the start of `rest`, the text after
+/// a backslash; 0 if it isn't one.
+fn escape_len(rest: &str) -> usize {
+    let bytes = rest.as_bytes();
+    match bytes.first() {
+        Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => 1,
+        Some(b'u') if bytes.len() >= 5 && bytes[1..5].iter().all(u8::is_ascii_hexdigit) => 5,
+        _ => 0,
+    }
+}
+
+// ─── CSV helper ──────────────────────────────────────────────────────────────
+
+pub fn csv_escape(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') || field.contains('\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// ─── Shared helpers ──────────────────────────────────────────────────────────
+
+pub fn get_file_mod_date(filename: &str) -> String {
+    fs::metadata(filename).ok()
+        .and_then(|m| m.modified().ok())
+        .map(|t| {
+            let dt: chrono::DateTime<chrono::Local> = t.into();
+            format!("{:02}.{:02}.{}", dt.day(), dt.month(), dt.year())
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Keep only the packages whose ATC code starts with `prefix` (ignoring case) in
+/// any of the snapshots, so a package that moved out of the class is still diffed.
+pub fn retain_atc_prefix<T>(snapshots: &mut [&mut BTreeMap<String, T>], prefix: &str, atc: impl Fn(&T) -> Option<&str>) {
+    let prefix = prefix.to_ascii_uppercase();
+    let matching: BTreeSet<String> = snapshots.iter()
+        .flat_map(|snapshot| snapshot.iter())
+        .filter(|(_, item)| atc(item).is_some_and(|code| code.to_ascii_uppercase().starts_with(&prefix)))
+        .map(|(key, _)| key.clone())
+        .collect();
+    for snapshot in snapshots.iter_mut() {
+        snapshot.retain(|key, _| matching.contains(key));
+    }
+    info!("ATC filter {}: {} packages match", prefix, matching.len());
+}
+
+/// Read a GTIN list for `--filter-gtin`: a JSON array, or plain text with one
+/// GTIN per line (blank lines and `#` comments are ignored).
+pub fn load_gtin_filter(path: &Path) -> Result<BTreeSet<String>> {
+    let content = fs::read_to_string(path)?;
+    if content.trim_start().starts_with('[') {
+        let items: Vec<Value> = serde_json::from_str(&content)?;
+        return Ok(items.iter()
+            .filter_map(|v| match v {
+                Value::String(s) => Some(s.trim().to_string()),
+                Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            })
+            .collect());
+    }
+    Ok(content.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Warn about filter GTINs that are in neither snapshot.
+pub fn report_unknown_filter_gtins(filter: &BTreeSet<String>, known: impl Fn(&str) -> bool) {
+    let unknown = filter.iter().filter(|gtin| !known(gtin)).count();
+    info!("GTIN filter: {} GTINs", filter.len());
+    if unknown > 0 {
+        warn!("GTIN filter: {} of {} GTINs are in neither file", unknown, filter.len());
+    }
+}
+
+// ─── Timing ──────────────────────────────────────────────────────────────────
+
+/// Wall-clock milliseconds per phase, written as `_stats` into the JSON output.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TimingStats {
+    pub download_ms: u64,
+    pub parse_ms: u64,
+    pub diff_ms: u64,
+    /// Output written so far; the JSON file carrying `_stats` is only
+    /// included in the summary logged at the end.
+    pub write_ms: u64,
+}
+
+impl TimingStats {
+    /// Milliseconds elapsed since `start`.
+    pub fn since(start: Instant) -> u64 {
+        start.elapsed().as_millis() as u64
+    }
+
+    /// Add the timings to `output` as `_stats`, merging them into an existing
+    /// `_stats` object (e.g. the category counts of `--group-by-gtin`).
+    pub fn insert_into(&self, output: &mut Map<String, Value>) -> Result<()> {
+        let Value::Object(timings) = serde_json::to_value(self)? else {
+            unreachable!("TimingStats serializes to an object")
+        };
+        match output.get_mut("_stats") {
+            Some(Value::Object(stats)) => stats.extend(timings),
+            _ => {
+                output.insert("_stats".into(), Value::Object(timings));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn log_summary(&self) {
+        info!("Timing:");
+        info!("  download: {:>8} ms", self.download_ms);
+        info!("  parse:    {:>8} ms", self.parse_ms);
+        info!("  diff:     {:>8} ms", self.diff_ms);
+        info!("  write:    {:>8} ms", self.write_ms);
+    }
+}
+
+// ─── Output files ────────────────────────────────────────────────────────────
+
+/// Log target of the messages naming written output files, which `--quiet`
+/// still shows.
+pub const OUTPUT_LOG_TARGET: &str = "pharma2merge::output";
+
+/// Create `dir` and its parents; with `dry_run` only report it.
+pub fn create_output_dir(dir: &Path, dry_run: bool) -> Result<()> {
+    if dry_run {
+        if !dir.is_dir() {
+            info!("[dry-run] Would create directory {}", dir.display());
+        }
+        return Ok(());
+    }
+    fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+/// Write `bytes` to `path`; with `dry_run` only report the size.
+pub fn write_output(path: &Path, bytes: &[u8], dry_run: bool) -> Result<()> {
+    if dry_run {
+        info!("[dry-run] Would write {} bytes to {}", bytes.len(), path.display());
+        return Ok(());
+    }
+    write_atomic(path, bytes)
+}
+
+/// Write `value` to `path` as pretty-printed JSON, or minified with
+/// `compact`, logging how much smaller that is.
+pub fn write_json(path: &Path, value: &Value, compact: bool, dry_run: bool) -> Result<()> {
+    let pretty = serde_json::to_vec_pretty(value)?;
+    if !compact {
+        return write_output(path, &pretty, dry_run);
+    }
+    let minified = serde_json::to_vec(value)?;
+    info!("{}: {} bytes compact, {} saved", path.display(), minified.len(), pretty.len() - minified.len());
+    write_output(path, &minified, dry_run)
+}
+
+/// Write `bytes` to a temporary file next to `path` and rename it into place,
+/// so readers never see a truncated file and a failed write leaves any
+/// existing `path` untouched.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("output");
+    let tmp_path = path.with_file_name(format!(".{}.tmp-{}", file_name, std::process::id()));
+    let result = File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.sync_all()
+        })
+        // std::fs::rename replaces an existing target on Windows as well
+        .and_then(|()| fs::rename(&tmp_path, path));
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+// ─── Diff output ─────────────────────────────────────────────────────────────
+
+/// Numeric flag legend for downstream consumers (matching Ruby NUMERIC_FLAGS).
+pub fn flag_legend() -> Value {
+    json!({
+        "1":  "new",
+        "2":  "sl_entry_delete",
+        "3":  "name_base",
+        "4":  "address",
+        "5":  "ikscat",
+        "6":  "composition",
+        "7":  "indication",
+        "8":  "sequence",
+        "9":  "expiry_date",
+        "10": "sl_entry",
+        "11": "price",
+        "12": "comment",
+        "13": "price_rise",
+        "14": "delete",
+        "15": "price_cut",
+        "16": "not_specified",
+        "17": "atc_code",
+        "18": "limitation",
+        "19": "strength"
+    })
+}
+
+/// Output settings shared by the FOPH and Swissmedic diffs.
+#[derive(Clone, Debug, Default)]
+pub struct OutputOptions {
+    /// Write one JSON file per category instead of a single diff file.
+    pub split: bool,
+    /// With `split`, also write `[]` files for empty categories.
+    pub include_empty: bool,
+    /// Only report what would be written.
+    pub dry_run: bool,
+    /// Leave the `_stats` timing block out of the JSON.
+    pub no_stats: bool,
+    /// Print the record count of every category and the written files as
+    /// one JSON line to stdout (see `print_summary`).
+    pub json_summary: bool,
+    /// Keep stdout valid JSON: a category filter prints its GTINs as one JSON
+    /// array instead of one per line.
+    pub machine_output: bool,
+    /// Fail if the new file has more than this percentage fewer packages than
+    /// the old one, which usually means a truncated download.
+    pub max_shrink_percent: Option<f64>,
+    /// Keep at most this many records per category (see `DiffReport::limit`).
+    pub limit: Option<usize>,
+    /// Only write these categories (names or aliases); `None` writes all.
+    pub only: Option<Vec<String>>,
+    /// Sort the records of each category instead of keeping them by GTIN.
+    pub order: Option<RecordOrder>,
+    /// Also write the diff as `diff_<dates>.xml`.
+    pub xml: bool,
+    /// Write the output of a diff without changes, marked `_no_changes`;
+    /// otherwise such a diff writes nothing.
+    pub report_no_changes: bool,
+    /// Write the JSON minified instead of pretty-printed.
+    pub compact_json: bool,
+    /// Fail with `HashMismatch` unless the diff hashes to this (see `diff_hash`).
+    pub assert_hash: Option<String>,
+    /// Append the counts and hash of every diff to this JSON file (see `append_history`).
+    pub append_history: Option<PathBuf>,
+}
+
+impl OutputOptions {
+    /// The output keys of the `only` categories, resolved by `key_of`, or
+    /// `UnknownCategory` naming `valid` for the first name it doesn't know.
+    pub fn only_keys(&self, key_of: impl Fn(&str) -> Option<String>, valid: &str) -> Result<Option<BTreeSet<String>>> {
+        let Some(names) = &self.only else { return Ok(None) };
+        names.iter()
+            .map(|name| key_of(name).ok_or_else(|| PharmaError::UnknownCategory { name: name.clone(), valid: valid.to_string() }))
+            .collect::<Result<_>>()
+            .map(Some)
+    }
+
+    /// The last step of a diff run: log the hash of `report`, append it to
+    /// the `append_history` file and check it against `assert_hash`.
+    pub fn finish(&self, report: &DiffReport, old_files: &[String], new_files: &[String]) -> Result<()> {
+        let hash = report.diff_hash();
+        info!("Diff hash: {}", hash);
+        if let Some(path) = &self.append_history {
+            append_history(path, report, old_files, new_files, self.dry_run)?;
+        }
+        assert_diff_hash(self.assert_hash.as_deref(), &hash)
+    }
+
+    /// Fail with `SuspiciousShrink` if the new file lost more than
+    /// `max_shrink_percent` of the old file's packages.
+    pub fn check_shrink(&self, old: usize, new: usize) -> Result<()> {
+        let Some(max) = self.max_shrink_percent else { return Ok(()) };
+        if old == 0 || new >= old {
+            return Ok(());
+        }
+        let percent = (old - new) as f64 * 100.0 / old as f64;
+        if percent > max {
+            return Err(PharmaError::SuspiciousShrink { old, new, percent, max });
+        }
+        Ok(())
+    }
+}
+
+/// `{"<category>": <records>, …}`, printed as the last stdout line by `print_summary`.
+pub fn category_counts(categories: &Map<String, Value>) -> Value {
+    categories.iter()
+        .map(|(key, items)| (key.clone(), items.as_array().map_or(0, |a| a.len()).into()))
+        .collect::<Map<String, Value>>()
+        .into()
+}
+
+/// Print the `category_counts` of a diff together with the files it wrote as
+/// one compact JSON line to stdout: `output_file` is the single diff JSON
+/// (`null` with `--split` or without JSON), `output_files` lists every file.
+pub fn print_summary(counts: Value, output_file: Option<&Path>, written: &[PathBuf]) {
+    let Value::Object(mut summary) = counts else { unreachable!("category_counts returns an object") };
+    let path_value = |p: &Path| Value::from(p.display().to_string());
+    summary.insert("output_file".into(), output_file.map_or(Value::Null, path_value));
+    summary.insert("output_files".into(), written.iter().map(|p| path_value(p)).collect());
+    println!("{}", Value::Object(summary));
+}
+
+/// Write every category to `<dir>/diff_<category>_<dates>.json` and the flag
+/// legend once to `diff_legend_<dates>.json`.
+pub fn write_split_output(dir: &Path, dates: &str, categories: &Map<String, Value>, opts: &OutputOptions) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    let mut write = |name: &str, value: &Value| -> Result<()> {
+        let path = dir.join(format!("diff_{}_{}.json", name, dates));
+        write_json(&path, value, opts.compact_json, opts.dry_run)?;
+        written.push(path);
+        Ok(())
+    };
+
+    write("legend", &flag_legend())?;
+    for (key, value) in categories {
+        let empty = value.as_array().is_some_and(|a| a.is_empty());
+        if empty && !opts.include_empty {
+            continue;
+        }
+        write(key, value)?;
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pharma2merge-lib-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn write_atomic_replaces_an_existing_file() {
+        let dir = scratch_dir("atomic-replace");
+        let path = dir.join("diff.json");
+        fs::write(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        // No temporary file is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_write_leaves_the_original_untouched() {
+        let dir = scratch_dir("atomic-failure");
+        let path = dir.join("diff.json");
+        fs::write(&path, b"original").unwrap();
+        // A directory where the temporary file would go makes creating it fail
+        let tmp_path = dir.join(format!(".diff.json.tmp-{}", std::process::id()));
+        fs::create_dir(&tmp_path).unwrap();
+        assert!(write_atomic(&path, b"truncated").is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"original");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flag_legend_names_every_flag_once() {
+        use crate::swissmedic_diff::swissmedic_flags;
+        let legend = flag_legend();
+        assert_eq!(legend["8"], "sequence");
+        assert_eq!(legend[swissmedic_flags::STRENGTH.to_string()], "strength");
+        let mut names: Vec<&str> = legend.as_object().unwrap().values().filter_map(Value::as_str).collect();
+        let count = names.len();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), count);
+    }
+
+    #[test]
+    fn sanitize_keeps_valid_escapes_and_fixes_raw_control_characters() {
+        let sanitize = |input: &str| -> Value {
+            serde_json::from_str(&sanitize_json_string(input)).unwrap_or_else(|e| panic!("{}: {}", input, e))
+        };
+        // A literal \u0000 escape stays an escape, not a doubled backslash
+        assert_eq!(sanitize(r#"{"name": "a\u0000b"}"#)["name"], "a\u{0}b");
+        assert_eq!(sanitize("{\"name\": \"a\tb\nc\"}")["name"], "a\tb\nc");
+        assert_eq!(sanitize("{\"name\": \"a\u{0}b\u{1f}c\"}")["name"], "abc");
+        assert_eq!(sanitize(r#"{"path": "C:\data\x", "quote": "say \"hi\""}"#)["path"], r"C:\data\x");
+        assert_eq!(sanitize(r#"{"quote": "say \"hi\" \u00e9"}"#)["quote"], "say \"hi\" é");
+        // Outside strings nothing changes
+        assert_eq!(sanitize_json_string("{\n\t\"a\": 1}\n"), "{\n\t\"a\": 1}\n");
+    }
+}
@@ -0,0 +1,495 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use chrono::{Datelike, Local};
+use serde_json::{json, Map, Value};
+use tracing::{info, instrument};
+
+use crate::html::{render_html_diff, render_html_report, HtmlOptions};
+use crate::error::{PharmaError, Result};
+use crate::report::{assert_diff_hash, diff_arrays, sha256_hex, RecordOrder};
+use crate::xml::render_xml;
+use crate::{
+    category_counts, create_output_dir, print_summary, sanitize_json_string, write_json, write_output, TimingStats,
+    OUTPUT_LOG_TARGET,
+};
+
+// ─── Merge ───────────────────────────────────────────────────────────────────
+
+fn print_json_stats(label: &str, value: &Value) {
+    if let Some(obj) = value.as_object() {
+        info!("{}:", label);
+        for (key, val) in obj {
+            if let Some(arr) = val.as_array() {
+                info!("  {}: {}", key, arr.len());
+            }
+        }
+    }
+}
+
+/// Settings for `run_merge`.
+#[derive(Clone, Debug, Default)]
+pub struct MergeOptions {
+    /// Also write an HTML report next to the JSON.
+    pub html: bool,
+    /// Also write the merged report as XML next to the JSON.
+    pub xml: bool,
+    /// Write the HTML report without inline JavaScript.
+    pub no_js: bool,
+    /// Show at most this many records per table of the HTML report; the
+    /// merged JSON stays complete.
+    pub limit: Option<usize>,
+    /// Re-sort the records of both diffs in the HTML report; the merged JSON
+    /// and XML keep them as read.
+    pub order: Option<RecordOrder>,
+    /// Add a `cross_reference` array joining both sources by GTIN.
+    pub join: bool,
+    /// Add a `correlated` array of the GTINs changed in both sources.
+    pub correlate: bool,
+    /// Only report what would be written.
+    pub dry_run: bool,
+    /// Skip the check that the inputs look like a FOPH and a Swissmedic diff.
+    pub no_validate: bool,
+    /// Leave the `_stats` timing block out of the JSON.
+    pub no_stats: bool,
+    /// Write the merged JSON minified instead of pretty-printed.
+    pub compact_json: bool,
+    /// Fail with `HashMismatch` unless the merged diffs hash to this.
+    pub assert_hash: Option<String>,
+    /// Print the record counts of both diffs and the written files as one
+    /// JSON line to stdout (see `print_summary`).
+    pub json_summary: bool,
+}
+
+impl MergeOptions {
+    fn html_options(&self) -> HtmlOptions {
+        HtmlOptions { no_js: self.no_js, limit: self.limit }
+    }
+}
+
+// ─── Input validation ────────────────────────────────────────────────────────
+
+const FOPH_KEYS: &[&str] = &[
+    "new", "del", "sl_entry", "sl_entry_delete", "limitation_changed", "name_base",
+    "ingredient_changes", "retail_up", "retail_down", "exfactory_up", "exfactory_down",
+];
+const SWISSMEDIC_KEYS: &[&str] = &[
+    "added", "deleted", "Name", "Strength", "Owner", "Date", "Handelsform",
+    "Swissmedic_Categorie", "Active_Agent", "Composition", "Indikation", "ATC",
+];
+
+/// Which diff a merge input looks like, judged by its top-level keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DiffKind {
+    Foph,
+    Swissmedic,
+    Unknown,
+}
+
+pub(crate) fn diff_kind(value: &Value) -> DiffKind {
+    let count = |keys: &[&str]| keys.iter().filter(|k| value.get(**k).is_some_and(Value::is_array)).count();
+    match (count(FOPH_KEYS), count(SWISSMEDIC_KEYS)) {
+        (0, 0) => DiffKind::Unknown,
+        (foph, swissmedic) if foph >= swissmedic => DiffKind::Foph,
+        _ => DiffKind::Swissmedic,
+    }
+}
+
+/// Check that the price file is a FOPH diff and the Swissmedic file a
+/// Swissmedic diff, with a specific message when they look swapped.
+pub fn validate_merge_inputs(price_path: &str, price: &Value, swissmedic_path: &str, swissmedic: &Value) -> Result<()> {
+    let invalid = |file: &str, message: &str| PharmaError::InvalidMergeInput {
+        file: file.to_string(),
+        message: message.to_string(),
+    };
+    match (diff_kind(price), diff_kind(swissmedic)) {
+        (DiffKind::Foph, DiffKind::Swissmedic) => Ok(()),
+        (DiffKind::Swissmedic, DiffKind::Foph) => Err(invalid(
+            price_path,
+            "price file appears to be a Swissmedic diff and the Swissmedic file a FOPH diff; swap the arguments",
+        )),
+        (DiffKind::Swissmedic, _) => Err(invalid(price_path, "price file appears to be a Swissmedic diff")),
+        (DiffKind::Unknown, _) => Err(invalid(
+            price_path,
+            "price file has none of the FOPH diff keys (new, del, retail_up, ...)",
+        )),
+        (_, DiffKind::Foph) => Err(invalid(swissmedic_path, "Swissmedic file appears to be a FOPH diff")),
+        (_, DiffKind::Unknown) => Err(invalid(
+            swissmedic_path,
+            "Swissmedic file has none of the Swissmedic diff keys (added, deleted, Name, Owner, ...)",
+        )),
+    }
+}
+
+/// Categories, numeric flags and name of one GTIN in a diff JSON.
+#[derive(Default)]
+struct GtinChanges {
+    categories: BTreeSet<String>,
+    flags: BTreeSet<u64>,
+    name: Option<String>,
+}
+
+/// Collect, for every GTIN in a diff JSON, the categories it appears in.
+fn gtin_changes(value: &Value) -> BTreeMap<String, GtinChanges> {
+    let mut map: BTreeMap<String, GtinChanges> = BTreeMap::new();
+    if let Some(obj) = value.as_object() {
+        for (key, val) in obj {
+            for item in val.as_array().map(|a| a.as_slice()).unwrap_or(&[]) {
+                if let Some(gtin) = item.get("gtin").and_then(|v| v.as_str()) {
+                    let changes = map.entry(gtin.to_string()).or_default();
+                    changes.categories.insert(key.clone());
+                    let flags = item.get("flags").and_then(|v| v.as_array()).map(|a| a.as_slice()).unwrap_or(&[]);
+                    changes.flags.extend(flags.iter().filter_map(Value::as_u64));
+                    if changes.name.is_none() {
+                        changes.name = ["name", "product_name"].iter()
+                            .find_map(|k| item.get(*k).and_then(|v| v.as_str()))
+                            .map(str::to_string);
+                    }
+                }
+            }
+        }
+    }
+    map
+}
+
+/// One entry per GTIN found in either diff, listing its categories on each side.
+pub fn cross_reference(price_value: &Value, swissmedic_value: &Value) -> Vec<Value> {
+    let foph = gtin_changes(price_value);
+    let swissmedic = gtin_changes(swissmedic_value);
+    let gtins: BTreeSet<&String> = foph.keys().chain(swissmedic.keys()).collect();
+    let empty = BTreeSet::new();
+    gtins.into_iter()
+        .map(|gtin| json!({
+            "gtin": gtin,
+            "in_foph": foph.get(gtin).map_or(&empty, |c| &c.categories),
+            "in_swissmedic": swissmedic.get(gtin).map_or(&empty, |c| &c.categories),
+        }))
+        .collect()
+}
+
+/// One entry per GTIN changed in both diffs, listing its flags and categories
+/// on each side, e.g. a package dropped from the SL whose Swissmedic category
+/// changed at the same time.
+pub fn correlate(price_value: &Value, swissmedic_value: &Value) -> Vec<Value> {
+    let foph = gtin_changes(price_value);
+    let swissmedic = gtin_changes(swissmedic_value);
+    foph.iter()
+        .filter_map(|(gtin, foph)| {
+            let swissmedic = swissmedic.get(gtin)?;
+            Some(json!({
+                "gtin": gtin,
+                "name": foph.name.as_ref().or(swissmedic.name.as_ref()),
+                "flags": foph.flags.union(&swissmedic.flags).collect::<Vec<_>>(),
+                "foph_flags": foph.flags,
+                "swissmedic_flags": swissmedic.flags,
+                "in_foph": foph.categories,
+                "in_swissmedic": swissmedic.categories,
+            }))
+        })
+        .collect()
+}
+
+/// Read a diff JSON file, dropping control characters that would make it invalid.
+pub(crate) fn read_diff_json(path: &str) -> Result<Value> {
+    let mut content = String::new();
+    File::open(path)?.read_to_string(&mut content)?;
+    Ok(serde_json::from_str(&sanitize_json_string(&content))?)
+}
+
+/// Re-sort the category arrays of `diff` by `order`, if given, for rendering;
+/// the merged JSON keeps the diffs as read.
+fn sort_for_rendering(order: Option<RecordOrder>, diff: &mut Value) {
+    if let (Some(order), Value::Object(categories)) = (order, diff) {
+        order.sort_categories(categories);
+    }
+}
+
+/// `diff/med-drugs-update_<today>.json` in `out_dir`, and today's date.
+fn merge_output_path(out_dir: &Path) -> (PathBuf, String) {
+    let today = Local::now().date_naive();
+    let date_str = format!("{:02}.{:02}.{}", today.day(), today.month(), today.year());
+    (out_dir.join("diff").join(format!("med-drugs-update_{}.json", date_str)), date_str)
+}
+
+#[instrument(skip_all)]
+pub fn run_merge(price_path: &str, swissmedic_path: &str, opts: &MergeOptions, out_dir: &Path) -> Result<()> {
+    let (output_path, date_str) = merge_output_path(out_dir);
+    create_output_dir(&out_dir.join("diff"), opts.dry_run)?;
+
+    let mut stats = TimingStats::default();
+    let parse_start = Instant::now();
+
+    let price_value = read_diff_json(price_path)?;
+    let swissmedic_value = read_diff_json(swissmedic_path)?;
+
+    if !opts.no_validate {
+        validate_merge_inputs(price_path, &price_value, swissmedic_path, &swissmedic_value)?;
+    }
+    stats.parse_ms = TimingStats::since(parse_start);
+    let diff_start = Instant::now();
+
+    // Print stats for both input files
+    print_json_stats(price_path, &price_value);
+    print_json_stats(swissmedic_path, &swissmedic_value);
+
+    // Print flag-coded summary from both sources
+    info!("=== Merged change summary (Ruby NUMERIC_FLAGS) ===");
+    info!("{:<5} {:<25}: Count", "Flag", "Category");
+    info!("--------------------------------------------------");
+
+    let print_category_count = |flag: u8, label: &str, value: &Value, key: &str| {
+        if let Some(arr) = value.get(key).and_then(|v| v.as_array()) {
+            if !arr.is_empty() {
+                info!("{:>3}   {:<25}: {}", flag, label, arr.len());
+            }
+        }
+    };
+
+    // FOPH/BSV price data
+    info!("  Price data ({}):", price_path);
+    print_category_count(1,  "new",              &price_value, "new");
+    print_category_count(14, "del (delete)",     &price_value, "del");
+    print_category_count(10, "sl_entry",         &price_value, "sl_entry");
+    print_category_count(2,  "sl_entry_delete",  &price_value, "sl_entry_delete");
+    print_category_count(18, "limitation_changed", &price_value, "limitation_changed");
+    print_category_count(3,  "name_base",        &price_value, "name_base");
+    print_category_count(6,  "ingredient_changes", &price_value, "ingredient_changes");
+    print_category_count(13, "retail_up",        &price_value, "retail_up");
+    print_category_count(15, "retail_down",      &price_value, "retail_down");
+    print_category_count(13, "exfactory_up",     &price_value, "exfactory_up");
+    print_category_count(15, "exfactory_down",   &price_value, "exfactory_down");
+
+    // Swissmedic data
+    info!("  Swissmedic data ({}):", swissmedic_path);
+    print_category_count(1,  "added (new)",            &swissmedic_value, "added");
+    print_category_count(14, "deleted",                &swissmedic_value, "deleted");
+    print_category_count(3,  "Name (name_base)",       &swissmedic_value, "Name");
+    print_category_count(19, "Strength (strength)",    &swissmedic_value, "Strength");
+    print_category_count(4,  "Owner (address)",        &swissmedic_value, "Owner");
+    print_category_count(5,  "Categorie (ikscat)",     &swissmedic_value, "Swissmedic_Categorie");
+    print_category_count(6,  "Active_Agent (comp)",    &swissmedic_value, "Active_Agent");
+    print_category_count(6,  "Composition",            &swissmedic_value, "Composition");
+    print_category_count(7,  "Indikation",             &swissmedic_value, "Indikation");
+    print_category_count(17, "ATC (atc_code)",         &swissmedic_value, "ATC");
+    print_category_count(8,  "Handelsform (sequence)", &swissmedic_value, "Handelsform");
+    print_category_count(9,  "Date (expiry_date)",     &swissmedic_value, "Date");
+
+    let mut root = Map::new();
+
+    let mut metadata = Map::new();
+    metadata.insert("generated_on".into(), Value::String(date_str.clone()));
+    metadata.insert("price_source_file".into(), Value::String(price_path.to_string()));
+    metadata.insert("swissmedic_source_file".into(), Value::String(swissmedic_path.to_string()));
+    metadata.insert("output_filename".into(), Value::String(output_path.display().to_string()));
+    let diff_hash = merged_diff_hash(&price_value, &swissmedic_value);
+    metadata.insert("diff_hash".into(), Value::String(diff_hash.clone()));
+    metadata.insert("note".into(), Value::String(
+        "Simple file merge: the complete original JSON from both input files is nested unchanged under 'price_data' and 'swissmedic_data'. No processing, grouping, or modification of any objects — 100% preservation of all data.".to_string()
+    ));
+    root.insert("metadata".into(), Value::Object(metadata));
+    if opts.join {
+        let joined = cross_reference(&price_value, &swissmedic_value);
+        let in_both = joined.iter()
+            .filter(|e| e["in_foph"].as_array().is_some_and(|a| !a.is_empty())
+                && e["in_swissmedic"].as_array().is_some_and(|a| !a.is_empty()))
+            .count();
+        info!("Cross-reference: {} GTINs, {} in both sources", joined.len(), in_both);
+        root.insert("cross_reference".into(), Value::Array(joined));
+    }
+    if opts.correlate {
+        let correlated = correlate(&price_value, &swissmedic_value);
+        info!("Correlated: {} GTINs changed in both sources", correlated.len());
+        root.insert("correlated".into(), Value::Array(correlated));
+    }
+    let summary = opts.json_summary.then(|| {
+        let counts = |value: &Value| value.as_object().map_or_else(|| json!({}), category_counts);
+        json!({"price_data": counts(&price_value), "swissmedic_data": counts(&swissmedic_value)})
+    });
+    root.insert("price_data".into(), price_value);
+    root.insert("swissmedic_data".into(), swissmedic_value);
+    stats.diff_ms = TimingStats::since(diff_start);
+
+    let write_start = Instant::now();
+    if !opts.no_stats {
+        stats.insert_into(&mut root)?;
+    }
+    write_json(&output_path, &Value::Object(root.clone()), opts.compact_json, opts.dry_run)?;
+    if !opts.dry_run {
+        info!(target: OUTPUT_LOG_TARGET, "Merge completed → {}", output_path.display());
+    }
+    let mut written = vec![output_path.clone()];
+
+    if opts.xml {
+        let xml_path = output_path.with_extension("xml");
+        write_output(&xml_path, render_xml("merge", &[], &root).as_bytes(), opts.dry_run)?;
+        if !opts.dry_run {
+            info!(target: OUTPUT_LOG_TARGET, "XML output   → {}", xml_path.display());
+        }
+        written.push(xml_path);
+    }
+    if opts.html {
+        let html_path = output_path.with_extension("html");
+        let mut report = Value::Object(root);
+        sort_for_rendering(opts.order, &mut report["price_data"]);
+        sort_for_rendering(opts.order, &mut report["swissmedic_data"]);
+        write_output(&html_path, render_html_diff(&report, &opts.html_options()).as_bytes(), opts.dry_run)?;
+        if !opts.dry_run {
+            info!(target: OUTPUT_LOG_TARGET, "HTML output  → {}", html_path.display());
+        }
+        written.push(html_path);
+    }
+    stats.write_ms = TimingStats::since(write_start);
+    stats.log_summary();
+    info!("Diff hash: {}", diff_hash);
+    if let Some(summary) = summary {
+        print_summary(summary, Some(&output_path), &written);
+    }
+    assert_diff_hash(opts.assert_hash.as_deref(), &diff_hash)
+}
+
+/// Checksum of the change records of both inputs (see `diff_hash`).
+fn merged_diff_hash(price_value: &Value, swissmedic_value: &Value) -> String {
+    let arrays = |value: &Value| Value::Object(value.as_object().map(diff_arrays).unwrap_or_default());
+    sha256_hex(&json!({ "price_data": arrays(price_value), "swissmedic_data": arrays(swissmedic_value) }))
+}
+
+// ─── HTML report ─────────────────────────────────────────────────────────────
+
+/// Settings for `run_html_report`.
+#[derive(Clone, Debug, Default)]
+pub struct HtmlReportOptions {
+    /// Write the report without inline JavaScript.
+    pub no_js: bool,
+    /// Show at most this many records per table.
+    pub limit: Option<usize>,
+    /// Re-sort the records of both diffs in the report.
+    pub order: Option<RecordOrder>,
+    /// Only report what would be written.
+    pub dry_run: bool,
+    /// Skip the check that the inputs look like a FOPH and a Swissmedic diff.
+    pub no_validate: bool,
+}
+
+/// Render the HTML report of a FOPH and a Swissmedic diff JSON straight to
+/// `diff/med-drugs-update_<today>.html`, without writing the merged JSON.
+#[instrument(skip_all)]
+pub fn run_html_report(price_path: &str, swissmedic_path: &str, opts: &HtmlReportOptions, out_dir: &Path) -> Result<()> {
+    let (output_path, date_str) = merge_output_path(out_dir);
+    let html_path = output_path.with_extension("html");
+    create_output_dir(&out_dir.join("diff"), opts.dry_run)?;
+
+    let mut price_value = read_diff_json(price_path)?;
+    let mut swissmedic_value = read_diff_json(swissmedic_path)?;
+    if !opts.no_validate {
+        validate_merge_inputs(price_path, &price_value, swissmedic_path, &swissmedic_value)?;
+    }
+    sort_for_rendering(opts.order, &mut price_value);
+    sort_for_rendering(opts.order, &mut swissmedic_value);
+
+    let html = render_html_report(&price_value, &swissmedic_value, &date_str, &HtmlOptions { no_js: opts.no_js, limit: opts.limit });
+    write_output(&html_path, html.as_bytes(), opts.dry_run)?;
+    if !opts.dry_run {
+        info!(target: OUTPUT_LOG_TARGET, "HTML output  → {}", html_path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn foph_diff() -> Value {
+        json!({
+            "new": [{"gtin": "7680123450017", "name": "ASPIRIN 500", "flags": [1]}],
+            "retail_up": [{"gtin": "7680543210017", "name": "DAFALGAN", "flags": [11, 13], "difference": 1.5}],
+            "del": [],
+        })
+    }
+
+    fn swissmedic_diff() -> Value {
+        json!({
+            "added": [{"gtin": "7680123450017", "name": "Aspirin 500 Bayer AG", "flags": [1]}],
+            "Owner": [{"gtin": "7680654320018", "product_name": "Ponstan", "old": "A", "new": "B", "flags": [4]}],
+            "deleted": [],
+        })
+    }
+
+    #[test]
+    fn cross_reference_joins_a_gtin_new_in_both_sources() {
+        let joined = cross_reference(&foph_diff(), &swissmedic_diff());
+        assert_eq!(joined.len(), 3);
+        let aspirin = joined.iter().find(|e| e["gtin"] == "7680123450017").unwrap();
+        assert_eq!(aspirin["in_foph"], json!(["new"]));
+        assert_eq!(aspirin["in_swissmedic"], json!(["added"]));
+        let dafalgan = joined.iter().find(|e| e["gtin"] == "7680543210017").unwrap();
+        assert_eq!(dafalgan["in_foph"], json!(["retail_up"]));
+        assert_eq!(dafalgan["in_swissmedic"], json!([]));
+    }
+
+    #[test]
+    fn join_leaves_the_merged_diffs_as_read() {
+        let dir = std::env::temp_dir().join(format!("pharma2merge-merge-join-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let price_path = dir.join("foph.json");
+        let swissmedic_path = dir.join("swissmedic.json");
+        std::fs::write(&price_path, foph_diff().to_string()).unwrap();
+        std::fs::write(&swissmedic_path, swissmedic_diff().to_string()).unwrap();
+
+        let opts = MergeOptions { join: true, no_stats: true, ..Default::default() };
+        run_merge(&price_path.display().to_string(), &swissmedic_path.display().to_string(), &opts, &dir).unwrap();
+
+        let (output_path, _) = merge_output_path(&dir);
+        let merged = read_diff_json(&output_path.display().to_string()).unwrap();
+        assert_eq!(merged["price_data"], foph_diff());
+        assert_eq!(merged["swissmedic_data"], swissmedic_diff());
+        assert_eq!(merged["cross_reference"].as_array().unwrap().len(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn swapped_inputs_are_rejected() {
+        let err = validate_merge_inputs("swissmedic.json", &swissmedic_diff(), "foph.json", &foph_diff()).unwrap_err();
+        let PharmaError::InvalidMergeInput { file, message } = err else { panic!("unexpected error {}", err) };
+        assert_eq!(file, "swissmedic.json");
+        assert!(message.contains("swap the arguments"), "{}", message);
+        assert!(validate_merge_inputs("foph.json", &foph_diff(), "swissmedic.json", &swissmedic_diff()).is_ok());
+    }
+
+    #[test]
+    fn unrelated_input_is_rejected() {
+        let err = validate_merge_inputs("foph.json", &foph_diff(), "other.json", &json!({"items": []})).unwrap_err();
+        assert!(matches!(err, PharmaError::InvalidMergeInput { ref file, .. } if file == "other.json"));
+    }
+
+    #[test]
+    fn sort_order_applies_to_the_html_report_only() {
+        let dir = std::env::temp_dir().join(format!("pharma2merge-merge-sort-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut foph = foph_diff();
+        foph["retail_up"] = json!([
+            {"gtin": "7680123450017", "name": "ZYRTEC", "flags": [11, 13], "difference": 0.5},
+            {"gtin": "7680543210017", "name": "BEPANTHEN", "flags": [11, 13], "difference": 1.5},
+        ]);
+        let price_path = dir.join("foph.json");
+        let swissmedic_path = dir.join("swissmedic.json");
+        std::fs::write(&price_path, foph.to_string()).unwrap();
+        std::fs::write(&swissmedic_path, swissmedic_diff().to_string()).unwrap();
+
+        let order = RecordOrder { key: crate::report::SortKey::Name, descending: false };
+        let opts = MergeOptions { html: true, order: Some(order), no_stats: true, ..Default::default() };
+        run_merge(&price_path.display().to_string(), &swissmedic_path.display().to_string(), &opts, &dir).unwrap();
+
+        let (output_path, _) = merge_output_path(&dir);
+        let merged = read_diff_json(&output_path.display().to_string()).unwrap();
+        assert_eq!(merged["price_data"], foph);
+        assert_eq!(merged["swissmedic_data"], swissmedic_diff());
+        let html = std::fs::read_to_string(output_path.with_extension("html")).unwrap();
+        assert!(html.find("BEPANTHEN").unwrap() < html.find("ZYRTEC").unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use serde_json::{json, Map, Value};
+use tracing::{info, instrument};
+
+use crate::error::Result;
+use crate::foph_diff::{
+    date_str_to_tuple, extract_date_from_bundles, extract_date_from_filename, process_bundles, read_foph_bundles,
+};
+use crate::swissmedic_diff::{extract_swissmedic_date, load_swissmedic_csv, LoadOptions};
+use crate::{
+    category_counts, create_output_dir, get_file_mod_date, print_summary, write_json, OutputOptions, OUTPUT_LOG_TARGET,
+};
+
+// ─── Orphans ─────────────────────────────────────────────────────────────────
+
+/// `{"gtin", "name"}` of every GTIN in `only`, with the name from `name_of`.
+fn orphan_records<'a>(only: impl Iterator<Item = &'a String>, name_of: impl Fn(&str) -> &'a str) -> Value {
+    only.map(|gtin| json!({ "gtin": gtin, "name": name_of(gtin) })).collect()
+}
+
+/// Compare the GTINs of a Swissmedic export with those of a FOPH SL export
+/// of about the same day: `swissmedic_only` lists approved packs without an
+/// SL entry, `foph_only` SL packages Swissmedic doesn't list (any more).
+/// Written to `diff/orphans_<swissmedic date>-<foph date>.json`.
+#[instrument(skip_all, fields(swissmedic = swissmedic_file, foph = foph_file))]
+pub fn run_orphans(swissmedic_file: &str, foph_file: &str, opts: &OutputOptions, out_dir: &Path) -> Result<()> {
+    let (swissmedic, foph) = rayon::join(
+        || load_swissmedic_csv(swissmedic_file, &LoadOptions::default()),
+        || -> Result<_> {
+            let bundles = read_foph_bundles(foph_file)?.bundles;
+            let fallback = date_str_to_tuple(&extract_date_from_filename(foph_file));
+            Ok(process_bundles(&bundles, &extract_date_from_bundles(&bundles, fallback)))
+        },
+    );
+    let swissmedic = swissmedic?.entries;
+    let mut foph = foph?;
+    // Packages whose SL authorization lapsed stay in the map (as sl_entry_delete) but are not on the SL
+    foph.retain(|_, package| package.has_sl_entry);
+    info!("Found {} Swissmedic packs, {} FOPH packages.", swissmedic.len(), foph.len());
+
+    let swissmedic_gtins: BTreeSet<&String> = swissmedic.keys().collect();
+    let foph_gtins: BTreeSet<&String> = foph.keys().collect();
+    let mut orphans = Map::new();
+    orphans.insert("swissmedic_only".into(), orphan_records(
+        swissmedic_gtins.difference(&foph_gtins).copied(),
+        |gtin| swissmedic[gtin].name.as_str(),
+    ));
+    orphans.insert("foph_only".into(), orphan_records(
+        foph_gtins.difference(&swissmedic_gtins).copied(),
+        |gtin| foph[gtin].name.as_str(),
+    ));
+
+    let swissmedic_date = extract_swissmedic_date(swissmedic_file).unwrap_or_else(|| get_file_mod_date(swissmedic_file));
+    let foph_date = extract_date_from_filename(foph_file);
+    let dir = out_dir.join("diff");
+    let path = dir.join(format!("orphans_{}-{}.json", swissmedic_date, foph_date));
+    create_output_dir(&dir, opts.dry_run)?;
+    let counts = category_counts(&orphans);
+    write_json(&path, &Value::Object(orphans), opts.compact_json, opts.dry_run)?;
+    if !opts.dry_run {
+        info!(target: OUTPUT_LOG_TARGET, "Orphans written to {}", path.display());
+    }
+    print_summary(counts, Some(&path), std::slice::from_ref(&path));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A bundle with one package and its SL authorization, valid from `start` to `end`.
+    fn sl_bundle(gtin: &str, name: &str, start: &str, end: &str) -> Value {
+        json!({
+            "resourceType": "Bundle",
+            "timestamp": "2026-02-01T00:00:00Z",
+            "entry": [
+                {"resource": {
+                    "resourceType": "PackagedProductDefinition",
+                    "id": "p1",
+                    "description": name,
+                    "packaging": {"identifier": [{"system": "urn:oid:2.51.1.1", "value": gtin}]},
+                }},
+                {"resource": {
+                    "resourceType": "RegulatedAuthorization",
+                    "id": "a1",
+                    "type": {"coding": [{"code": "756000002003"}]},
+                    "subject": [{"reference": "PackagedProductDefinition/p1"}],
+                    "validityPeriod": {"start": start, "end": end},
+                }},
+            ],
+        })
+    }
+
+    #[test]
+    fn lapsed_sl_package_is_not_on_the_foph_side() {
+        let dir = std::env::temp_dir().join(format!("pharma2merge-orphans-lapsed-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let swissmedic_file = dir.join("swissmedic_01.02.2026.csv");
+        let row = |reg_nr: &str, name: &str| format!("{},,{},,,,,,,,001,,,,,,,,,", reg_nr, name);
+        fs::write(&swissmedic_file, [row("12345", "Aspirin"), row("54321", "Lapsed")].join("\n")).unwrap();
+        let foph_file = dir.join("sl_foph_01.02.2026.ndjson");
+        let bundles = [
+            sl_bundle("7680123450017", "ASPIRIN", "2020-01-01", "2030-12-31"),
+            sl_bundle("7680543210017", "LAPSED", "2020-01-01", "2026-01-15"),
+            sl_bundle("7680654320018", "SL ONLY", "2020-01-01", "2030-12-31"),
+        ];
+        fs::write(&foph_file, bundles.iter().map(Value::to_string).collect::<Vec<_>>().join("\n")).unwrap();
+
+        run_orphans(&swissmedic_file.display().to_string(), &foph_file.display().to_string(), &OutputOptions::default(), &dir)
+            .unwrap();
+        let written = fs::read_dir(dir.join("diff")).unwrap().next().unwrap().unwrap().path();
+        let orphans: Value = serde_json::from_slice(&fs::read(written).unwrap()).unwrap();
+        assert_eq!(orphans["swissmedic_only"], json!([{"gtin": "7680543210017", "name": "Lapsed"}]));
+        assert_eq!(orphans["foph_only"], json!([{"gtin": "7680654320018", "name": "SL ONLY"}]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
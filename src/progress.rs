@@ -0,0 +1,57 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+// ─── Progress bars ───────────────────────────────────────────────────────────
+
+/// Cleared by `--no-progress`. Bars are drawn to stderr and stay hidden
+/// anyway when it is not a terminal.
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Download bars, stacked so that downloads running side by side don't
+/// draw over each other.
+fn download_bars() -> &'static MultiProgress {
+    static BARS: OnceLock<MultiProgress> = OnceLock::new();
+    BARS.get_or_init(MultiProgress::new)
+}
+
+/// Bar for a download of `total` bytes (from `Content-Length`), or a byte
+/// counter if the size is unknown, labelled with `label`.
+pub fn bytes_bar(total: Option<u64>, label: &str) -> ProgressBar {
+    if !enabled() {
+        return ProgressBar::hidden();
+    }
+    let bar = match total {
+        Some(total) => ProgressBar::new(total).with_style(
+            ProgressStyle::with_template("  {prefix} [{bar:40}] {bytes}/{total_bytes} {bytes_per_sec}, ETA {eta}")
+                .expect("valid template")
+                .progress_chars("=> "),
+        ),
+        None => ProgressBar::new_spinner().with_style(
+            ProgressStyle::with_template("  {prefix} {spinner} {bytes} {bytes_per_sec}").expect("valid template"),
+        ),
+    };
+    download_bars().add(bar.with_prefix(label.to_string()))
+}
+
+/// Spinner showing `message` and the number of lines read so far.
+pub fn spinner(message: String) -> ProgressBar {
+    if !enabled() {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::new_spinner()
+        .with_style(ProgressStyle::with_template("  {spinner} {msg} ({human_pos} lines)").expect("valid template"))
+        .with_message(message);
+    bar.enable_steady_tick(Duration::from_millis(100));
+    bar
+}
@@ -0,0 +1,372 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::error::{PharmaError, Result};
+use crate::foph_diff::group_by_gtin;
+use crate::html::{render_html_diff, HtmlOptions};
+use crate::xml::render_xml;
+use crate::{category_counts, csv_escape, flag_legend, write_json, write_output, write_split_output, OutputOptions};
+
+// ─── Diff report ─────────────────────────────────────────────────────────────
+
+/// Which export a `DiffReport` compares.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffSource {
+    Foph,
+    Swissmedic,
+}
+
+/// The result of one FOPH or Swissmedic diff, with a serializer per output
+/// format so JSON, CSV and HTML are built from the same records.
+#[derive(Clone, Debug)]
+pub struct DiffReport {
+    pub source: DiffSource,
+    /// `<old date>-<new date>`, as used in the output filenames.
+    pub dates: String,
+    /// Change records per category, e.g. `retail_up` or `Owner`.
+    pub categories: Map<String, Value>,
+    /// Further top-level views such as `by_manufacturer`, written after the
+    /// categories (or to `diff_<view>_<dates>.json` with `--split`).
+    pub views: Map<String, Value>,
+    /// Record count per category before `limit` cut them short; empty
+    /// unless limited.
+    pub totals: Map<String, Value>,
+}
+
+impl DiffReport {
+    pub fn new(source: DiffSource, dates: String, categories: Map<String, Value>) -> Self {
+        DiffReport { source, dates, categories, views: Map::new(), totals: Map::new() }
+    }
+
+    /// Keep the first `limit` records of every category. The JSON then lists
+    /// the cut categories under `_truncated` and, via `insert_totals`, the
+    /// full counts under `_stats.total_records`.
+    pub fn limit(&mut self, limit: usize) {
+        let Value::Object(totals) = category_counts(&self.categories) else { unreachable!("counts are an object") };
+        self.totals = totals;
+        for items in self.categories.values_mut().filter_map(Value::as_array_mut) {
+            items.truncate(limit);
+        }
+    }
+
+    /// `{"<category>": true}` for every category `limit` cut short.
+    pub fn truncated(&self) -> Map<String, Value> {
+        self.categories.iter()
+            .filter(|(key, items)| {
+                let total = self.totals.get(*key).and_then(Value::as_u64).unwrap_or(0) as usize;
+                total > items.as_array().map_or(0, |a| a.len())
+            })
+            .map(|(key, _)| (key.clone(), Value::Bool(true)))
+            .collect()
+    }
+
+    /// Add the counts before `limit` to `output` as `_stats.total_records`.
+    pub fn insert_totals(&self, output: &mut Map<String, Value>) {
+        if self.totals.is_empty() {
+            return;
+        }
+        let stats = output.entry("_stats").or_insert_with(|| Value::Object(Map::new()));
+        stats["total_records"] = Value::Object(self.totals.clone());
+    }
+
+    /// The diff JSON: the flag legend, every category and the views.
+    pub fn to_json(&self) -> Value {
+        self.json_with(self.categories.clone())
+    }
+
+    /// Like `to_json`, but with the categories keyed by GTIN (see `group_by_gtin`).
+    pub fn to_json_by_gtin(&self) -> Value {
+        self.json_with(group_by_gtin(&self.categories))
+    }
+
+    fn json_with(&self, body: Map<String, Value>) -> Value {
+        let mut output = Map::new();
+        // Include numeric flag legend for downstream consumers (matching Ruby NUMERIC_FLAGS)
+        output.insert("_flag_legend".into(), flag_legend());
+        output.extend(body);
+        output.extend(self.views.clone());
+        let truncated = self.truncated();
+        if !truncated.is_empty() {
+            output.insert("_truncated".into(), Value::Object(truncated));
+        }
+        output.insert("_diff_hash".into(), Value::String(self.diff_hash()));
+        if self.is_empty() {
+            output.insert("_no_changes".into(), Value::Bool(true));
+        }
+        Value::Object(output)
+    }
+
+    /// Record count per category, before `limit` if it cut any.
+    pub fn counts(&self) -> Value {
+        if self.totals.is_empty() { category_counts(&self.categories) } else { Value::Object(self.totals.clone()) }
+    }
+
+    /// The counts `print_summary` reports: the records written per category,
+    /// plus `_truncated` as in the JSON when `limit` cut any.
+    pub fn summary_counts(&self) -> Value {
+        let Value::Object(mut counts) = category_counts(&self.categories) else { unreachable!("counts are an object") };
+        let truncated = self.truncated();
+        if !truncated.is_empty() {
+            counts.insert("_truncated".into(), Value::Object(truncated));
+        }
+        Value::Object(counts)
+    }
+
+    /// Whether no category holds a record.
+    pub fn is_empty(&self) -> bool {
+        self.categories.values().all(|items| items.as_array().is_none_or(Vec::is_empty))
+    }
+
+    /// `diff_hash` of the categories and array views as in `to_json`, the
+    /// same for every output layout.
+    pub fn diff_hash(&self) -> String {
+        let mut arrays = self.categories.clone();
+        arrays.extend(self.views.clone());
+        diff_hash(&arrays)
+    }
+
+    /// Write every category and view to its own JSON file in `dir`.
+    pub fn write_split(&self, dir: &Path, opts: &OutputOptions) -> Result<Vec<PathBuf>> {
+        let mut written = write_split_output(dir, &self.dates, &self.categories, opts)?;
+        for (key, value) in &self.views {
+            let path = dir.join(format!("diff_{}_{}.json", key, self.dates));
+            write_json(&path, value, opts.compact_json, opts.dry_run)?;
+            written.push(path);
+        }
+        let truncated = self.truncated();
+        if !truncated.is_empty() {
+            let path = dir.join(format!("diff_truncated_{}.json", self.dates));
+            write_json(&path, &Value::Object(truncated), opts.compact_json, opts.dry_run)?;
+            written.push(path);
+        }
+        // Empty categories have no file of their own, so say why there is none
+        if self.is_empty() {
+            let path = dir.join(format!("diff_no_changes_{}.json", self.dates));
+            write_output(&path, b"true", opts.dry_run)?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+
+    /// Write one CSV per non-empty category into `dir`, named
+    /// `diff_<dates>_<category>.csv`.
+    pub fn to_csv_dir(&self, dir: &Path, dry_run: bool) -> Result<Vec<PathBuf>> {
+        let mut written = Vec::new();
+        for (key, items) in &self.categories {
+            let items = items.as_array().map(|a| a.as_slice()).unwrap_or(&[]);
+            // A header-only file is just noise in a spreadsheet
+            if items.is_empty() {
+                continue;
+            }
+            let columns = self.csv_columns(key);
+            let path = dir.join(format!("diff_{}_{}.csv", self.dates, key));
+            let mut buf = Vec::new();
+            writeln!(buf, "{}", columns.join(","))?;
+            for item in items {
+                let row: Vec<String> = columns.iter().map(|c| csv_escape(&csv_cell(&item[*c]))).collect();
+                writeln!(buf, "{}", row.join(","))?;
+            }
+            write_output(&path, &buf, dry_run)?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+
+    fn csv_columns(&self, key: &str) -> &'static [&'static str] {
+        match self.source {
+            // Categories of further price types share the price columns
+            DiffSource::Foph => FOPH_CSV_COLUMNS.iter()
+                .find(|(k, _)| *k == key)
+                .map_or(PRICE_CSV_COLUMNS, |(_, columns)| columns),
+            DiffSource::Swissmedic => match key {
+                "added" | "deleted" => &["gtin", "name", "strength", "flags"],
+                _ => &["gtin", "product_name", "strength", "flags", "old", "new"],
+            },
+        }
+    }
+
+    /// The diff JSON as an XML document (see `render_xml`).
+    pub fn to_xml(&self) -> String {
+        let source = match self.source {
+            DiffSource::Foph => "foph",
+            DiffSource::Swissmedic => "swissmedic",
+        };
+        let Value::Object(body) = self.to_json() else { unreachable!("DiffReport serializes to an object") };
+        render_xml("diff", &[("source", source), ("dates", &self.dates)], &body)
+    }
+
+    /// Write `to_xml` to `diff_<dates>.xml` in `dir`.
+    pub fn write_xml(&self, dir: &Path, dry_run: bool) -> Result<PathBuf> {
+        let path = dir.join(format!("diff_{}.xml", self.dates));
+        write_output(&path, self.to_xml().as_bytes(), dry_run)?;
+        Ok(path)
+    }
+
+    /// Render this diff alone as a standalone HTML page.
+    pub fn to_html(&self, opts: &HtmlOptions) -> String {
+        let data_key = match self.source {
+            DiffSource::Foph => "price_data",
+            DiffSource::Swissmedic => "swissmedic_data",
+        };
+        let mut page = json!({ "metadata": { "generated_on": self.dates } });
+        page[data_key] = self.to_json();
+        render_html_diff(&page, opts)
+    }
+}
+
+// ─── Diff hash ───────────────────────────────────────────────────────────────
+
+/// The category arrays of a diff JSON: every entry that is an array and not
+/// `_`-prefixed metadata such as `_stats`.
+pub(crate) fn diff_arrays(diff: &Map<String, Value>) -> Map<String, Value> {
+    diff.iter()
+        .filter(|(key, value)| !key.starts_with('_') && value.is_array())
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Hex SHA-256 of the compact JSON of `value`. Object keys serialize sorted,
+/// so equal content always hashes alike.
+pub(crate) fn sha256_hex(value: &Value) -> String {
+    Sha256::digest(value.to_string().as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Checksum of the change records of a diff, leaving out metadata like
+/// `_stats` and `generated_on`: two runs over the same inputs hash alike.
+pub fn diff_hash(diff: &Map<String, Value>) -> String {
+    sha256_hex(&Value::Object(diff_arrays(diff)))
+}
+
+/// Fail with `HashMismatch` unless `actual` equals the `--assert-hash` value.
+pub fn assert_diff_hash(expected: Option<&str>, actual: &str) -> Result<()> {
+    match expected {
+        Some(expected) if !expected.trim().eq_ignore_ascii_case(actual) => {
+            Err(PharmaError::HashMismatch { expected: expected.trim().to_string(), actual: actual.to_string() })
+        }
+        _ => Ok(()),
+    }
+}
+
+// ─── Record order ────────────────────────────────────────────────────────────
+
+/// Field the records of each category are sorted by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    Gtin,
+    /// `name`, or `product_name` for Swissmedic field changes.
+    Name,
+    /// Absolute `difference` of a price change.
+    PriceDiff,
+    /// Absolute `pct_change` of a price change.
+    PctChange,
+}
+
+/// Order of the records within each category, as chosen with `--sort-by`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordOrder {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl RecordOrder {
+    /// Sort `records`, keeping the current (GTIN) order among equal keys.
+    /// Records without a price difference or percentage come last either way.
+    pub fn sort(&self, records: &mut [Value]) {
+        fn name(item: &Value) -> Option<&str> {
+            item["name"].as_str().or_else(|| item["product_name"].as_str())
+        }
+        let number = |item: &Value, field: &str| item[field].as_f64().map(f64::abs);
+        records.sort_by(|a, b| {
+            let ordering = match self.key {
+                SortKey::Gtin => a["gtin"].as_str().cmp(&b["gtin"].as_str()),
+                SortKey::Name => name(a).cmp(&name(b)),
+                SortKey::PriceDiff | SortKey::PctChange => {
+                    let field = if self.key == SortKey::PriceDiff { "difference" } else { "pct_change" };
+                    match (number(a, field), number(b, field)) {
+                        (Some(x), Some(y)) => x.total_cmp(&y),
+                        // Not reversed below
+                        (x, y) => return x.is_none().cmp(&y.is_none()),
+                    }
+                }
+            };
+            if self.descending { ordering.reverse() } else { ordering }
+        });
+    }
+
+    /// Sort every category array of a diff; `_`-prefixed entries such as
+    /// `_stats` are left alone.
+    pub fn sort_categories(&self, categories: &mut Map<String, Value>) {
+        for (key, items) in categories.iter_mut() {
+            if let (false, Value::Array(items)) = (key.starts_with('_'), items) {
+                self.sort(items);
+            }
+        }
+    }
+}
+
+/// CSV columns of every price change category.
+const PRICE_CSV_COLUMNS: &[&str] = &[
+    "gtin", "name", "flags", "type", "old_price", "new_price", "difference", "pct_change", "change_date",
+];
+
+/// CSV columns per FOPH category.
+const FOPH_CSV_COLUMNS: &[(&str, &[&str])] = &[
+    ("new", &["gtin", "name", "flags", "retail_price", "exfactory_price"]),
+    ("del", &["gtin", "name", "flags", "retail_price", "exfactory_price"]),
+    ("sl_entry", &["gtin", "name", "flags"]),
+    ("sl_entry_delete", &["gtin", "name", "flags"]),
+    ("limitation_changed", &[
+        "gtin", "name", "flags", "old_has_limitation", "new_has_limitation", "old_points", "new_points", "old_text", "new_text",
+    ]),
+    ("name_base", &["gtin", "name", "flags", "old_name", "new_name"]),
+    ("ingredient_changes", &["gtin", "name", "flags", "old_ingredients", "new_ingredients"]),
+    ("retail_up", PRICE_CSV_COLUMNS),
+    ("retail_down", PRICE_CSV_COLUMNS),
+    ("exfactory_up", PRICE_CSV_COLUMNS),
+    ("exfactory_down", PRICE_CSV_COLUMNS),
+];
+
+fn csv_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(csv_cell).collect::<Vec<_>>().join(";"),
+        // An ingredient: `name strength`
+        Value::Object(fields) => fields.values().map(csv_cell).filter(|s| !s.is_empty()).collect::<Vec<_>>().join(" "),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_an_empty_diff_is_marked_no_changes() {
+        let categories = |records: Value| -> Map<String, Value> {
+            [("new".to_string(), records), ("del".to_string(), json!([]))].into_iter().collect()
+        };
+        let empty = DiffReport::new(DiffSource::Foph, "01.01.2026-01.02.2026".into(), categories(json!([])));
+        assert_eq!(empty.to_json()["_no_changes"], true);
+        let changed = DiffReport::new(DiffSource::Foph, "01.01.2026-01.02.2026".into(),
+            categories(json!([{"gtin": "7680123450017", "flags": [1]}])));
+        assert!(changed.to_json().get("_no_changes").is_none());
+    }
+
+    #[test]
+    fn summary_counts_match_the_limited_json() {
+        let categories = [
+            ("new".to_string(), json!([{"gtin": "7680123450017"}, {"gtin": "7680543210017"}, {"gtin": "7680654320018"}])),
+            ("del".to_string(), json!([{"gtin": "7680222220016"}])),
+        ].into_iter().collect();
+        let mut report = DiffReport::new(DiffSource::Foph, "01.01.2026-01.02.2026".into(), categories);
+        assert_eq!(report.summary_counts(), json!({"new": 3, "del": 1}));
+
+        report.limit(2);
+        assert_eq!(report.summary_counts(), json!({"new": 2, "del": 1, "_truncated": {"new": true}}));
+        assert_eq!(report.counts(), json!({"new": 3, "del": 1}));
+    }
+}
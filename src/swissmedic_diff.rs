@@ -0,0 +1,1278 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::time::Instant;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tracing::{debug, info, instrument, warn};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::download::xlsx_to_csv_bytes;
+use crate::error::{PharmaError, Result};
+use crate::report::{DiffReport, DiffSource};
+use crate::{
+    create_output_dir, get_file_mod_date, print_summary, report_unknown_filter_gtins, retain_atc_prefix,
+    write_json, OutputOptions, TimingStats, OUTPUT_LOG_TARGET,
+};
+
+// ─── Categories ──────────────────────────────────────────────────────────────
+
+/// Output keys of the Swissmedic diff categories.
+const CATEGORIES: [&str; 12] = [
+    "added", "deleted", "Name", "Strength", "Owner", "Date", "Handelsform", "Swissmedic_Categorie", "Active_Agent",
+    "Composition", "Indikation", "ATC",
+];
+
+/// The category names `category_key` accepts, for error messages.
+pub const CATEGORY_NAMES: &str = "added, deleted, Name, Strength, Owner, Date, Handelsform, Swissmedic_Categorie, \
+                                  Active_Agent, Composition, Indikation, ATC";
+
+/// Map a category name, in any case, to the output JSON key.
+pub fn category_key(cat: &str) -> Option<&'static str> {
+    CATEGORIES.into_iter().find(|key| key.eq_ignore_ascii_case(cat))
+}
+
+// ─── Numeric flags (Swissmedic-side, matching Ruby NUMERIC_FLAGS) ───────────
+
+/// Flags 1-16 matching Ruby OuwerkerkPlugin::NUMERIC_FLAGS.
+/// The FOPH-side flags are defined in foph_diff::numeric_flags.
+/// Here we define the Swissmedic-relevant subset.
+pub mod swissmedic_flags {
+    #![allow(dead_code)]
+    pub const NEW: u8              = 1;
+    pub const NAME_BASE: u8        = 3;
+    pub const ADDRESS: u8          = 4;  // owner/company
+    pub const IKSCAT: u8           = 5;
+    pub const COMPOSITION: u8      = 6;
+    pub const INDICATION: u8       = 7;
+    pub const SEQUENCE: u8         = 8;  // handelsform (trade form / sequence info)
+    pub const EXPIRY_DATE: u8      = 9;
+    pub const DELETE: u8           = 14;
+    pub const NOT_SPECIFIED: u8    = 16;
+    /// Not part of the Ruby flags; the ODDB plugin doesn't track ATC codes.
+    pub const ATC_CODE: u8         = 17;
+    /// Not part of the Ruby flags; the dosage strength number (`Dosisstärke-nummer`).
+    pub const STRENGTH: u8         = 19;
+}
+
+// ─── Swissmedic CSV diff ─────────────────────────────────────────────────────
+
+pub fn calculate_gtin_checksum(base12: &str) -> char {
+    if base12.len() != 12 { return 'X'; }
+    let sum: u32 = base12.chars().enumerate().map(|(i, c)| {
+        let d = c.to_digit(10).unwrap_or(0);
+        if i % 2 == 0 { d } else { d * 3 }
+    }).sum();
+    let checksum = (10 - (sum % 10)) % 10;
+    std::char::from_digit(checksum, 10).unwrap_or('X')
+}
+
+/// True if `gtin` is 13 digits and its last digit is the GS1 check digit of the first 12.
+pub fn gtin_check_digit_ok(gtin: &str) -> bool {
+    gtin.len() == 13
+        && gtin.chars().all(|c| c.is_ascii_digit())
+        && calculate_gtin_checksum(&gtin[..12]) == gtin.as_bytes()[12] as char
+}
+
+/// True if the raw registration number and pack code are plain digits short
+/// enough that `build_gtin` uses them without dropping anything.
+fn raw_ids_clean(reg_nr_raw: &str, pack_code_raw: &str) -> bool {
+    let reg_nr = reg_nr_raw.trim();
+    let pack_code = pack_code_raw.trim();
+    reg_nr.len() <= 5
+        && pack_code.len() <= 3
+        && reg_nr.chars().all(|c| c.is_ascii_digit())
+        && pack_code.chars().all(|c| c.is_ascii_digit())
+}
+
+/// The GTIN `7680` + registration number (5 digits) + pack code (3 digits,
+/// `000` if empty) + check digit, both numbers zero-padded and stripped of
+/// non-digits. A registration number or pack code with more digits has no
+/// GTIN, as cutting it would yield the GTIN of another pack.
+pub fn build_gtin(reg_nr_raw: &str, pack_code_raw: &str) -> Result<String> {
+    let reg_nr: String = reg_nr_raw.trim().chars().filter(|c| c.is_ascii_digit()).collect();
+    let pack_code: String = pack_code_raw.trim().chars().filter(|c| c.is_ascii_digit()).collect();
+
+    let invalid = |reason: String| PharmaError::InvalidGtin { gtin: format!("7680{}{}", reg_nr, pack_code), reason };
+    if reg_nr.is_empty() {
+        return Err(invalid(format!("no registration number in '{}'", reg_nr_raw.trim())));
+    }
+    if reg_nr.len() > 5 {
+        return Err(invalid(format!("registration number '{}' has more than 5 digits", reg_nr)));
+    }
+    if pack_code.len() > 3 {
+        return Err(invalid(format!("pack code '{}' has more than 3 digits", pack_code)));
+    }
+
+    let base12 = format!("7680{:0>5}{:0>3}", reg_nr, pack_code);
+    Ok(format!("{}{}", base12, calculate_gtin_checksum(&base12)))
+}
+
+pub fn extract_swissmedic_date(filename: &str) -> Option<String> {
+    let stem = std::path::Path::new(filename)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("");
+
+    // Try Packungen-YYYY.MM.DD pattern
+    if let Some(pos) = stem.find("Packungen-") {
+        let date_part = &stem[pos + 10..];
+        let segments: Vec<&str> = date_part.split('.').collect();
+        if segments.len() == 3
+            && segments[0].len() == 4
+            && segments.iter().all(|s| s.chars().all(|c| c.is_ascii_digit()))
+        {
+            return Some(date_part.to_string());
+        }
+    }
+
+    // Try dd.mm.yyyy pattern anywhere after '_'
+    for part in stem.split('_') {
+        let segments: Vec<&str> = part.split('.').collect();
+        if segments.len() == 3
+            && segments[0].len() <= 2
+            && segments[1].len() <= 2
+            && segments[2].len() == 4
+            && segments.iter().all(|s| s.chars().all(|c| c.is_ascii_digit()))
+        {
+            return Some(part.to_string());
+        }
+    }
+
+    None
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwissmedicEntry {
+    pub name: String,
+    pub owner: String,
+    pub date: String,
+    pub handelsform: String,
+    pub category: String,
+    pub active_agent: String,
+    pub composition: String,
+    pub indication: String,
+    /// Empty when the export has no ATC column.
+    #[serde(default)]
+    pub atc: String,
+    /// The dosage strength number (`Dosisstärke-nummer`) telling the
+    /// strengths of a product apart; empty if the export has none.
+    #[serde(default)]
+    pub strength: String,
+}
+
+pub type SwissmedicMap = BTreeMap<String, SwissmedicEntry>;
+
+// ─── Column layout ───────────────────────────────────────────────────────────
+
+/// Column indices of the Swissmedic packages export.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnLayout {
+    pub reg_nr: usize,
+    pub name: usize,
+    pub owner: usize,
+    pub first_approval: usize,
+    pub sequence_approval: usize,
+    pub date: usize,
+    pub pack_code: usize,
+    pub handelsform: usize,
+    pub category: usize,
+    pub active_agent: usize,
+    pub composition: usize,
+    pub indication: usize,
+    /// `None` if the header has no ATC column.
+    pub atc: Option<usize>,
+    pub strength: usize,
+}
+
+impl Default for ColumnLayout {
+    /// The layout of `zugelassene_packungen_ham.xlsx` as of 2026.
+    fn default() -> Self {
+        ColumnLayout {
+            reg_nr: 0,
+            name: 2,
+            owner: 3,
+            first_approval: 7,
+            sequence_approval: 8,
+            date: 9,
+            pack_code: 10,
+            handelsform: 12,
+            category: 13,
+            active_agent: 16,
+            composition: 17,
+            indication: 19,
+            atc: Some(6),
+            strength: 1,
+        }
+    }
+}
+
+/// Field names of `ColumnLayout`, as used in `--column-map` files.
+pub const COLUMN_FIELDS: [&str; 14] = [
+    "reg_nr", "name", "owner", "first_approval", "sequence_approval", "date", "pack_code",
+    "handelsform", "category", "active_agent", "composition", "indication", "atc", "strength",
+];
+
+/// Lowercase and drop whitespace, hyphens and dots so "Zulassungs-\nnummer"
+/// matches "zulassungsnummer".
+fn normalize_header(h: &str) -> String {
+    h.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '.')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+impl ColumnLayout {
+    /// Detect the layout from a header row by its German column names.
+    /// Returns `None` if the row doesn't look like a header; columns that
+    /// can't be matched keep their default index.
+    pub fn detect<S: AsRef<str>>(header: &[S]) -> Option<ColumnLayout> {
+        let (layout, missing) = ColumnLayout::from_header(header);
+        let is_header = !missing.iter().any(|(field, _)| *field == "reg_nr" || *field == "pack_code");
+        is_header.then_some(layout)
+    }
+
+    /// Look up every column of `header` by name. Fields that can't be found
+    /// are returned with their fallback index (`None` for the optional ATC
+    /// column, which is then left empty).
+    pub fn from_header<S: AsRef<str>>(header: &[S]) -> (ColumnLayout, Vec<(&'static str, Option<usize>)>) {
+        let names: Vec<String> = header.iter().map(|h| normalize_header(h.as_ref())).collect();
+        // Patterns are tried in order, so "zuldatum" wins over the "zulassungsdatum"
+        // that is also part of "Erstzulassungsdatum"
+        let position = |patterns: &[&str]| -> Option<usize> {
+            patterns.iter().find_map(|p| names.iter().position(|n| n.contains(p)))
+        };
+        let default = ColumnLayout::default();
+        let mut missing = Vec::new();
+        let mut find = |column: &'static str, patterns: &[&str], fallback: usize| -> usize {
+            position(patterns).unwrap_or_else(|| {
+                missing.push((column, Some(fallback)));
+                fallback
+            })
+        };
+
+        let mut layout = ColumnLayout {
+            reg_nr: find("reg_nr", &["zulassungsnummer"], default.reg_nr),
+            pack_code: find("pack_code", &["packungscode"], default.pack_code),
+            name: find("name", &["bezeichnung", "präparatename", "praeparatename"], default.name),
+            owner: find("owner", &["zulassungsinhaber"], default.owner),
+            first_approval: find("first_approval", &["erstzulassung"], default.first_approval),
+            sequence_approval: find("sequence_approval", &["zuldatum", "zulassungsdatum"], default.sequence_approval),
+            date: find("date", &["gültigkeitsdauer", "gueltigkeitsdauer", "ablaufdatum"], default.date),
+            handelsform: find("handelsform", &["handelsform", "einheit"], default.handelsform),
+            category: find("category", &["abgabekategoriepackung", "abgabekategorie"], default.category),
+            active_agent: find("active_agent", &["wirkstoff"], default.active_agent),
+            composition: find("composition", &["zusammensetzung"], default.composition),
+            indication: find("indication", &["anwendungsgebiet"], default.indication),
+            strength: find("strength", &["dosisstärkenummer", "dosisstaerkenummer"], default.strength),
+            atc: None,
+        };
+        layout.atc = position(&["atccode", "atc"]);
+        if layout.atc.is_none() {
+            missing.push(("atc", None));
+        }
+        (layout, missing)
+    }
+
+    /// Point `field` (one of `COLUMN_FIELDS`) at column `index`.
+    fn set(&mut self, field: &str, index: usize) {
+        match field {
+            "reg_nr" => self.reg_nr = index,
+            "name" => self.name = index,
+            "owner" => self.owner = index,
+            "first_approval" => self.first_approval = index,
+            "sequence_approval" => self.sequence_approval = index,
+            "date" => self.date = index,
+            "pack_code" => self.pack_code = index,
+            "handelsform" => self.handelsform = index,
+            "category" => self.category = index,
+            "active_agent" => self.active_agent = index,
+            "composition" => self.composition = index,
+            "indication" => self.indication = index,
+            "atc" => self.atc = Some(index),
+            "strength" => self.strength = index,
+            _ => unreachable!("unknown column field {}", field),
+        }
+    }
+
+    /// The entry fields of a CSV record.
+    fn entry(&self, record: &csv::StringRecord) -> SwissmedicEntry {
+        let get = |i: usize| -> String {
+            record.get(i).unwrap_or("").trim().to_string()
+        };
+        SwissmedicEntry {
+            name: get(self.name),
+            owner: get(self.owner),
+            date: get(self.date),
+            handelsform: get(self.handelsform),
+            category: get(self.category),
+            active_agent: get(self.active_agent),
+            composition: get(self.composition),
+            indication: get(self.indication),
+            atc: self.atc.map(get).unwrap_or_default(),
+            strength: get(self.strength),
+        }
+    }
+
+    /// Columns holding Excel serial dates in the xlsx export.
+    pub fn date_columns(&self) -> [usize; 3] {
+        [self.first_approval, self.sequence_approval, self.date]
+    }
+
+    /// The columns whose name in `header` says they hold a date (`…datum…`,
+    /// `…date…`, `Erstzulassung…`, `Gültigkeitsdauer…`), wherever they are,
+    /// so a column added before them doesn't turn their dates into numbers.
+    /// Other columns are left as numbers.
+    pub fn header_date_columns<S: AsRef<str>>(header: &[S]) -> Vec<usize> {
+        const DATE_WORDS: [&str; 5] = ["datum", "date", "erstzulassung", "gültigkeit", "gueltigkeit"];
+        header.iter().enumerate()
+            .filter(|(_, name)| {
+                let name = normalize_header(name.as_ref());
+                DATE_WORDS.iter().any(|word| name.contains(word))
+            })
+            .map(|(col, _)| col)
+            .collect()
+    }
+}
+
+/// A column given by 0-based index or by its header name.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum ColumnRef {
+    Index(usize),
+    Header(String),
+}
+
+/// Column overrides read from a `--column-map` JSON file such as
+/// `{"name": 2, "active_agent": "Wirkstoff(e)"}`. Only built by `load`,
+/// which rejects fields not in `COLUMN_FIELDS`.
+#[derive(Clone, Debug, Default)]
+pub struct ColumnMap {
+    path: String,
+    columns: BTreeMap<String, ColumnRef>,
+}
+
+impl ColumnMap {
+    pub fn load(path: &Path) -> Result<ColumnMap> {
+        let invalid = |message: String| PharmaError::Config { path: path.display().to_string(), message };
+        let content = fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+        let columns: BTreeMap<String, ColumnRef> = serde_json::from_str(&content).map_err(|e| invalid(e.to_string()))?;
+        if let Some(field) = columns.keys().find(|f| !COLUMN_FIELDS.contains(&f.as_str())) {
+            return Err(invalid(format!("unknown column '{}', expected one of {}", field, COLUMN_FIELDS.join(", "))));
+        }
+        Ok(ColumnMap { path: path.display().to_string(), columns })
+    }
+
+    /// True if any column is given by header name.
+    pub fn uses_header_names(&self) -> bool {
+        self.columns.values().any(|c| matches!(c, ColumnRef::Header(_)))
+    }
+
+    /// Override the columns of `layout`. Header names are resolved against
+    /// `header` and left alone while there is none.
+    fn apply(&self, layout: &mut ColumnLayout, header: Option<&[&str]>) -> Result<()> {
+        for (field, column) in &self.columns {
+            let index = match (column, header) {
+                (ColumnRef::Index(i), _) => *i,
+                (ColumnRef::Header(name), Some(header)) => {
+                    let wanted = normalize_header(name);
+                    header.iter().position(|h| normalize_header(h) == wanted).ok_or_else(|| PharmaError::Config {
+                        path: self.path.clone(),
+                        message: format!("no column '{}' in the header row", name),
+                    })?
+                }
+                (ColumnRef::Header(_), None) => continue,
+            };
+            layout.set(field, index);
+        }
+        Ok(())
+    }
+
+    fn contains(&self, field: &str) -> bool {
+        self.columns.contains_key(field)
+    }
+}
+
+/// A CSV row whose GTIN failed validation.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct InvalidGtinWarning {
+    /// 1-based CSV record number.
+    pub line: usize,
+    pub reg_nr: String,
+    pub pack_code: String,
+    pub gtin: String,
+    pub reason: String,
+}
+
+/// Synthetic key for the `n`-th row (1-based) of a registration that has no
+/// numeric pack code, e.g. `00123-packless-2`. Never a valid GTIN.
+pub fn packless_key(reg_nr_digits: &str, n: usize) -> String {
+    format!("{:0>5}-packless-{}", reg_nr_digits, n)
+}
+
+/// A CSV row that `load_swissmedic_csv` could not turn into a pack.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SkippedRow {
+    /// 1-based CSV record number.
+    pub line: usize,
+    pub reason: String,
+}
+
+/// The packs of a Swissmedic CSV and what went wrong while loading it.
+#[derive(Clone, Debug, Default)]
+pub struct SwissmedicCsv {
+    pub entries: SwissmedicMap,
+    /// Rows without a numeric pack code, keyed by `packless_key`.
+    pub packless: SwissmedicMap,
+    /// Rows whose GTIN is doubtful, kept under it, or impossible because the
+    /// registration number or pack code is too long, left out.
+    pub invalid_gtins: Vec<InvalidGtinWarning>,
+    /// Rows without a registration number or too short, including title rows
+    /// before the header.
+    pub skipped: Vec<SkippedRow>,
+    /// Record number of the header row, if one was found.
+    pub header_line: Option<usize>,
+}
+
+/// Settings for `load_swissmedic_csv`.
+#[derive(Clone, Debug, Default)]
+pub struct LoadOptions {
+    /// Fail on the first row whose GTIN fails validation instead of warning.
+    pub strict_gtin: bool,
+    /// Treat the first row as the header even if it isn't recognised as one.
+    pub has_header: bool,
+    /// Overrides of the detected or default column layout.
+    pub column_map: Option<ColumnMap>,
+    /// Field delimiter of a CSV; detected from its first line if not set.
+    pub delimiter: Option<u8>,
+}
+
+/// True if `filename` is an Excel workbook rather than a CSV export.
+pub fn is_xlsx(filename: &str) -> bool {
+    Path::new(filename).extension().is_some_and(|e| e.eq_ignore_ascii_case("xlsx"))
+}
+
+/// Open a Swissmedic export as CSV, converting an xlsx in memory and a CSV
+/// to clean UTF-8 (see `csv_to_utf8`), with the delimiter to read it with.
+fn open_swissmedic_source(filename: &str, delimiter: Option<u8>) -> Result<(Box<dyn Read>, u8)> {
+    if is_xlsx(filename) {
+        let csv = xlsx_to_csv_bytes(&fs::read(filename)?)?;
+        return Ok((Box::new(Cursor::new(csv)), b','));
+    }
+    let csv = csv_to_utf8(filename, fs::read(filename)?);
+    let delimiter = delimiter.unwrap_or_else(|| {
+        let detected = detect_delimiter(&csv);
+        if detected != b',' {
+            info!("{}: detected {:?} as the field delimiter", filename, char::from(detected));
+        }
+        detected
+    });
+    Ok((Box::new(Cursor::new(csv)), delimiter))
+}
+
+/// Delimiters a Swissmedic CSV has been seen with, depending on the locale
+/// of the machine that saved it.
+const CSV_DELIMITERS: [u8; 3] = [b',', b';', b'\t'];
+
+/// The most frequent of `CSV_DELIMITERS` in the first line of `csv`; comma
+/// on a tie or if there is none.
+fn detect_delimiter(csv: &[u8]) -> u8 {
+    let first_line = csv.split(|&b| b == b'\n').next().unwrap_or_default();
+    let count = |delimiter: u8| first_line.iter().filter(|&&b| b == delimiter).count();
+    // max_by_key keeps the last maximum, so go backwards to prefer the comma
+    CSV_DELIMITERS.into_iter().rev().max_by_key(|&d| count(d)).filter(|&d| count(d) > 0).unwrap_or(b',')
+}
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// CSV exports saved on Windows may start with a UTF-8 BOM, which would end
+/// up in the first cell, or be Latin-1. Drop the BOM, and read bytes that
+/// are no valid UTF-8 as Latin-1 so umlauts in names survive.
+fn csv_to_utf8(filename: &str, mut bytes: Vec<u8>) -> Vec<u8> {
+    if bytes.starts_with(UTF8_BOM) {
+        bytes.drain(..UTF8_BOM.len());
+    }
+    match String::from_utf8(bytes) {
+        Ok(text) => text.into_bytes(),
+        Err(err) => {
+            info!("{}: not valid UTF-8, reading it as Latin-1", filename);
+            err.into_bytes().iter().map(|&b| char::from(b)).collect::<String>().into_bytes()
+        }
+    }
+}
+
+/// Date of a Swissmedic export from its filename. The xlsx as published has
+/// no date in its name, so for xlsx files the modification date is used instead.
+fn swissmedic_file_date(filename: &str) -> Result<String> {
+    if let Some(date) = extract_swissmedic_date(filename) {
+        return Ok(date);
+    }
+    let mod_date = Some(get_file_mod_date(filename)).filter(|d| is_xlsx(filename) && d != "unknown");
+    if let Some(date) = &mod_date {
+        info!("{}: no date in filename, using its modification date {}", filename, date);
+    }
+    mod_date.ok_or_else(|| PharmaError::DateParseError(format!("no date in filename {}", filename)))
+}
+
+/// Load a Swissmedic CSV (or xlsx) keyed by GTIN. Rows with a suspicious GTIN
+/// are kept and reported in `invalid_gtins` (or rejected with `strict_gtin`).
+#[instrument(skip_all, fields(file = filename))]
+pub fn load_swissmedic_csv(filename: &str, opts: &LoadOptions) -> Result<SwissmedicCsv> {
+    let (source, delimiter) = open_swissmedic_source(filename, opts.delimiter)?;
+    read_swissmedic_records(filename, source, delimiter, opts)
+}
+
+/// `load_swissmedic_csv` for an xlsx already in memory, such as a download
+/// not written to disk; `label` stands for the file name in messages.
+#[instrument(skip_all, fields(file = label))]
+pub fn load_swissmedic_xlsx(label: &str, xlsx: &[u8], opts: &LoadOptions) -> Result<SwissmedicCsv> {
+    read_swissmedic_records(label, Cursor::new(xlsx_to_csv_bytes(xlsx)?), b',', opts)
+}
+
+/// The packs of the CSV records in `source`, with the layout detected from
+/// the header row or given in `opts`.
+fn read_swissmedic_records(filename: &str, source: impl Read, delimiter: u8, opts: &LoadOptions) -> Result<SwissmedicCsv> {
+    let mut data = BTreeMap::new();
+    let mut warnings = Vec::new();
+    let mut skipped_rows = Vec::new();
+    let mut packless = BTreeMap::new();
+    let mut packless_counts: BTreeMap<String, usize> = BTreeMap::new();
+    let mut header_line = None;
+    let mut loaded = 0usize;
+    let mut skipped = 0usize;
+    let mut total = 0usize;
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(source);
+
+    if let Some(map) = opts.column_map.as_ref().filter(|m| m.uses_header_names() && !opts.has_header) {
+        return Err(PharmaError::Config {
+            path: map.path.clone(),
+            message: "columns given by header name require --has-header".into(),
+        });
+    }
+
+    // Title rows may precede the header; until a header is seen use the default layout
+    let mut layout = ColumnLayout::default();
+    if let Some(map) = &opts.column_map {
+        map.apply(&mut layout, None)?;
+    }
+    let mut header_found = false;
+
+    for result in rdr.records() {
+        let record = result?;
+        total += 1;
+
+        if !header_found {
+            let cells: Vec<&str> = record.iter().collect();
+            if opts.has_header || ColumnLayout::detect(&cells).is_some() {
+                let (mut detected, missing) = ColumnLayout::from_header(&cells);
+                let mapped = |field: &str| opts.column_map.as_ref().is_some_and(|m| m.contains(field));
+                for (field, fallback) in missing.into_iter().filter(|(field, _)| !mapped(field)) {
+                    match fallback {
+                        Some(i) => warn!("{}: no '{}' column in header row {}, using column {}", filename, field, total, i + 1),
+                        None => warn!("{}: no '{}' column in header row {}, leaving it empty", filename, field, total),
+                    }
+                }
+                if let Some(map) = &opts.column_map {
+                    map.apply(&mut detected, Some(&cells))?;
+                }
+                if detected != layout {
+                    info!("{}: detected column layout from header row {}", filename, total);
+                }
+                layout = detected;
+                header_found = true;
+                header_line = Some(total);
+                skipped += 1;
+                continue;
+            }
+        }
+
+        if record.len() <= layout.reg_nr.max(layout.pack_code) {
+            skipped += 1;
+            skipped_rows.push(SkippedRow {
+                line: total,
+                reason: format!("only {} columns", record.len()),
+            });
+            continue;
+        }
+
+        let (reg_nr, pack_code) = (&record[layout.reg_nr], &record[layout.pack_code]);
+        let mut report_invalid = |gtin: String, reason: String| -> Result<()> {
+            if opts.strict_gtin {
+                return Err(PharmaError::InvalidGtin { gtin, reason: format!("{} (line {} of {})", reason, total, filename) });
+            }
+            warn!("{}:{}: invalid GTIN {} for registration number '{}' (pack code '{}'): {}",
+                filename, total, gtin, reg_nr.trim(), pack_code.trim(), reason);
+            warnings.push(InvalidGtinWarning {
+                line: total,
+                reg_nr: reg_nr.trim().to_string(),
+                pack_code: pack_code.trim().to_string(),
+                gtin,
+                reason,
+            });
+            Ok(())
+        };
+
+        // Without a pack code build_gtin would fall back to ...000 and all
+        // packless rows of a registration would overwrite each other
+        let pack_code_trimmed = pack_code.trim();
+        if pack_code_trimmed.is_empty() || !pack_code_trimmed.chars().all(|c| c.is_ascii_digit()) {
+            let reg_digits: String = reg_nr.trim().chars().filter(|c| c.is_ascii_digit()).collect();
+            if reg_digits.len() > 5 {
+                let reason = format!("registration number '{}' has more than 5 digits", reg_digits);
+                report_invalid(format!("7680{}", reg_digits), reason)?;
+                continue;
+            }
+            if !reg_digits.is_empty() {
+                let n = packless_counts.entry(reg_digits.clone()).or_insert(0usize);
+                *n += 1;
+                packless.insert(packless_key(&reg_digits, *n), layout.entry(&record));
+                continue;
+            }
+        }
+
+        // Rows without a registration number, such as title rows, are just
+        // skipped; one too long for a GTIN is reported and left out
+        let gtin = match build_gtin(reg_nr, pack_code) {
+            Ok(gtin) => gtin,
+            Err(PharmaError::InvalidGtin { gtin, reason }) => {
+                if reg_nr.chars().any(|c| c.is_ascii_digit()) {
+                    report_invalid(gtin, reason)?;
+                } else {
+                    skipped += 1;
+                    skipped_rows.push(SkippedRow { line: total, reason });
+                }
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        // A pack code or registration number that had to be cleaned up
+        // yields a well-formed but wrong GTIN. The export has no GTIN column,
+        // so there is no check digit of its own to verify.
+        if !raw_ids_clean(reg_nr, pack_code) {
+            report_invalid(gtin.clone(), "registration number or pack code contains non-digits".to_string())?;
+        }
+
+        data.insert(gtin, layout.entry(&record));
+        loaded += 1;
+    }
+
+    info!("{}: {} packs loaded ({} skipped, {} invalid_gtin, {} packless, {} total lines)",
+        filename, loaded, skipped, warnings.len(), packless.len(), total);
+    Ok(SwissmedicCsv { entries: data, packless, invalid_gtins: warnings, skipped: skipped_rows, header_line })
+}
+
+type FieldGetter = fn(&SwissmedicEntry) -> &str;
+
+/// Fields compared by `run_swissmedic_diff` with their flag, in output order.
+/// For fields marked `true` a change from or to empty is not reported, as an
+/// export without the column would otherwise change every pack.
+const COMPARED_FIELDS: [(u8, FieldGetter, bool); 10] = [
+    (swissmedic_flags::NAME_BASE, |e| e.name.as_str(), false),
+    (swissmedic_flags::STRENGTH, |e| e.strength.as_str(), true),
+    (swissmedic_flags::ADDRESS, |e| e.owner.as_str(), false),
+    (swissmedic_flags::EXPIRY_DATE, |e| e.date.as_str(), false),
+    (swissmedic_flags::SEQUENCE, |e| e.handelsform.as_str(), false),
+    (swissmedic_flags::IKSCAT, |e| e.category.as_str(), false),
+    (swissmedic_flags::COMPOSITION, |e| e.active_agent.as_str(), false),
+    (swissmedic_flags::COMPOSITION, |e| e.composition.as_str(), false),
+    (swissmedic_flags::INDICATION, |e| e.indication.as_str(), false),
+    (swissmedic_flags::ATC_CODE, |e| e.atc.as_str(), false),
+];
+
+/// True if `old` and `new` differ at most in leading and trailing whitespace.
+/// Whitespace between words counts, as "10 mg" → "10mg" is a real change.
+pub fn whitespace_only_change(old: &str, new: &str) -> bool {
+    old.trim() == new.trim()
+}
+
+/// Settings for `run_swissmedic_diff`.
+#[derive(Clone, Debug, Default)]
+pub struct SwissmedicDiffOptions {
+    pub load: LoadOptions,
+    /// Also compare rows without a pack code, under their `packless_key`.
+    pub include_packless: bool,
+    /// Compare fields byte for byte instead of after NFC normalization.
+    pub no_normalize_unicode: bool,
+    /// Report whitespace-only field changes with flag 16 instead of dropping them.
+    pub report_whitespace_changes: bool,
+    /// Only diff packages whose ATC code starts with this prefix.
+    pub filter_atc: Option<String>,
+    /// Only report changes of these GTINs (see `load_gtin_filter`).
+    pub filter_gtin: Option<BTreeSet<String>>,
+    pub output: OutputOptions,
+}
+
+#[instrument(skip_all)]
+pub fn run_swissmedic_diff(old_file: &str, new_file: &str, opts: &SwissmedicDiffOptions, out_dir: &Path) -> Result<()> {
+    let only = opts.output.only_keys(|name| category_key(name).map(str::to_string), CATEGORY_NAMES)?;
+    let old_date = swissmedic_file_date(old_file)?;
+    let new_date = swissmedic_file_date(new_file)?;
+
+    info!("Old date: {}, New date: {}", old_date, new_date);
+
+    let mut stats = TimingStats::default();
+    let parse_start = Instant::now();
+
+    // Load both files in parallel
+    let (old_result, new_result) = rayon::join(
+        || load_swissmedic_csv(old_file, &opts.load),
+        || load_swissmedic_csv(new_file, &opts.load),
+    );
+    let (old_csv, new_csv) = (old_result?, new_result?);
+    let with_packless = |csv: SwissmedicCsv| -> SwissmedicMap {
+        let mut entries = csv.entries;
+        if opts.include_packless {
+            entries.extend(csv.packless);
+        }
+        entries
+    };
+    let mut old_data = with_packless(old_csv);
+    let mut new_data = with_packless(new_csv);
+    opts.output.check_shrink(old_data.len(), new_data.len())?;
+    if let Some(prefix) = &opts.filter_atc {
+        retain_atc_prefix(&mut [&mut old_data, &mut new_data], prefix, |entry| Some(entry.atc.as_str()));
+    }
+    stats.parse_ms = TimingStats::since(parse_start);
+    let diff_start = Instant::now();
+
+    info!("=== Starting comparison between {} and {} ===", old_date, new_date);
+
+    // Parallel iteration over the BTreeMaps collects in GTIN order, keeping the output stable
+    let pack_records = |from: &SwissmedicMap, other: &SwissmedicMap, flag: u8| -> Vec<Value> {
+        from.par_iter()
+            .filter(|(gtin, _)| !other.contains_key(*gtin))
+            .map(|(gtin, entry)| {
+                let full_name = format!("{} {}", entry.name, entry.owner).trim().to_string();
+                let mut record = json!({"gtin": gtin, "name": full_name, "owner": entry.owner, "flags": [flag]});
+                if !entry.strength.is_empty() {
+                    record["strength"] = Value::from(entry.strength.as_str());
+                }
+                record
+            })
+            .collect()
+    };
+    let (mut added, mut deleted) = rayon::join(
+        || pack_records(&new_data, &old_data, swissmedic_flags::NEW),
+        || pack_records(&old_data, &new_data, swissmedic_flags::DELETE),
+    );
+
+    type ChangeVec = Vec<Value>;
+
+    let make_change = |gtin: &str, new_entry: &SwissmedicEntry, old_val: &str, new_val: &str, flags: Vec<u8>| -> Value {
+        let mut change = json!({
+            "gtin": gtin,
+            "product_name": new_entry.name,
+            "owner": new_entry.owner,
+            "old": old_val,
+            "new": new_val,
+            "flags": flags,
+        });
+        if !new_entry.strength.is_empty() {
+            change["strength"] = Value::from(new_entry.strength.as_str());
+        }
+        change
+    };
+
+    // Normalize line endings, and unless disabled composed vs. decomposed
+    // accents ("é" vs. "e\u{301}"), for comparison
+    let normalize = |s: &str| -> String {
+        let s = s.replace("\r\n", "\n").replace('\r', "\n");
+        if opts.no_normalize_unicode { s } else { s.nfc().collect() }
+    };
+
+    let fields_equal = |a: &str, b: &str| -> bool {
+        normalize(a) == normalize(b)
+    };
+
+    // Compare the packs present in both files in parallel, then split by field
+    let mut field_changes: Vec<(usize, Value)> = old_data.par_iter()
+        .filter_map(|(gtin, old_entry)| new_data.get(gtin).map(|new_entry| (gtin, old_entry, new_entry)))
+        .flat_map_iter(|(gtin, old_entry, new_entry)| {
+            COMPARED_FIELDS.iter().enumerate().filter_map(move |(i, (flag, field, skip_empty))| {
+                let (old_val, new_val) = (field(old_entry), field(new_entry));
+                if fields_equal(old_val, new_val) || (*skip_empty && (old_val.is_empty() || new_val.is_empty())) {
+                    return None;
+                }
+                if !whitespace_only_change(&normalize(old_val), &normalize(new_val)) {
+                    return Some((i, make_change(gtin, new_entry, old_val, new_val, vec![*flag])));
+                }
+                opts.report_whitespace_changes.then(|| {
+                    let mut change = make_change(gtin, new_entry, old_val, new_val, vec![swissmedic_flags::NOT_SPECIFIED]);
+                    change["whitespace_only"] = Value::Bool(true);
+                    (i, change)
+                })
+            })
+        })
+        .collect();
+
+    // Filter after diffing so that a package leaving the file still shows up as deleted
+    if let Some(gtins) = &opts.filter_gtin {
+        report_unknown_filter_gtins(gtins, |gtin| old_data.contains_key(gtin) || new_data.contains_key(gtin));
+        let keep = |record: &Value| record["gtin"].as_str().is_some_and(|gtin| gtins.contains(gtin));
+        added.retain(keep);
+        deleted.retain(keep);
+        field_changes.retain(|(_, change)| keep(change));
+    }
+
+    let mut changes: [ChangeVec; 10] = Default::default();
+    for (i, change) in field_changes {
+        changes[i].push(change);
+    }
+    let [changes_name, changes_strength, changes_owner, changes_date, changes_handelsform, changes_category, changes_agent, changes_composition, changes_indication, changes_atc] = changes;
+
+    let mut categories = Map::new();
+    categories.insert("deleted".into(), Value::Array(deleted.clone()));
+    categories.insert("added".into(), Value::Array(added.clone()));
+    categories.insert("Name".into(), Value::Array(changes_name.clone()));
+    categories.insert("Strength".into(), Value::Array(changes_strength.clone()));
+    categories.insert("Owner".into(), Value::Array(changes_owner.clone()));
+    categories.insert("Date".into(), Value::Array(changes_date.clone()));
+    categories.insert("Handelsform".into(), Value::Array(changes_handelsform.clone()));
+    categories.insert("Swissmedic_Categorie".into(), Value::Array(changes_category.clone()));
+    categories.insert("Active_Agent".into(), Value::Array(changes_agent.clone()));
+    categories.insert("Composition".into(), Value::Array(changes_composition.clone()));
+    categories.insert("Indikation".into(), Value::Array(changes_indication.clone()));
+    categories.insert("ATC".into(), Value::Array(changes_atc.clone()));
+    if let Some(only) = &only {
+        categories.retain(|key, _| only.contains(key));
+    }
+    if let Some(order) = opts.output.order {
+        order.sort_categories(&mut categories);
+    }
+
+    stats.diff_ms = TimingStats::since(diff_start);
+
+    let write_start = Instant::now();
+    let dates = format!("{}-{}", old_date, new_date);
+    let mut report = DiffReport::new(DiffSource::Swissmedic, dates.clone(), categories);
+    if let Some(limit) = opts.output.limit {
+        report.limit(limit);
+    }
+    let summary = opts.output.json_summary.then(|| report.summary_counts());
+    if report.is_empty() && !opts.output.report_no_changes {
+        info!("No changes detected");
+        if let Some(summary) = summary {
+            print_summary(summary, None, &[]);
+        }
+        return opts.output.finish(&report, &[old_file.to_string()], &[new_file.to_string()]);
+    }
+    let csv_dir = out_dir.join("csv");
+    create_output_dir(&csv_dir, opts.output.dry_run)?;
+    let mut written = Vec::new();
+    let output_filename = if opts.output.split {
+        written = report.write_split(&csv_dir, &opts.output)?;
+        csv_dir.join(format!("diff_<category>_{}.json", dates))
+    } else {
+        let Value::Object(mut output) = report.to_json() else { unreachable!("DiffReport serializes to an object") };
+        if !opts.output.no_stats {
+            report.insert_totals(&mut output);
+            stats.write_ms = TimingStats::since(write_start);
+            stats.insert_into(&mut output)?;
+        }
+        let output_filename = csv_dir.join(format!("diff_{}.json", dates));
+        write_json(&output_filename, &Value::Object(output), opts.output.compact_json, opts.output.dry_run)?;
+        written.push(output_filename.clone());
+        output_filename
+    };
+    let xml_file = opts.output.xml.then(|| report.write_xml(&csv_dir, opts.output.dry_run)).transpose()?;
+    written.extend(xml_file.clone());
+    stats.write_ms = TimingStats::since(write_start);
+
+    // Terminal summary
+    info!("Results summary:");
+    info!("  Deleted: {} packs", deleted.len());
+    info!("  Added:   {} packs", added.len());
+
+    debug!("Deleted packs:");
+    for e in &deleted {
+        debug!("  {}  {}", e["gtin"].as_str().unwrap_or(""), e["name"].as_str().unwrap_or(""));
+    }
+    debug!("Added packs:");
+    for e in &added {
+        debug!("  {}  {}", e["gtin"].as_str().unwrap_or(""), e["name"].as_str().unwrap_or(""));
+    }
+
+    let print_changes = |changes: &[Value], title: &str| {
+        debug!("{} ({} changes):", title, changes.len());
+        for c in changes {
+            debug!("  {} [{}]: \"{}\" → \"{}\"",
+                c["gtin"].as_str().unwrap_or(""),
+                c["product_name"].as_str().unwrap_or(""),
+                c["old"].as_str().unwrap_or(""),
+                c["new"].as_str().unwrap_or(""),
+            );
+        }
+    };
+
+    print_changes(&changes_name, "Name");
+    print_changes(&changes_strength, "Strength");
+    print_changes(&changes_owner, "Owner");
+    print_changes(&changes_date, "Date");
+    print_changes(&changes_handelsform, "Handelsform");
+    print_changes(&changes_category, "Swissmedic Categorie");
+    print_changes(&changes_agent, "Active Agent");
+    print_changes(&changes_composition, "Composition");
+    print_changes(&changes_indication, "Indikation");
+    print_changes(&changes_atc, "ATC");
+
+    info!("=== Summary of changes per category (with Ruby NUMERIC_FLAGS) ===");
+    info!("{:<5} {:<21}: Changes", "Flag", "Category");
+    info!("----------------------------------------------");
+    info!("{:<5} {:<21}: {} packs",  " 1",  "Added (new)",          added.len());
+    info!("{:<5} {:<21}: {} packs",  "14",  "Deleted",              deleted.len());
+    info!("{:<5} {:<21}: {} changes", " 3",  "Name",                changes_name.len());
+    info!("{:<5} {:<21}: {} changes", "19",  "Strength (strength)", changes_strength.len());
+    info!("{:<5} {:<21}: {} changes", " 4",  "Owner (address)",     changes_owner.len());
+    info!("{:<5} {:<21}: {} changes", " 9",  "Date (expiry_date)",  changes_date.len());
+    info!("{:<5} {:<21}: {} changes", " 8",  "Handelsform (seq)",   changes_handelsform.len());
+    info!("{:<5} {:<21}: {} changes", " 5",  "Swissmedic Categorie", changes_category.len());
+    info!("{:<5} {:<21}: {} changes", " 6",  "Active Agent (comp)", changes_agent.len());
+    info!("{:<5} {:<21}: {} changes", " 6",  "Composition",         changes_composition.len());
+    info!("{:<5} {:<21}: {} changes", " 7",  "Indikation",          changes_indication.len());
+    info!("{:<5} {:<21}: {} changes", "17",  "ATC (atc_code)",      changes_atc.len());
+
+    if !opts.output.dry_run {
+        info!(target: OUTPUT_LOG_TARGET, "JSON output written to: {}", output_filename.display());
+        if let Some(path) = &xml_file {
+            info!(target: OUTPUT_LOG_TARGET, "XML output written to: {}", path.display());
+        }
+    }
+    stats.log_summary();
+    if let Some(summary) = summary {
+        print_summary(summary, (!opts.output.split).then_some(output_filename.as_path()), &written);
+    }
+    opts.output.finish(&report, &[old_file.to_string()], &[new_file.to_string()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_digit_of_a_valid_gtin_is_accepted() {
+        // The EAN-13 examples of GS1 and of ISBN-13
+        assert!(gtin_check_digit_ok("5901234123457"));
+        assert!(gtin_check_digit_ok("9780306406157"));
+        assert!(gtin_check_digit_ok("7680475040157"));
+    }
+
+    #[test]
+    fn corrupted_check_digit_is_rejected() {
+        assert!(!gtin_check_digit_ok("7680475040158"));
+        // A swapped pair of digits changes the weighted sum as well
+        assert!(!gtin_check_digit_ok("7680745040157"));
+    }
+
+    #[test]
+    fn malformed_gtins_fail_the_check() {
+        assert!(!gtin_check_digit_ok("768047504015"));
+        assert!(!gtin_check_digit_ok("76804750401570"));
+        assert!(!gtin_check_digit_ok("76804750A0157"));
+    }
+
+    /// A data row in the default column layout.
+    fn row(reg_nr: &str, pack_code: &str, name: &str) -> String {
+        let mut cells = vec![""; 20];
+        cells[0] = reg_nr;
+        cells[2] = name;
+        cells[10] = pack_code;
+        cells.join(",")
+    }
+
+    fn load(rows: &[String], opts: &LoadOptions) -> Result<SwissmedicCsv> {
+        read_swissmedic_records("test.csv", Cursor::new(rows.join("\n")), b',', opts)
+    }
+
+    #[test]
+    fn too_long_ids_are_reported_as_invalid_gtins() {
+        let rows = [
+            row("12345", "1", "Aspirin"),
+            row("123456", "1", "Six digit registration"),
+            row("12345", "1234", "Four digit pack code"),
+            row("654321", "", "Packless, six digits"),
+            row("12 345", "2", "Cleaned up"),
+        ];
+        let csv = load(&rows, &LoadOptions::default()).unwrap();
+        let lines: Vec<usize> = csv.invalid_gtins.iter().map(|w| w.line).collect();
+        assert_eq!(lines, [2, 3, 4, 5]);
+        assert!(csv.invalid_gtins[0].reason.contains("more than 5 digits"));
+        assert!(csv.invalid_gtins[1].reason.contains("more than 3 digits"));
+        assert!(csv.skipped.is_empty());
+        assert!(csv.packless.is_empty());
+        // Only the rows with a GTIN, doubtful or not, are loaded
+        assert_eq!(csv.entries.keys().collect::<Vec<_>>(), ["7680123450017", "7680123450024"]);
+    }
+
+    #[test]
+    fn strict_gtin_rejects_a_too_long_registration_number() {
+        let rows = [row("123456", "1", "Six digit registration")];
+        let err = load(&rows, &LoadOptions { strict_gtin: true, ..Default::default() }).unwrap_err();
+        assert!(matches!(err, PharmaError::InvalidGtin { ref reason, .. } if reason.contains("line 1")));
+    }
+
+    #[test]
+    fn shuffled_header_columns_are_read_by_name() {
+        let rows = [
+            "Packungscode,Zulassungsinhaberin,Wirkstoff(e),Zulassungsnummer,Bezeichnung des Arzneimittels,\
+             Abgabekategorie Packung,Anwendungsgebiet"
+                .to_string(),
+            "001,Bayer AG,Acetylsalicylsäure,12345,Aspirin 500,D,Schmerzen".to_string(),
+        ];
+        let csv = load(&rows, &LoadOptions::default()).unwrap();
+        assert_eq!(csv.header_line, Some(1));
+        let entry = &csv.entries["7680123450017"];
+        assert_eq!(entry.name, "Aspirin 500");
+        assert_eq!(entry.owner, "Bayer AG");
+        assert_eq!(entry.active_agent, "Acetylsalicylsäure");
+        assert_eq!(entry.category, "D");
+        assert_eq!(entry.indication, "Schmerzen");
+        // Not in the header: an ATC column is left empty rather than read from its default position
+        assert_eq!(entry.atc, "");
+    }
+
+    /// A scratch directory for one test, emptied first.
+    fn scratch_dir(test: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("pharma2merge-swissmedic-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write `rows` as `swissmedic_<date>.csv` to `dir`.
+    fn write_export(dir: &Path, date: &str, rows: &[String]) -> String {
+        let path = dir.join(format!("swissmedic_{}.csv", date));
+        fs::write(&path, rows.join("\n")).unwrap();
+        path.display().to_string()
+    }
+
+    /// Diff `old` against `new` in `dir` and read back the diff JSON, `null` if none was written.
+    fn diff(dir: &Path, old: &str, new: &str, opts: &SwissmedicDiffOptions) -> Value {
+        let diff_path = dir.join("csv").join("diff_01.01.2026-01.02.2026.json");
+        let _ = fs::remove_file(&diff_path);
+        run_swissmedic_diff(old, new, opts, dir).unwrap();
+        fs::read(&diff_path).map_or(Value::Null, |bytes| serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[test]
+    fn parallel_diff_of_50k_rows_is_sorted_and_byte_stable() {
+        let dir = scratch_dir("50k");
+        let export = |date: &str, name_of: &dyn Fn(usize) -> Option<String>| -> String {
+            let rows: Vec<String> = (0..50_000)
+                .filter_map(|i| name_of(i).map(|name| row(&(10_000 + i / 1000).to_string(), &(i % 1000).to_string(), &name)))
+                .collect();
+            write_export(&dir, date, &rows)
+        };
+        let old = export("01.01.2026", &|i| (i % 100 != 0).then(|| format!("Product {}", i)));
+        let new = export("01.02.2026", &|i| {
+            let renamed = if i % 7 == 0 { " retard" } else { "" };
+            (i % 100 != 50).then(|| format!("Product {}{}", i, renamed))
+        });
+
+        let opts = SwissmedicDiffOptions { output: OutputOptions { no_stats: true, ..Default::default() }, ..Default::default() };
+        let diff_path = dir.join("csv").join("diff_01.01.2026-01.02.2026.json");
+        run_swissmedic_diff(&old, &new, &opts, &dir).unwrap();
+        let first = fs::read(&diff_path).unwrap();
+        run_swissmedic_diff(&old, &new, &opts, &dir).unwrap();
+        assert_eq!(fs::read(&diff_path).unwrap(), first);
+
+        let diff: Value = serde_json::from_slice(&first).unwrap();
+        let renamed = (0..50_000).filter(|i| i % 7 == 0 && i % 100 != 0 && i % 100 != 50).count();
+        for (category, count) in [("added", 500), ("deleted", 500), ("Name", renamed)] {
+            let records = diff[category].as_array().unwrap();
+            assert_eq!(records.len(), count, "{}", category);
+            let gtins: Vec<&str> = records.iter().map(|r| r["gtin"].as_str().unwrap()).collect();
+            assert!(gtins.windows(2).all(|pair| pair[0] < pair[1]), "{} not sorted by GTIN", category);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn packless_rows_of_one_registration_are_kept_apart() {
+        let rows = [
+            row("22222", "", "Tablets"),
+            row("22222", "", "Syrup"),
+            row("22222", "1", "Tablets 10"),
+        ];
+        let csv = load(&rows, &LoadOptions::default()).unwrap();
+        assert_eq!(csv.packless.len(), 2);
+        assert_eq!(csv.packless["22222-packless-1"].name, "Tablets");
+        assert_eq!(csv.packless["22222-packless-2"].name, "Syrup");
+        // Neither collapses onto the ...000 GTIN
+        assert_eq!(csv.entries.keys().collect::<Vec<_>>(), ["7680222220016"]);
+    }
+
+    #[test]
+    fn packless_rows_are_only_diffed_with_include_packless() {
+        let dir = scratch_dir("packless");
+        let old = write_export(&dir, "01.01.2026", &[row("22222", "", "Tablets"), row("12345", "1", "Aspirin")]);
+        let new = write_export(&dir, "01.02.2026", &[row("12345", "1", "Aspirin")]);
+        let output = OutputOptions { no_stats: true, ..Default::default() };
+
+        let opts = SwissmedicDiffOptions { output: output.clone(), ..Default::default() };
+        assert_eq!(diff(&dir, &old, &new, &opts), Value::Null);
+        let opts = SwissmedicDiffOptions { include_packless: true, output, ..Default::default() };
+        assert_eq!(diff(&dir, &old, &new, &opts)["deleted"][0]["gtin"], "22222-packless-1");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn combining_accent_equals_the_precomposed_one() {
+        let dir = scratch_dir("nfc");
+        let old = write_export(&dir, "01.01.2026", &[row("12345", "1", "Crème Dr. Müller")]);
+        let new = write_export(&dir, "01.02.2026", &[row("12345", "1", "Cre\u{300}me Dr. Mu\u{308}ller")]);
+        let output = OutputOptions { no_stats: true, ..Default::default() };
+
+        let opts = SwissmedicDiffOptions { output: output.clone(), ..Default::default() };
+        assert_eq!(diff(&dir, &old, &new, &opts), Value::Null);
+        let opts = SwissmedicDiffOptions { no_normalize_unicode: true, output, ..Default::default() };
+        assert_eq!(diff(&dir, &old, &new, &opts)["Name"].as_array().unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_leading_and_trailing_whitespace_is_ignored() {
+        assert!(whitespace_only_change("Pfizer AG", " Pfizer AG "));
+        assert!(whitespace_only_change("Pfizer AG", "Pfizer AG\t"));
+        assert!(!whitespace_only_change("Pfizer AG", "Pfizer  AG"));
+        assert!(!whitespace_only_change("Aspirin 10 mg", "Aspirin 10mg"));
+    }
+
+    #[test]
+    fn removed_inner_space_is_a_name_change() {
+        let dir = scratch_dir("inner-space");
+        let old = write_export(&dir, "01.01.2026", &[row("12345", "1", "Aspirin 10 mg")]);
+        let new = write_export(&dir, "01.02.2026", &[row("12345", "1", "Aspirin 10mg")]);
+        let opts = SwissmedicDiffOptions { output: OutputOptions { no_stats: true, ..Default::default() }, ..Default::default() };
+        let change = &diff(&dir, &old, &new, &opts)["Name"][0];
+        assert_eq!(change["flags"], json!([swissmedic_flags::NAME_BASE]));
+        assert_eq!(change["whitespace_only"], Value::Null);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bom_and_latin1_exports_read_like_utf8() {
+        let text = "Zulassungsnummer,Bezeichnung des Arzneimittels,Zulassungsinhaberin,Packungscode\n\
+                    12345,Aspirin 500,Zürich Pharma AG,001\n";
+        let dir = scratch_dir("encoding");
+        let read = |name: &str, bytes: Vec<u8>| {
+            let path = dir.join(name);
+            fs::write(&path, bytes).unwrap();
+            load_swissmedic_csv(&path.display().to_string(), &LoadOptions::default()).unwrap()
+        };
+        let with_bom = read("bom.csv", [UTF8_BOM, text.as_bytes()].concat());
+        let latin1 = read("latin1.csv", text.chars().map(|c| u8::try_from(c).unwrap()).collect());
+
+        for csv in [with_bom, latin1] {
+            // A BOM left in the first cell would hide the header and the name columns with it
+            assert_eq!(csv.header_line, Some(1));
+            assert_eq!(csv.entries["7680123450017"].owner, "Zürich Pharma AG");
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn comma_semicolon_and_tab_exports_read_alike() {
+        let rows = [
+            ["Zugelassene Packungen", "Stand: 01.02.2026", "", ""],
+            ["Zulassungsnummer", "Bezeichnung des Arzneimittels", "Zulassungsinhaberin", "Packungscode"],
+            ["12345", "Aspirin 500", "\"Pharma AG, Zürich\"", "001"],
+            ["54321", "Dafalgan 1 g", "Bern Pharma AG", "002"],
+        ];
+        let text = |delimiter: &str| rows.map(|cells| cells.join(delimiter)).join("\n");
+        let dir = scratch_dir("delimiters");
+        let read = |name: &str, text: String, opts: &LoadOptions| {
+            let path = dir.join(name);
+            fs::write(&path, text).unwrap();
+            load_swissmedic_csv(&path.display().to_string(), opts).unwrap().entries
+        };
+        let comma = read("comma.csv", text(","), &LoadOptions::default());
+        assert_eq!(comma["7680123450017"].owner, "Pharma AG, Zürich");
+        assert_eq!(comma.len(), 2);
+        assert_eq!(read("semicolon.csv", text(";"), &LoadOptions::default()), comma);
+        assert_eq!(read("tab.csv", text("\t"), &LoadOptions::default()), comma);
+
+        // A title row without separators detects as comma; --csv-delimiter overrides it
+        let untitled = text(";").replacen("Zugelassene Packungen;Stand: 01.02.2026;;", "Zugelassene Packungen", 1);
+        let opts = LoadOptions { delimiter: Some(b';'), ..Default::default() };
+        assert_eq!(read("override.csv", untitled, &opts), comma);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn strength_and_handelsform_changes_have_their_own_flags() {
+        let row = |strength: &str, handelsform: &str| {
+            let mut cells = vec![""; 20];
+            (cells[0], cells[1], cells[2], cells[10], cells[12]) = ("12345", strength, "Aspirin", "1", handelsform);
+            cells.join(",")
+        };
+        let dir = scratch_dir("strength");
+        let old = write_export(&dir, "01.01.2026", &[row("01", "Tabletten")]);
+        let new = write_export(&dir, "01.02.2026", &[row("02", "Filmtabletten")]);
+        let opts = SwissmedicDiffOptions { output: OutputOptions { no_stats: true, ..Default::default() }, ..Default::default() };
+        let diff = diff(&dir, &old, &new, &opts);
+        assert_eq!(diff["Strength"][0]["flags"], json!([swissmedic_flags::STRENGTH]));
+        assert_eq!(diff["Handelsform"][0]["flags"], json!([swissmedic_flags::SEQUENCE]));
+        assert_eq!(diff["_flag_legend"]["19"], "strength");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn build_gtin_pads_both_numbers() {
+        assert_eq!(build_gtin("12345", "001").unwrap(), "7680123450017");
+        assert_eq!(build_gtin("123", "1").unwrap(), "7680001230014");
+        assert_eq!(build_gtin("1", "1").unwrap(), "7680000010013");
+        assert_eq!(build_gtin(" 12345 ", " 1 ").unwrap(), "7680123450017");
+    }
+
+    #[test]
+    fn build_gtin_pads_an_empty_pack_code_to_000() {
+        assert_eq!(build_gtin("12345", "").unwrap(), "7680123450000");
+    }
+
+    #[test]
+    fn build_gtin_rejects_missing_and_too_long_numbers() {
+        let reason = |reg_nr: &str, pack_code: &str| match build_gtin(reg_nr, pack_code) {
+            Err(PharmaError::InvalidGtin { reason, .. }) => reason,
+            other => panic!("{:?}/{:?} built {:?}", reg_nr, pack_code, other),
+        };
+        assert!(reason("123456", "001").contains("more than 5 digits"));
+        assert!(reason("", "001").contains("no registration number"));
+        assert!(reason("  ", "001").contains("no registration number"));
+        assert!(reason("12345", "0012").contains("more than 3 digits"));
+    }
+
+    #[test]
+    fn build_gtin_appends_the_ean13_check_digit() {
+        // Check digits worked out by hand: 7·1+6·3+8·1+0·3+4·1+7·3+5·1+0·3+4·1+0·3+1·1+5·3 = 83 → 7
+        assert_eq!(build_gtin("47504", "015").unwrap(), "7680475040157");
+        assert_eq!(build_gtin("54321", "001").unwrap(), "7680543210017");
+        assert_eq!(build_gtin("65432", "001").unwrap(), "7680654320018");
+        for (reg_nr, pack_code) in [("47504", "015"), ("00001", "999"), ("99999", "0")] {
+            assert!(gtin_check_digit_ok(&build_gtin(reg_nr, pack_code).unwrap()));
+        }
+    }
+
+    #[test]
+    fn column_map_rejects_unknown_fields() {
+        let dir = scratch_dir("column-map");
+        let path = dir.join("columns.json");
+        fs::write(&path, r#"{"name": 2, "nmae": 3}"#).unwrap();
+        let err = ColumnMap::load(&path).unwrap_err();
+        assert!(matches!(err, PharmaError::Config { ref message, .. } if message.contains("unknown column 'nmae'")), "{}", err);
+
+        fs::write(&path, r#"{"name": 1}"#).unwrap();
+        let opts = LoadOptions { column_map: Some(ColumnMap::load(&path).unwrap()), ..Default::default() };
+        let csv = load(&["12345,Renamed,Aspirin,,,,,,,,001".to_string()], &opts).unwrap();
+        assert_eq!(csv.entries["7680123450017"].name, "Renamed");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+use serde_json::{Map, Value};
+
+// ─── XML output ──────────────────────────────────────────────────────────────
+
+/// Escape `s` for XML text and attribute values. Control characters XML 1.0
+/// does not allow are dropped.
+pub fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(ch),
+            c if (c as u32) < 0x20 => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Whether `name` can be used as an element name as is (ASCII letters,
+/// digits, `_`, `-` and `.`, not starting with a digit, `-` or `.`).
+fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        && !name.to_ascii_lowercase().starts_with("xml")
+}
+
+/// Render a diff or merge JSON object as an XML document with `root` as the
+/// document element carrying `attributes`.
+///
+/// Object keys become elements (`<entry key="…">` where a key is no valid
+/// element name, e.g. a GTIN); arrays repeat their elements as `<item>`,
+/// except change records, which become `<package gtin="…" name="…" flags="…">`
+/// with their other fields as children. `null` fields are left out.
+pub fn render_xml(root: &str, attributes: &[(&str, &str)], body: &Map<String, Value>) -> String {
+    let mut xml = String::with_capacity(64 * 1024);
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!("<{}", root));
+    for (name, value) in attributes {
+        xml.push_str(&format!(" {}=\"{}\"", name, xml_escape(value)));
+    }
+    xml.push_str(">\n");
+    for (key, value) in body {
+        write_element(&mut xml, key, value, 1);
+    }
+    xml.push_str(&format!("</{}>\n", root));
+    xml
+}
+
+fn write_element(xml: &mut String, key: &str, value: &Value, depth: usize) {
+    if value.is_null() {
+        return;
+    }
+    let indent = "  ".repeat(depth);
+    let (open, close) = if is_xml_name(key) {
+        (key.to_string(), key)
+    } else {
+        (format!("entry key=\"{}\"", xml_escape(key)), "entry")
+    };
+    match value {
+        Value::Array(items) => {
+            xml.push_str(&format!("{}<{} count=\"{}\">\n", indent, open, items.len()));
+            for item in items {
+                match item.as_object() {
+                    Some(record) if record.contains_key("gtin") => write_package(xml, record, depth + 1),
+                    _ => write_element(xml, "item", item, depth + 1),
+                }
+            }
+            xml.push_str(&format!("{}</{}>\n", indent, close));
+        }
+        Value::Object(fields) => {
+            xml.push_str(&format!("{}<{}>\n", indent, open));
+            for (key, value) in fields {
+                write_element(xml, key, value, depth + 1);
+            }
+            xml.push_str(&format!("{}</{}>\n", indent, close));
+        }
+        scalar => xml.push_str(&format!("{}<{}>{}</{}>\n", indent, open, xml_escape(&text(scalar)), close)),
+    }
+}
+
+/// A change record: `gtin`, the name (`name`, else Swissmedic's
+/// `product_name`) and the space-separated `flags` as attributes.
+fn write_package(xml: &mut String, record: &Map<String, Value>, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let name_key = if record.contains_key("name") { "name" } else { "product_name" };
+    xml.push_str(&format!("{}<package gtin=\"{}\"", indent, xml_escape(&text(&record["gtin"]))));
+    if let Some(name) = record.get(name_key).filter(|v| !v.is_null()) {
+        xml.push_str(&format!(" name=\"{}\"", xml_escape(&text(name))));
+    }
+    if let Some(flags) = record.get("flags").and_then(Value::as_array) {
+        let flags: Vec<String> = flags.iter().map(text).collect();
+        xml.push_str(&format!(" flags=\"{}\"", flags.join(" ")));
+    }
+    let children: Vec<_> = record.iter()
+        .filter(|(key, value)| !matches!(key.as_str(), "gtin" | "flags") && *key != name_key && !value.is_null())
+        .collect();
+    if children.is_empty() {
+        xml.push_str("/>\n");
+        return;
+    }
+    xml.push_str(">\n");
+    for (key, value) in children {
+        write_element(xml, key, value, depth + 1);
+    }
+    xml.push_str(&format!("{}</package>\n", indent));
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Decode the five predefined entities, failing on any other `&`.
+    fn unescape(s: &str) -> String {
+        let mut out = String::new();
+        let mut rest = s;
+        while let Some(pos) = rest.find('&') {
+            out.push_str(&rest[..pos]);
+            let end = rest[pos..].find(';').expect("unterminated entity") + pos;
+            out.push(match &rest[pos + 1..end] {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                other => panic!("unknown entity &{};", other),
+            });
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// Parse `xml` as far as these documents need: checks that every element
+    /// is closed in order and returns the start tags with their attributes.
+    fn parse(xml: &str) -> Vec<(String, Vec<(String, String)>)> {
+        let body = xml.strip_prefix("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n").expect("XML declaration");
+        let (mut tags, mut open) = (Vec::new(), Vec::new());
+        let mut rest = body;
+        while let Some(start) = rest.find('<') {
+            unescape(&rest[..start]);
+            let end = rest[start..].find('>').expect("unterminated tag") + start;
+            let tag = &rest[start + 1..end];
+            rest = &rest[end + 1..];
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(open.pop().as_deref(), Some(name), "mismatched </{}>", name);
+                continue;
+            }
+            let (tag, self_closing) = tag.strip_suffix('/').map_or((tag, false), |t| (t, true));
+            let (name, mut attrs) = tag.split_once(' ').unwrap_or((tag, ""));
+            let mut attributes = Vec::new();
+            while let Some((key, value)) = attrs.trim_start().split_once("=\"") {
+                let (value, after) = value.split_once('"').expect("unterminated attribute");
+                attributes.push((key.to_string(), unescape(value)));
+                attrs = after;
+            }
+            assert!(attrs.trim().is_empty(), "stray text in <{}>", tag);
+            if !self_closing {
+                open.push(name.to_string());
+            }
+            tags.push((name.to_string(), attributes));
+        }
+        assert!(open.is_empty() && rest.trim().is_empty(), "unclosed {:?}", open);
+        tags
+    }
+
+    #[test]
+    fn xml_parses_back_to_the_gtins_of_the_json() {
+        let diff = json!({
+            "new": [
+                {"gtin": "7680123450017", "name": "ASPIRIN <500> & \"Co\" l'été\u{1}", "flags": [1]},
+                {"gtin": "7680654320018", "name": "PONSTAN", "flags": [1], "manufacturer": null},
+            ],
+            "retail_up": [{"gtin": "7680543210017", "name": "DAFALGAN", "flags": [11, 13], "difference": 1.5}],
+            "Owner": [{"gtin": "7680222220016", "product_name": "Zyrtec", "old": "A&B", "new": "C", "flags": [4]}],
+            "del": [],
+            "by_manufacturer": {"Bayer AG": {"count": 1}},
+        });
+        let xml = render_xml("diff", &[("dates", "01.01.2026-01.02.2026")], diff.as_object().unwrap());
+        let tags = parse(&xml);
+
+        let mut gtins: Vec<&str> = tags.iter()
+            .filter(|(name, _)| name == "package")
+            .map(|(_, attrs)| attrs.iter().find(|(key, _)| key == "gtin").unwrap().1.as_str())
+            .collect();
+        let mut json_gtins: Vec<&str> = ["new", "Owner", "del", "retail_up"].iter()
+            .flat_map(|key| diff[key].as_array().unwrap())
+            .map(|record| record["gtin"].as_str().unwrap())
+            .collect();
+        gtins.sort_unstable();
+        json_gtins.sort_unstable();
+        assert_eq!(gtins, json_gtins);
+
+        for key in ["new", "del", "retail_up", "Owner"] {
+            // The first `<new>` with attributes; Owner changes have a `<new>` field too
+            let (_, attrs) = tags.iter().find(|(name, attrs)| name == key && !attrs.is_empty()).unwrap();
+            assert_eq!(attrs[0], ("count".to_string(), diff[key].as_array().unwrap().len().to_string()));
+        }
+        let name = |gtin: &str| tags.iter()
+            .find(|(_, attrs)| attrs.iter().any(|(k, v)| k == "gtin" && v == gtin))
+            .and_then(|(_, attrs)| attrs.iter().find(|(k, _)| k == "name"))
+            .map(|(_, v)| v.clone());
+        assert_eq!(name("7680123450017").as_deref(), Some("ASPIRIN <500> & \"Co\" l'été"));
+        assert_eq!(name("7680222220016").as_deref(), Some("Zyrtec"));
+        assert!(tags.iter().any(|(name, attrs)| name == "entry" && attrs[0].1 == "Bayer AG"));
+    }
+}